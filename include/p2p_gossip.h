@@ -0,0 +1,142 @@
+#ifndef P2P_GOSSIP_H
+#define P2P_GOSSIP_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ The kind of a `GossipEvent`.
+ */
+typedef enum GossipEventKind {
+  /*
+   A message is received.
+   */
+  GOSSIP_EVENT_KIND_MESSAGE,
+  /*
+   A connection to a peer is established.
+   */
+  GOSSIP_EVENT_KIND_PEER_JOINED,
+  /*
+   The last connection to a peer is closed.
+   */
+  GOSSIP_EVENT_KIND_PEER_LEFT,
+} GossipEventKind;
+
+/*
+ A node started with `gossip_start`.
+ */
+typedef struct GossipHandle GossipHandle;
+
+/*
+ The options of `gossip_start`, the other ones have the defaults of the binary.
+ */
+typedef struct GossipOptions {
+  /*
+   The port to run on.
+   */
+  uint16_t port;
+  /*
+   The addresses of the first nodes to connect to, comma-separated, or NULL.
+   */
+  const char *connect;
+  /*
+   The path to the certificate PEM file, or NULL for `cert.pem`.
+   */
+  const char *cert;
+  /*
+   The path to the secret key PEM file, or NULL for `key.pem`.
+   */
+  const char *key;
+  /*
+   Generate a self-signed certificate if `cert` or `key` does not exist.
+   */
+  bool ephemeral_cert;
+  /*
+   Do not verify the peers' certificates.
+   */
+  bool skip_server_verification;
+  /*
+   The log levels in the format of `--log-level`, or NULL to keep them,
+   shared by all the nodes of the process.
+   */
+  const char *log_level;
+} GossipOptions;
+
+/*
+ An event of a node, filled in by `gossip_poll_event`. The pointers stay valid
+ until the next call to `gossip_poll_event` or `gossip_stop` on the node.
+ */
+typedef struct GossipEvent {
+  GossipEventKind kind;
+  /*
+   The listen address of the peer, or of the one the message is received from.
+   */
+  const char *peer;
+  /*
+   The listen address of the node which sent the message, NULL for other events.
+   */
+  const char *origin;
+  /*
+   The body of the message, not NUL-terminated, NULL for other events.
+   */
+  const uint8_t *body;
+  uintptr_t body_len;
+} GossipEvent;
+
+/*
+ Starts a node as per `options` on a runtime of its own.
+ Returns NULL and logs the error on failure.
+
+ # Safety
+
+ `options` must point to valid options, whose strings are NUL-terminated or NULL.
+ */
+GossipHandle *gossip_start(const GossipOptions *options);
+
+/*
+ Sends a new message with the `len` bytes of `body` to all peers.
+ Returns 0, or -1 if `body` is not UTF-8.
+
+ # Safety
+
+ `handle` must be returned by `gossip_start` and not stopped,
+ `body` must point to `len` readable bytes.
+ */
+int gossip_send(GossipHandle *handle, const uint8_t *body, uintptr_t len);
+
+/*
+ Writes the listen addresses of the connected peers, comma-separated, to `buf`,
+ truncated to `len - 1` bytes and NUL-terminated, like `snprintf`.
+ Returns the length of the whole list, without the NUL.
+
+ # Safety
+
+ `handle` must be returned by `gossip_start` and not stopped,
+ `buf` must point to `len` writable bytes, or be NULL if `len` is 0.
+ */
+uintptr_t gossip_peers(GossipHandle *handle, char *buf, uintptr_t len);
+
+/*
+ Fills in `event` with the next event of the node, without waiting for one.
+ Returns whether there was one.
+
+ # Safety
+
+ `handle` must be returned by `gossip_start` and not stopped,
+ `event` must point to a writable `GossipEvent`.
+ */
+bool gossip_poll_event(GossipHandle *handle, GossipEvent *event);
+
+/*
+ Drains the node for up to the drain timeout, stops it and frees `handle`.
+
+ # Safety
+
+ `handle` must be returned by `gossip_start` and not stopped, or NULL.
+ */
+void gossip_stop(GossipHandle *handle);
+
+#endif  /* P2P_GOSSIP_H */
@@ -0,0 +1,172 @@
+"""Python bindings of p2p-gossip, over the C ABI of its `ffi` feature.
+
+The shared library is looked up in `P2P_GOSSIP_LIB`, else on the library path
+as `p2p_gossip`, once built with
+`cargo rustc --release --lib --features ffi --crate-type cdylib`.
+"""
+
+import asyncio
+import ctypes
+import ctypes.util
+import os
+import time
+from typing import AsyncIterator, Iterator, List, NamedTuple, Optional, Sequence
+
+# The kind of the message events, see `GossipEventKind`
+_MESSAGE = 0
+
+
+class _Options(ctypes.Structure):
+    _fields_ = [
+        ("port", ctypes.c_uint16),
+        ("connect", ctypes.c_char_p),
+        ("cert", ctypes.c_char_p),
+        ("key", ctypes.c_char_p),
+        ("ephemeral_cert", ctypes.c_bool),
+        ("skip_server_verification", ctypes.c_bool),
+        ("log_level", ctypes.c_char_p),
+    ]
+
+
+class _Event(ctypes.Structure):
+    _fields_ = [
+        ("kind", ctypes.c_int),
+        ("peer", ctypes.c_char_p),
+        ("origin", ctypes.c_char_p),
+        ("body", ctypes.POINTER(ctypes.c_uint8)),
+        ("body_len", ctypes.c_size_t),
+    ]
+
+
+_lib = None
+
+
+def _library() -> ctypes.CDLL:
+    global _lib
+    if _lib is None:
+        path = os.environ.get("P2P_GOSSIP_LIB") or ctypes.util.find_library("p2p_gossip")
+        if path is None:
+            raise OSError("libp2p_gossip not found, set P2P_GOSSIP_LIB to its path")
+        lib = ctypes.CDLL(path)
+        lib.gossip_start.argtypes = [ctypes.POINTER(_Options)]
+        lib.gossip_start.restype = ctypes.c_void_p
+        lib.gossip_send.argtypes = [ctypes.c_void_p, ctypes.c_char_p, ctypes.c_size_t]
+        lib.gossip_send.restype = ctypes.c_int
+        lib.gossip_peers.argtypes = [ctypes.c_void_p, ctypes.c_char_p, ctypes.c_size_t]
+        lib.gossip_peers.restype = ctypes.c_size_t
+        lib.gossip_poll_event.argtypes = [ctypes.c_void_p, ctypes.POINTER(_Event)]
+        lib.gossip_poll_event.restype = ctypes.c_bool
+        lib.gossip_stop.argtypes = [ctypes.c_void_p]
+        lib.gossip_stop.restype = None
+        _lib = lib
+    return _lib
+
+
+def _encode(s: Optional[str]) -> Optional[bytes]:
+    return None if s is None else s.encode()
+
+
+class Message(NamedTuple):
+    """A message received from `peer`, created by `origin`."""
+
+    peer: str
+    origin: str
+    body: str
+
+
+class Peer:
+    """A node of the mesh, running until `close` is called or its `with` block ends.
+
+    The options are the ones of the binary with the same names;
+    `log_level` sets the log levels of the whole process.
+    """
+
+    def __init__(
+        self,
+        port: int,
+        connect: Sequence[str] = (),
+        *,
+        cert: Optional[str] = None,
+        key: Optional[str] = None,
+        ephemeral_cert: bool = False,
+        skip_server_verification: bool = False,
+        log_level: Optional[str] = None,
+        poll_interval: float = 0.01,
+    ):
+        self._lib = _library()
+        options = _Options(
+            port,
+            _encode(",".join(connect) or None),
+            _encode(cert),
+            _encode(key),
+            ephemeral_cert,
+            skip_server_verification,
+            _encode(log_level),
+        )
+        self._handle = self._lib.gossip_start(ctypes.byref(options))
+        if not self._handle:
+            raise OSError("failed to start the node, see its log")
+        self._poll_interval = poll_interval
+
+    def _node(self) -> int:
+        if not self._handle:
+            raise ValueError("the node is closed")
+        return self._handle
+
+    def send(self, body: str) -> None:
+        """Sends a new message with `body` to all peers."""
+        data = body.encode()
+        self._lib.gossip_send(self._node(), data, len(data))
+
+    def peers(self) -> List[str]:
+        """The listen addresses of the peers the node is connected to."""
+        node = self._node()
+        size = self._lib.gossip_peers(node, None, 0) + 1
+        buf = ctypes.create_string_buffer(size)
+        self._lib.gossip_peers(node, buf, size)
+        return buf.value.decode().split(",") if buf.value else []
+
+    def _poll(self) -> Optional[Message]:
+        event = _Event()
+        while self._lib.gossip_poll_event(self._node(), ctypes.byref(event)):
+            if event.kind == _MESSAGE:
+                body = ctypes.string_at(event.body, event.body_len)
+                return Message(event.peer.decode(), event.origin.decode(), body.decode())
+        return None
+
+    def subscribe(self, timeout: Optional[float] = None) -> Iterator[Message]:
+        """Yields the received messages, until none comes for `timeout` seconds if set."""
+        deadline = None if timeout is None else time.monotonic() + timeout
+        while deadline is None or time.monotonic() < deadline:
+            message = self._poll()
+            if message is None:
+                time.sleep(self._poll_interval)
+                continue
+            yield message
+            if timeout is not None:
+                deadline = time.monotonic() + timeout
+
+    async def messages(self) -> AsyncIterator[Message]:
+        """Yields the received messages without blocking the event loop."""
+        while True:
+            message = self._poll()
+            if message is None:
+                await asyncio.sleep(self._poll_interval)
+            else:
+                yield message
+
+    def close(self) -> None:
+        """Drains the node and stops it."""
+        if self._handle:
+            self._lib.gossip_stop(self._handle)
+            self._handle = None
+
+    def __enter__(self) -> "Peer":
+        return self
+
+    def __exit__(self, *exc) -> None:
+        self.close()
+
+    def __del__(self) -> None:
+        if hasattr(self, "_handle"):
+            self.close()
@@ -0,0 +1,261 @@
+use crate::{
+    address_validation::AddressValidation,
+    config::{configure_server, read_certs_from_file, ConfigError},
+    log::log,
+};
+use core::{net::SocketAddr, time::Duration};
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use quinn::TransportConfig;
+use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Certificates older than this are renewed, Let's Encrypt ones last 90 days.
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+/// How often the age of the certificate is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// How long to wait before trying again after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How many times the order is polled for its status before giving up.
+const MAX_POLLS: u32 = 10;
+
+#[derive(Error, Debug)]
+pub enum AcmeError {
+    #[error("ACME error: {0}")]
+    Acme(#[from] instant_acme::Error),
+    #[error("failed to generate the key: {0}")]
+    Rcgen(#[from] rcgen::RcgenError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid account credentials: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("{0}")]
+    Order(String),
+}
+
+/// Obtains certificates for `domain` from the ACME server of `directory`
+/// with the HTTP-01 challenge, served on `http_listen`.
+pub struct Acme {
+    pub domain: String,
+    /// A contact URI, e.g. `mailto:admin@example.com`.
+    pub contact: Option<String>,
+    pub directory: String,
+    pub http_listen: SocketAddr,
+    /// The file the account credentials are kept in, created on first use.
+    pub account: PathBuf,
+    /// The files the obtained certificate chain and key are written to.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Whether the certificate in `path` is missing or older than `RENEW_AFTER`.
+fn renewal_due(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age > RENEW_AFTER)
+        })
+        .unwrap_or(true)
+}
+
+/// Obtains a certificate whenever the one in `acme.cert` is due for renewal
+/// and replaces the certificate of the endpoints with it, keeping `transport`.
+/// Logs errors on failure.
+pub async fn renew_loop(
+    acme: Acme,
+    address_validation: Arc<AddressValidation>,
+    transport: Arc<TransportConfig>,
+) {
+    loop {
+        if !renewal_due(&acme.cert) {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            continue;
+        }
+        let res = async {
+            let (cert_pem, key_pem) = obtain(&acme).await?;
+            fs::write(&acme.cert, cert_pem)?;
+            fs::write(&acme.key, key_pem)?;
+            let (certs, key) = read_certs_from_file(&acme.cert, &acme.key, None)?;
+            let server_config = configure_server(certs, key, false, None, transport.clone())?;
+            address_validation.set_server_config(Some(server_config));
+            Ok::<_, AcmeError>(())
+        }
+        .await;
+        match res {
+            Ok(()) => log(&[b"Obtained a certificate for ", acme.domain.as_bytes()]),
+            Err(e) => {
+                log(&[
+                    b"Failed to obtain a certificate for ",
+                    acme.domain.as_bytes(),
+                    b", error: ",
+                    e.to_string().as_bytes(),
+                ]);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Restores the account from `acme.account`, or creates one and saves it there.
+async fn account(acme: &Acme) -> Result<Account, AcmeError> {
+    if let Ok(credentials) = fs::read(&acme.account) {
+        let credentials: AccountCredentials = serde_json::from_slice(&credentials)?;
+        return Ok(Account::from_credentials(credentials).await?);
+    }
+    let contact: Vec<_> = acme.contact.iter().map(String::as_str).collect();
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        &acme.directory,
+        None,
+    )
+    .await?;
+    fs::write(&acme.account, serde_json::to_vec(&credentials)?)?;
+    Ok(account)
+}
+
+/// Orders a certificate for `acme.domain`, returning the PEM
+/// of its chain and of its private key.
+async fn obtain(acme: &Acme) -> Result<(String, String), AcmeError> {
+    let account = account(acme).await?;
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &[Identifier::Dns(acme.domain.clone())],
+        })
+        .await?;
+
+    let mut key_authorizations = HashMap::new();
+    let mut challenge_urls = Vec::new();
+    for authorization in order.authorizations().await? {
+        match authorization.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => return Err(AcmeError::Order(format!("authorization is {status:?}"))),
+        }
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Http01)
+            .ok_or_else(|| AcmeError::Order("no HTTP-01 challenge offered".into()))?;
+        key_authorizations.insert(
+            challenge.token.clone(),
+            order.key_authorization(challenge).as_str().to_owned(),
+        );
+        challenge_urls.push(challenge.url.clone());
+    }
+
+    let listener = TcpListener::bind(acme.http_listen).await?;
+    let server = tokio::spawn(challenge_server(listener, Arc::new(key_authorizations)));
+    let res = async {
+        for url in &challenge_urls {
+            order.set_challenge_ready(url).await?;
+        }
+        let mut delay = Duration::from_secs(1);
+        for _ in 0..MAX_POLLS {
+            tokio::time::sleep(delay).await;
+            match order.refresh().await?.status {
+                OrderStatus::Ready => return Ok(()),
+                OrderStatus::Invalid => return Err(AcmeError::Order("order is invalid".into())),
+                _ => delay *= 2,
+            }
+        }
+        Err(AcmeError::Order("order is not ready in time".into()))
+    }
+    .await;
+    server.abort();
+    res?;
+
+    let mut params = CertificateParams::new(vec![acme.domain.clone()]);
+    params.distinguished_name = DistinguishedName::new();
+    let cert = Certificate::from_params(params)?;
+    order.finalize(&cert.serialize_request_der()?).await?;
+    let mut delay = Duration::from_secs(1);
+    for _ in 0..MAX_POLLS {
+        if let Some(chain) = order.certificate().await? {
+            return Ok((chain, cert.serialize_private_key_pem()));
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+    Err(AcmeError::Order("certificate is not issued in time".into()))
+}
+
+/// Answers the HTTP-01 challenges of `key_authorizations` by their tokens.
+async fn challenge_server(listener: TcpListener, key_authorizations: Arc<HashMap<String, String>>) {
+    while let Ok((mut stream, _)) = listener.accept().await {
+        let key_authorizations = key_authorizations.clone();
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+            let len = stream.read(&mut request).await.unwrap_or(0);
+            let response = match challenge_response(&request[..len], &key_authorizations) {
+                Some(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Returns the key authorization requested by `request`, if any.
+fn challenge_response<'a>(
+    request: &[u8],
+    key_authorizations: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    let request = core::str::from_utf8(request).ok()?;
+    let path = request.strip_prefix("GET ")?.split(' ').next()?;
+    let token = path.strip_prefix("/.well-known/acme-challenge/")?;
+    key_authorizations.get(token).map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_response() {
+        let key_authorizations = HashMap::from([("token".to_owned(), "token.key".to_owned())]);
+        assert_eq!(
+            challenge_response(
+                b"GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                &key_authorizations
+            ),
+            Some("token.key")
+        );
+        assert_eq!(
+            challenge_response(
+                b"GET /.well-known/acme-challenge/other HTTP/1.1\r\n\r\n",
+                &key_authorizations
+            ),
+            None
+        );
+        assert_eq!(
+            challenge_response(b"GET / HTTP/1.1\r\n\r\n", &key_authorizations),
+            None
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use clap::ValueEnum;
+use core::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+use std::collections::HashMap;
+
+/// The number of invalid addresses a peer may gossip before its peer lists are ignored.
+const INVALID_LIMIT: u32 = 16;
+
+/// Why an address in a peer list is not dialed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidAddress {
+    Unspecified,
+    Multicast,
+    Broadcast,
+    /// The address the node listens on, in any of its forms.
+    Own,
+    Port,
+}
+
+impl fmt::Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unspecified => "unspecified address",
+            Self::Multicast => "multicast address",
+            Self::Broadcast => "broadcast address",
+            Self::Own => "own address",
+            Self::Port => "invalid port",
+        })
+    }
+}
+
+/// Checks an address of a peer list received by the node listening on `local_addr`.
+/// IPv4-mapped IPv6 addresses are checked as the IPv4 ones.
+pub fn check_address(addr: SocketAddr, local_addr: SocketAddr) -> Result<(), InvalidAddress> {
+    let ip = addr.ip().to_canonical();
+    if ip.is_unspecified() {
+        return Err(InvalidAddress::Unspecified);
+    }
+    if ip.is_multicast() {
+        return Err(InvalidAddress::Multicast);
+    }
+    if ip == IpAddr::V4(Ipv4Addr::BROADCAST) {
+        return Err(InvalidAddress::Broadcast);
+    }
+    if addr.port() == 0 {
+        return Err(InvalidAddress::Port);
+    }
+    let local_ip = local_addr.ip().to_canonical();
+    if addr.port() == local_addr.port()
+        && (ip == local_ip || ip.is_loopback() && local_ip.is_unspecified())
+    {
+        return Err(InvalidAddress::Own);
+    }
+    Ok(())
+}
+
+/// The address the peer connecting from `remote_addr` is dialed on, given the
+/// `listen_addr` it advertised: with the IP it connected from if it advertised
+/// the unspecified one, e.g. when listening on all interfaces.
+pub fn advertised_addr(listen_addr: SocketAddr, remote_addr: SocketAddr) -> SocketAddr {
+    if !listen_addr.ip().to_canonical().is_unspecified() {
+        return listen_addr;
+    }
+    match remote_addr.ip().to_canonical() {
+        IpAddr::V4(ip) => SocketAddr::new(ip.into(), listen_addr.port()),
+        // keeping the scope of a link-local address
+        IpAddr::V6(_) => {
+            let mut addr = remote_addr;
+            addr.set_port(listen_addr.port());
+            addr
+        }
+    }
+}
+
+/// Whether `origin`, the origin of a message received from the peer on `remote_addr`,
+/// is that peer, in the form of the listen address it advertises.
+pub fn is_sent_by(origin: SocketAddr, remote_addr: SocketAddr) -> bool {
+    advertised_addr(origin, remote_addr) == remote_addr
+}
+
+/// Which peers private addresses are exchanged with, selected with `--advertise-private`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AdvertisePrivate {
+    Never,
+    /// With the peers on private addresses themselves.
+    LanOnly,
+    Always,
+}
+
+impl AdvertisePrivate {
+    /// Whether `addr` is sent to or taken from the peer listening on `peer`.
+    pub fn allows(self, addr: SocketAddr, peer: SocketAddr) -> bool {
+        if !is_private(addr.ip()) {
+            return true;
+        }
+        match self {
+            Self::Never => false,
+            Self::LanOnly => is_private(peer.ip()),
+            Self::Always => true,
+        }
+    }
+}
+
+/// Whether `ip` is a private (RFC 1918), unique local or link-local address,
+/// not routable between networks. Loopback addresses are not counted.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        // fc00::/7 and fe80::/10
+        IpAddr::V6(ip) => {
+            ip.segments()[0] & 0xfe00 == 0xfc00 || ip.segments()[0] & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Counts the invalid addresses gossiped by each peer,
+/// whose peer lists are ignored past a limit.
+#[derive(Default)]
+pub struct GossipScores {
+    invalid: HashMap<SocketAddr, u32>,
+}
+
+impl GossipScores {
+    /// Records an invalid address gossiped by `peer`,
+    /// returning whether its peer lists are ignored from now on.
+    pub fn record_invalid(&mut self, peer: SocketAddr) -> bool {
+        let invalid = self.invalid.entry(peer).or_default();
+        *invalid += 1;
+        *invalid == INVALID_LIMIT
+    }
+
+    pub fn is_ignored(&self, peer: SocketAddr) -> bool {
+        self.invalid
+            .get(&peer)
+            .is_some_and(|&invalid| invalid >= INVALID_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_address() {
+        let local = "127.0.0.1:9000".parse().unwrap();
+        let check = |addr: &str| check_address(addr.parse().unwrap(), local);
+        assert_eq!(check("127.0.0.1:9001"), Ok(()));
+        assert_eq!(check("[::1]:9000"), Ok(()));
+        assert_eq!(check("0.0.0.0:9001"), Err(InvalidAddress::Unspecified));
+        assert_eq!(check("[::]:9001"), Err(InvalidAddress::Unspecified));
+        assert_eq!(check("224.0.0.1:9001"), Err(InvalidAddress::Multicast));
+        assert_eq!(check("[ff02::1]:9001"), Err(InvalidAddress::Multicast));
+        assert_eq!(
+            check("255.255.255.255:9001"),
+            Err(InvalidAddress::Broadcast)
+        );
+        assert_eq!(check("127.0.0.2:0"), Err(InvalidAddress::Port));
+        assert_eq!(check("127.0.0.1:9000"), Err(InvalidAddress::Own));
+        assert_eq!(check("[::ffff:127.0.0.1]:9000"), Err(InvalidAddress::Own));
+        let unspecified = "0.0.0.0:9000".parse().unwrap();
+        assert_eq!(
+            check_address("127.0.0.1:9000".parse().unwrap(), unspecified),
+            Err(InvalidAddress::Own)
+        );
+    }
+
+    #[test]
+    fn test_advertised_addr() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let remote = addr("10.0.0.2:51000");
+        assert_eq!(
+            advertised_addr(addr("10.0.0.3:9000"), remote),
+            addr("10.0.0.3:9000")
+        );
+        assert_eq!(
+            advertised_addr(addr("0.0.0.0:9000"), remote),
+            addr("10.0.0.2:9000")
+        );
+        assert_eq!(
+            advertised_addr(addr("[::]:9000"), addr("[::ffff:10.0.0.2]:51000")),
+            addr("10.0.0.2:9000")
+        );
+        assert_eq!(
+            advertised_addr(addr("[::]:9000"), addr("[fe80::2%3]:51000")),
+            addr("[fe80::2%3]:9000")
+        );
+        assert!(is_sent_by(addr("0.0.0.0:9000"), addr("10.0.0.2:9000")));
+        assert!(!is_sent_by(addr("10.0.0.3:9000"), addr("10.0.0.2:9000")));
+    }
+
+    #[test]
+    fn test_advertise_private() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        for private in [
+            "10.0.0.1:1",
+            "172.16.0.1:1",
+            "192.168.1.1:1",
+            "169.254.0.1:1",
+        ] {
+            assert!(is_private(addr(private).ip()), "{private}");
+        }
+        for private in ["[fd00::1]:1", "[fe80::1]:1", "[::ffff:192.168.1.1]:1"] {
+            assert!(is_private(addr(private).ip()), "{private}");
+        }
+        for public in [
+            "8.8.8.8:1",
+            "127.0.0.1:1",
+            "172.32.0.1:1",
+            "[2001:db8::1]:1",
+        ] {
+            assert!(!is_private(addr(public).ip()), "{public}");
+        }
+
+        let (lan, wan) = (addr("192.168.1.1:9000"), addr("8.8.8.8:9000"));
+        assert!(AdvertisePrivate::Never.allows(wan, lan));
+        assert!(!AdvertisePrivate::Never.allows(lan, lan));
+        assert!(AdvertisePrivate::LanOnly.allows(lan, addr("10.0.0.1:9000")));
+        assert!(!AdvertisePrivate::LanOnly.allows(lan, wan));
+        assert!(AdvertisePrivate::Always.allows(lan, wan));
+    }
+
+    #[test]
+    fn test_gossip_scores() {
+        let peer = "127.0.0.1:9000".parse().unwrap();
+        let mut scores = GossipScores::default();
+        for _ in 1..INVALID_LIMIT {
+            assert!(!scores.record_invalid(peer));
+        }
+        assert!(!scores.is_ignored(peer));
+        assert!(scores.record_invalid(peer));
+        assert!(scores.is_ignored(peer));
+        assert!(!scores.record_invalid(peer));
+    }
+}
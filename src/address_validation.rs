@@ -0,0 +1,146 @@
+use crate::log::log;
+use core::time::Duration;
+use quinn::{Endpoint, ServerConfig};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// The time validation stays on after the threshold was last reached.
+///
+/// quinn ignores the retry tokens of clients once validation is off,
+/// which fails the handshakes of the clients sent a retry just before,
+/// so it is not turned off as soon as the load drops.
+const HOLD: Duration = Duration::from_secs(60);
+
+/// Sets the server config of the endpoints, requiring clients to validate
+/// their addresses with a stateless retry once `threshold` handshakes
+/// are in progress, so that spoofed handshakes cost a round trip before
+/// any cryptographic work is done.
+pub struct AddressValidation {
+    endpoints: Arc<[Endpoint]>,
+    threshold: Option<usize>,
+    handshakes: AtomicUsize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    config: Option<ServerConfig>,
+    validating: bool,
+    /// The last time the threshold was reached.
+    reached: Option<Instant>,
+}
+
+impl AddressValidation {
+    /// Without a `threshold`, addresses are never validated.
+    pub fn new(endpoints: Arc<[Endpoint]>, threshold: Option<usize>) -> Self {
+        Self {
+            endpoints,
+            threshold,
+            handshakes: AtomicUsize::new(0),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Sets the server config of the endpoints, `None` to stop accepting connections.
+    pub fn set_server_config(&self, config: Option<ServerConfig>) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.validating = self.should_validate(&mut state);
+        self.apply(&state);
+    }
+
+    /// Counts a handshake as in progress until the returned `Handshake` is dropped.
+    pub fn track(self: &Arc<Self>) -> Handshake {
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        self.update();
+        Handshake(self.clone())
+    }
+
+    fn should_validate(&self, state: &mut State) -> bool {
+        let Some(threshold) = self.threshold else {
+            return false;
+        };
+        let now = Instant::now();
+        if self.handshakes.load(Ordering::Relaxed) >= threshold {
+            state.reached = Some(now);
+        }
+        state
+            .reached
+            .is_some_and(|reached| now.duration_since(reached) < HOLD)
+    }
+
+    fn update(&self) {
+        let mut state = self.state.lock().unwrap();
+        let validating = self.should_validate(&mut state);
+        if state.validating == validating {
+            return;
+        }
+        state.validating = validating;
+        self.apply(&state);
+        let handshakes = self.handshakes.load(Ordering::Relaxed).to_string();
+        if validating {
+            log(&[
+                b"Validating client addresses, ",
+                handshakes.as_bytes(),
+                b" handshakes in progress",
+            ]);
+        } else {
+            log(&[
+                b"Stopped validating client addresses, ",
+                handshakes.as_bytes(),
+                b" handshakes in progress",
+            ]);
+        }
+    }
+
+    fn apply(&self, state: &State) {
+        let config = state.config.clone().map(|mut config| {
+            config.use_retry(state.validating);
+            config
+        });
+        for endpoint in &*self.endpoints {
+            endpoint.set_server_config(config.clone());
+        }
+    }
+}
+
+/// A handshake counted by an `AddressValidation`.
+pub struct Handshake(Arc<AddressValidation>);
+
+impl Drop for Handshake {
+    fn drop(&mut self) {
+        self.0.handshakes.fetch_sub(1, Ordering::Relaxed);
+        self.0.update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_validation() {
+        let validation = Arc::new(AddressValidation::new(Arc::from([]), Some(2)));
+        let validating = || validation.state.lock().unwrap().validating;
+        let first = validation.track();
+        assert!(!validating());
+        let second = validation.track();
+        assert!(validating());
+        drop(first);
+        drop(second);
+        // held after the load drops
+        assert!(validating());
+
+        let always = AddressValidation::new(Arc::from([]), Some(0));
+        always.set_server_config(None);
+        assert!(always.state.lock().unwrap().validating);
+        let never = Arc::new(AddressValidation::new(Arc::from([]), None));
+        let _handshake = never.track();
+        assert!(!never.state.lock().unwrap().validating);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{
+    blocklist::Blocked,
+    error::CloseReason,
+    log::{log_levels, set_log_levels},
+    peers::diff,
+    producer::{gather_topology, overlay_ping, request, send_to, trace_route},
+    stats::PathStats,
+    Context,
+};
+use core::{fmt::Write, net::SocketAddr};
+use std::{collections::HashMap, io, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    time::Instant,
+};
+
+/// Serves the admin API on `listener`, until accepting a connection fails.
+///
+/// Each line a client sends is a command, each response
+/// is terminated by an empty line.
+pub async fn admin_loop(ctx: Context, listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        ctx.supervisor
+            .spawn("admin_client", handle_client(ctx.clone(), stream));
+    }
+}
+
+/// Runs the commands sent by an admin client.
+async fn handle_client(ctx: Context, stream: TcpStream) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim() == "watch peers" {
+            return watch_peers(&ctx, lines, write).await;
+        }
+        let mut response = run_command(&ctx, line.trim()).await;
+        response.push('\n');
+        write.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Runs `watch peers`, writing the peers as added and then each change
+/// to them, one per line, until the client disconnects.
+async fn watch_peers(
+    ctx: &Context,
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    mut write: OwnedWriteHalf,
+) -> io::Result<()> {
+    let mut changed = ctx.peers.subscribe();
+    let mut old = Arc::new(HashMap::new());
+    loop {
+        changed.mark_unchanged();
+        let new = ctx.peers.snapshot();
+        let mut response = String::new();
+        for change in diff(&old, &new) {
+            writeln!(response, "{change}").unwrap();
+        }
+        write.write_all(response.as_bytes()).await?;
+        old = new;
+        tokio::select! {
+            res = changed.changed() => if res.is_err() {
+                return Ok(());
+            },
+            // further commands are ignored
+            line = lines.next_line() => if line?.is_none() {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Runs `command`, returning the response, which ends with a newline.
+async fn run_command(ctx: &Context, command: &str) -> String {
+    let mut response = String::new();
+    match command {
+        "aggregates" => match ctx.aggregator.lock().await.estimates() {
+            Some(estimates) => {
+                match estimates.nodes {
+                    Some(nodes) => writeln!(response, "nodes: {nodes:.2}"),
+                    None => writeln!(response, "nodes: unknown"),
+                }
+                .unwrap();
+                writeln!(
+                    response,
+                    "peers: avg {:.2}, min {}, max {}",
+                    estimates.average, estimates.min, estimates.max
+                )
+                .unwrap();
+            }
+            None => response.push_str("no estimates yet\n"),
+        },
+        "stats" => response.push_str(&ctx.stats.to_string()),
+        "peers" => {
+            let sessions = ctx.sessions.lock().await;
+            let now = Instant::now();
+            for (&peer, connection) in ctx.connections.lock().await.iter() {
+                write_peer_name(ctx, peer, &mut response);
+                write!(response, ": {}", PathStats::of(connection)).unwrap();
+                if let Some(stats) = sessions.stats(peer, now) {
+                    write!(response, "; {stats}").unwrap();
+                }
+                response.push('\n');
+            }
+            // so that peers which disconnect for long stand out too
+            for peer in sessions.disconnected_peers() {
+                write_peer_name(ctx, peer, &mut response);
+                let stats = sessions.stats(peer, now).unwrap();
+                writeln!(response, ": disconnected; {stats}").unwrap();
+            }
+        }
+        "kv" => match &ctx.kv_store {
+            Some(kv_store) => {
+                for (origin, envelope) in kv_store.entries() {
+                    writeln!(response, "{origin}: {}", envelope.body).unwrap();
+                }
+            }
+            None => response.push_str("the kv handler is not enabled\n"),
+        },
+        "loglevel" => writeln!(response, "{}", log_levels()).unwrap(),
+        "blocklist" => {
+            for blocked in ctx.blocklist.lock().await.iter() {
+                writeln!(response, "{blocked}").unwrap();
+            }
+        }
+        "filters" => {
+            for (i, rule) in ctx.filters.lock().await.iter().enumerate() {
+                writeln!(response, "{i}: {rule}").unwrap();
+            }
+        }
+        _ => match command.split_once(' ') {
+            Some(("filter", args)) => run_filter_command(ctx, args, &mut response).await,
+            Some(("loglevel", spec)) => {
+                let mut levels = log_levels();
+                match levels.apply(spec) {
+                    Ok(()) => {
+                        set_log_levels(levels);
+                        writeln!(response, "{levels}")
+                    }
+                    Err(e) => writeln!(response, "invalid log level: {e}"),
+                }
+                .unwrap();
+            }
+            Some(("block", blocked)) => run_block_command(ctx, blocked, &mut response).await,
+            Some(("unblock", blocked)) => match blocked.parse() {
+                Ok(blocked) => match ctx.blocklist.lock().await.remove(blocked) {
+                    Ok(true) => writeln!(response, "unblocked {blocked}"),
+                    Ok(false) => writeln!(response, "{blocked} is not blocked"),
+                    Err(e) => writeln!(response, "failed to save the blocklist: {e}"),
+                },
+                Err(e) => writeln!(response, "invalid peer {blocked:?}: {e}"),
+            }
+            .unwrap(),
+            Some(("send", args)) => run_send_command(ctx, args, &mut response).await,
+            Some(("request", args)) => run_request_command(ctx, args, &mut response).await,
+            Some(("ping", peer)) => match peer.parse() {
+                Ok(peer) => match overlay_ping(ctx, peer).await {
+                    Ok((rtt, hops)) => writeln!(response, "rtt {rtt:?}, {hops} hops"),
+                    Err(e) => writeln!(response, "ping failed: {e}"),
+                },
+                Err(e) => writeln!(response, "invalid peer {peer:?}: {e}"),
+            }
+            .unwrap(),
+            Some(("trace", peer)) => match peer.parse() {
+                Ok(peer) => match trace_route(ctx, peer).await {
+                    Ok(path) => {
+                        for (i, hop) in path.iter().enumerate() {
+                            writeln!(response, "{}: {hop}", i + 1).unwrap();
+                        }
+                    }
+                    Err(e) => writeln!(response, "trace failed: {e}").unwrap(),
+                },
+                Err(e) => writeln!(response, "invalid peer {peer:?}: {e}").unwrap(),
+            },
+            Some(("topology", "dot")) => response.push_str(&gather_topology(ctx).await.to_dot()),
+            Some(("topology", "json")) => {
+                writeln!(response, "{}", gather_topology(ctx).await.to_json()).unwrap();
+            }
+            _ => writeln!(response, "unknown command {command:?}").unwrap(),
+        },
+    }
+    response
+}
+
+/// Writes `peer` followed by its alias in the `--peers-file`, if it has one.
+fn write_peer_name(ctx: &Context, peer: SocketAddr, response: &mut String) {
+    write!(response, "{peer}").unwrap();
+    if let Some(Some(alias)) = ctx.static_peers.read().unwrap().get(&peer) {
+        write!(response, " ({alias})").unwrap();
+    }
+}
+
+/// Runs `block PEER`, closing the connections to the peers it blocks.
+async fn run_block_command(ctx: &Context, blocked: &str, response: &mut String) {
+    let blocked: Blocked = match blocked.parse() {
+        Ok(blocked) => blocked,
+        Err(e) => {
+            writeln!(response, "invalid peer {blocked:?}: {e}").unwrap();
+            return;
+        }
+    };
+    match ctx.blocklist.lock().await.insert(blocked) {
+        Ok(true) => writeln!(response, "blocked {blocked}"),
+        Ok(false) => writeln!(response, "{blocked} is already blocked"),
+        Err(e) => writeln!(response, "failed to save the blocklist: {e}"),
+    }
+    .unwrap();
+    for (&peer, connection) in ctx.connections.lock().await.iter() {
+        if ctx.is_blocked(peer).await {
+            CloseReason::Banned.close(connection);
+        }
+    }
+}
+
+/// Runs `send PEER PAYLOAD`.
+async fn run_send_command(ctx: &Context, args: &str, response: &mut String) {
+    let Some((peer, payload)) = args.split_once(' ') else {
+        writeln!(response, "no payload to send").unwrap();
+        return;
+    };
+    match peer.parse() {
+        Ok(peer) => match send_to(ctx, peer, payload.to_owned()).await {
+            0 => writeln!(response, "no connections to send over"),
+            sent => writeln!(response, "sent over {sent} connections"),
+        },
+        Err(e) => writeln!(response, "invalid peer {peer:?}: {e}"),
+    }
+    .unwrap();
+}
+
+/// Runs `request PEER BODY`.
+async fn run_request_command(ctx: &Context, args: &str, response: &mut String) {
+    let Some((peer, body)) = args.split_once(' ') else {
+        writeln!(response, "no request to send").unwrap();
+        return;
+    };
+    match peer.parse() {
+        Ok(peer) => match request(ctx, peer, body.to_owned()).await {
+            Ok(output) => writeln!(response, "{}", output.trim_end()),
+            Err(e) => writeln!(response, "request failed: {e}"),
+        },
+        Err(e) => writeln!(response, "invalid peer {peer:?}: {e}"),
+    }
+    .unwrap();
+}
+
+/// Runs `filter add RULE` or `filter remove INDEX`.
+async fn run_filter_command(ctx: &Context, args: &str, response: &mut String) {
+    let mut filters = ctx.filters.lock().await;
+    match args.split_once(' ') {
+        Some(("add", rule)) => match rule.parse() {
+            Ok(rule) => {
+                filters.push(rule);
+                writeln!(response, "added filter {}", filters.len() - 1)
+            }
+            Err(e) => writeln!(response, "invalid rule: {e}"),
+        },
+        Some(("remove", index)) => match index.parse() {
+            Ok(index) if index < filters.len() => {
+                let rule = filters.remove(index);
+                writeln!(response, "removed filter {rule}")
+            }
+            _ => writeln!(response, "no filter {index:?}"),
+        },
+        _ => writeln!(response, "unknown filter command {args:?}"),
+    }
+    .unwrap();
+}
+
+/// Prints the connected peers of the node serving the admin API on `admin`,
+/// or with `watch`, the changes to its peers as they happen.
+pub async fn print_peers(admin: SocketAddr, watch: bool) -> io::Result<()> {
+    let command = if watch { "watch peers" } else { "peers" };
+    print_lines(admin, command, !watch).await
+}
+
+/// Sends `command` to the node serving the admin API on `admin`
+/// and prints its response.
+pub async fn print_response(admin: SocketAddr, command: &str) -> io::Result<()> {
+    print_lines(admin, command, true).await
+}
+
+/// Sends `command` to the admin API on `admin` and prints the lines it responds with,
+/// until the empty line terminating the response if `until_empty`, else until it disconnects.
+async fn print_lines(admin: SocketAddr, command: &str, until_empty: bool) -> io::Result<()> {
+    let (read, mut write) = TcpStream::connect(admin).await?.into_split();
+    write.write_all(format!("{command}\n").as_bytes()).await?;
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() && until_empty {
+            break;
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use core::net::SocketAddr;
+use serde::{Deserialize, Serialize};
+
+/// A push-sum pair, the estimate being `sum / weight`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PushSum {
+    pub sum: f64,
+    pub weight: f64,
+}
+
+impl PushSum {
+    fn estimate(self) -> Option<f64> {
+        (self.weight > 0.0).then(|| self.sum / self.weight)
+    }
+
+    fn halve(&mut self) -> Self {
+        self.sum /= 2.0;
+        self.weight /= 2.0;
+        *self
+    }
+
+    fn add(&mut self, other: Self) {
+        self.sum += other.sum;
+        self.weight += other.weight;
+    }
+}
+
+/// The half of the aggregation state a peer sends to a random peer each round.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Share {
+    pub epoch: u64,
+    /// The lowest address seen in the epoch.
+    pub leader: SocketAddr,
+    pub count: PushSum,
+    pub average: PushSum,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Estimates of global values computed over a whole epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimates {
+    /// Unknown until the peers agree on a leader.
+    pub nodes: Option<f64>,
+    pub average: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Estimates the number of nodes and the average, minimum and maximum
+/// of a metric over all nodes with push-sum gossip (Kempe et al., 2003).
+///
+/// Aggregation restarts every epoch, so that estimates follow the changes
+/// of the network. The estimates of the last finished epoch are reported.
+///
+/// To count the nodes, every node starts with a sum of 1 and only the
+/// leader, the node with the lowest address seen in the previous epoch,
+/// starts with a weight of 1, so that the sums add up to the number of nodes.
+pub struct Aggregator {
+    addr: SocketAddr,
+    epoch: u64,
+    leader: SocketAddr,
+    count: PushSum,
+    average: PushSum,
+    min: f64,
+    max: f64,
+    /// Whether the epoch was started from the beginning,
+    /// otherwise its estimates are incomplete.
+    complete: bool,
+    last: Option<Estimates>,
+}
+
+impl Aggregator {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            epoch: 0,
+            leader: addr,
+            count: PushSum::default(),
+            average: PushSum::default(),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            complete: false,
+            last: None,
+        }
+    }
+
+    /// Moves to `epoch` if it is newer, with `metric` as the local value.
+    fn advance(&mut self, epoch: u64, metric: f64) {
+        if epoch <= self.epoch {
+            return;
+        }
+        if self.complete && self.epoch + 1 == epoch {
+            self.last = self.average.estimate().map(|average| Estimates {
+                nodes: self.count.estimate(),
+                average,
+                min: self.min,
+                max: self.max,
+            });
+        } else {
+            self.last = None;
+        }
+        let is_leader = self.complete && self.leader == self.addr;
+        self.complete = self.epoch != 0;
+        self.epoch = epoch;
+        self.leader = self.addr;
+        self.count = PushSum {
+            sum: 1.0,
+            weight: if is_leader { 1.0 } else { 0.0 },
+        };
+        self.average = PushSum {
+            sum: metric,
+            weight: 1.0,
+        };
+        self.min = metric;
+        self.max = metric;
+    }
+
+    /// Gives away half of the state, to be sent to a random peer.
+    pub fn share(&mut self, epoch: u64, metric: f64) -> Share {
+        self.advance(epoch, metric);
+        Share {
+            epoch: self.epoch,
+            leader: self.leader,
+            count: self.count.halve(),
+            average: self.average.halve(),
+            min: self.min,
+            max: self.max,
+        }
+    }
+
+    /// Takes in a share received from a peer.
+    pub fn receive(&mut self, share: &Share, metric: f64) {
+        self.advance(share.epoch, metric);
+        if share.epoch != self.epoch {
+            return;
+        }
+        self.leader = self.leader.min(share.leader);
+        self.count.add(share.count);
+        self.average.add(share.average);
+        self.min = self.min.min(share.min);
+        self.max = self.max.max(share.max);
+    }
+
+    /// Returns the estimates of the last finished epoch.
+    pub fn estimates(&self) -> Option<Estimates> {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64Mcg;
+
+    #[test]
+    fn test_aggregation() {
+        let mut rng = Pcg64Mcg::from_entropy();
+        let metrics = [1.0, 2.0, 3.0, 4.0, 10.0];
+        let mut aggregators: Vec<_> = (0..metrics.len())
+            .map(|i| Aggregator::new(format!("127.0.0.1:{}", 8080 + i).parse().unwrap()))
+            .collect();
+
+        for epoch in 1..=3 {
+            for _ in 0..50 {
+                for i in 0..aggregators.len() {
+                    let share = aggregators[i].share(epoch, metrics[i]);
+                    // a share sent to self is kept
+                    let j = rng.gen_range(0..aggregators.len());
+                    aggregators[j].receive(&share, metrics[j]);
+                }
+            }
+        }
+        for (i, aggregator) in aggregators.iter_mut().enumerate() {
+            aggregator.share(4, metrics[i]);
+            let estimates = aggregator.estimates().unwrap();
+            assert!(
+                (estimates.nodes.unwrap() - 5.0).abs() < 0.01,
+                "{estimates:?}"
+            );
+            assert!((estimates.average - 4.0).abs() < 0.01, "{estimates:?}");
+            assert_eq!(estimates.min, 1.0);
+            assert_eq!(estimates.max, 10.0);
+        }
+    }
+
+    #[test]
+    fn test_first_epochs_incomplete() {
+        let mut aggregator = Aggregator::new("127.0.0.1:8080".parse().unwrap());
+        aggregator.share(1, 1.0);
+        assert_eq!(aggregator.estimates(), None);
+        // joined in the middle of epoch 1
+        aggregator.share(2, 1.0);
+        assert_eq!(aggregator.estimates(), None);
+        aggregator.share(3, 1.0);
+        assert_eq!(
+            aggregator.estimates(),
+            Some(Estimates {
+                nodes: None,
+                average: 1.0,
+                min: 1.0,
+                max: 1.0
+            })
+        );
+        aggregator.share(4, 1.0);
+        assert_eq!(aggregator.estimates().unwrap().nodes, Some(1.0));
+    }
+}
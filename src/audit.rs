@@ -0,0 +1,468 @@
+use crate::{
+    log::log,
+    message::{unix_millis, Envelope},
+    utils::{deserialize_address, hex, serialize_address},
+};
+use chrono::{DateTime, SecondsFormat};
+use clap::ValueEnum;
+use core::{fmt, net::SocketAddr};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Written at the start of an audit log file.
+const AUDIT_MAGIC: &[u8] = b"P2PGAUD1";
+
+/// What happened to a message.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum AuditEvent {
+    Sent,
+    Received,
+    /// Queued for the other peers in relay-only mode.
+    Forwarded,
+    Dropped,
+}
+
+impl AuditEvent {
+    fn from_u8(n: u8) -> Option<Self> {
+        Self::value_variants().get(n as usize).copied()
+    }
+}
+
+/// A message sent, received, forwarded or dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch, by the clock of this node.
+    pub time: u64,
+    pub event: AuditEvent,
+    /// The listen address of the peer the message is sent to or received from,
+    /// the latter for forwarded messages.
+    pub peer: SocketAddr,
+    pub origin: SocketAddr,
+    pub nonce: u64,
+    /// Milliseconds since the Unix epoch the message was created at,
+    /// by the clock of `origin`.
+    pub timestamp: u64,
+    /// The hash of the message, the same on all the nodes it passes.
+    pub hash: [u8; 32],
+    /// Why the message was dropped, empty for the other events.
+    pub reason: String,
+}
+
+impl AuditRecord {
+    pub fn new(event: AuditEvent, peer: SocketAddr, envelope: &Envelope, reason: &str) -> Self {
+        Self {
+            time: unix_millis(),
+            event,
+            peer,
+            origin: envelope.origin,
+            nonce: envelope.nonce,
+            timestamp: envelope.timestamp,
+            hash: message_hash(envelope),
+            reason: reason.to_owned(),
+        }
+    }
+
+    /// Encodes the record as:
+    /// - the length of the rest of the record, `u32`
+    /// - `time`, `u64`
+    /// - `event`, `u8`, 0 for sent, 1 for received, 2 for forwarded and 3 for dropped
+    /// - `peer` and `origin`, serialized with `serialize_address`
+    /// - `nonce` and `timestamp`, `u64`
+    /// - `hash`, 32 bytes
+    /// - the length of `reason`, `u16`, and `reason`
+    ///
+    /// All integers are little-endian.
+    fn encode(&self) -> Vec<u8> {
+        let reason = &self.reason.as_bytes()[..self.reason.len().min(u16::MAX as usize)];
+        let mut record = vec![0; 4];
+        record.extend_from_slice(&self.time.to_le_bytes());
+        record.push(self.event as u8);
+        record.extend_from_slice(&serialize_address(&self.peer));
+        record.extend_from_slice(&serialize_address(&self.origin));
+        record.extend_from_slice(&self.nonce.to_le_bytes());
+        record.extend_from_slice(&self.timestamp.to_le_bytes());
+        record.extend_from_slice(&self.hash);
+        record.extend_from_slice(&(reason.len() as u16).to_le_bytes());
+        record.extend_from_slice(reason);
+        let len = (record.len() - 4) as u32;
+        record[..4].copy_from_slice(&len.to_le_bytes());
+        record
+    }
+
+    /// Decodes a record encoded with `encode` without its length.
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        let time = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        let event = AuditEvent::from_u8(take(&mut data, 1)?[0])?;
+        let peer = take_address(&mut data)?;
+        let origin = take_address(&mut data)?;
+        let nonce = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        let timestamp = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        let hash = take(&mut data, 32)?.try_into().unwrap();
+        let reason_len = u16::from_le_bytes(take(&mut data, 2)?.try_into().unwrap());
+        let reason = String::from_utf8(take(&mut data, reason_len as usize)?.to_vec()).ok()?;
+        Some(Self {
+            time,
+            event,
+            peer,
+            origin,
+            nonce,
+            timestamp,
+            hash,
+            reason,
+        })
+    }
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let event = match self.event {
+            AuditEvent::Sent => "sent",
+            AuditEvent::Received => "received",
+            AuditEvent::Forwarded => "forwarded",
+            AuditEvent::Dropped => "dropped",
+        };
+        write!(
+            f,
+            "{} {event} {}, peer {}, origin {}, nonce {}, created {}",
+            format_millis(self.time),
+            hex(&self.hash),
+            self.peer,
+            self.origin,
+            self.nonce,
+            format_millis(self.timestamp),
+        )?;
+        if !self.reason.is_empty() {
+            write!(f, ", reason: {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits the first `len` bytes off `data`.
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Some(head)
+}
+
+/// Splits an address serialized with `serialize_address` off `data`.
+fn take_address(data: &mut &[u8]) -> Option<SocketAddr> {
+    let addr = deserialize_address(data).ok()?;
+    take(data, serialize_address(&addr).len())?;
+    Some(addr)
+}
+
+/// The SHA-256 hash of the serialized `envelope`.
+pub fn message_hash(envelope: &Envelope) -> [u8; 32] {
+    Sha256::digest(bincode::serialize(envelope).unwrap()).into()
+}
+
+fn format_millis(millis: u64) -> String {
+    DateTime::from_timestamp_millis(millis as i64).map_or_else(
+        || millis.to_string(),
+        |time| time.to_rfc3339_opts(SecondsFormat::Millis, true),
+    )
+}
+
+/// Parses an RFC 3339 time, e.g. `2024-05-01T12:00:00Z`,
+/// into milliseconds since the Unix epoch.
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.timestamp_millis().max(0) as u64)
+        .map_err(|e| e.to_string())
+}
+
+/// Decodes the records of an audit log file, up to a truncated one
+/// left by a node stopped while writing it. Also returns the length
+/// of the complete records, with the magic.
+fn decode_records(data: &[u8]) -> io::Result<(Vec<AuditRecord>, usize)> {
+    let Some(mut rest) = data.strip_prefix(AUDIT_MAGIC) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an audit log file",
+        ));
+    };
+    let mut records = Vec::new();
+    loop {
+        let len = data.len() - rest.len();
+        let record = take(&mut rest, 4)
+            .and_then(|record_len| {
+                take(
+                    &mut rest,
+                    u32::from_le_bytes(record_len.try_into().unwrap()) as usize,
+                )
+            })
+            .and_then(AuditRecord::decode);
+        match record {
+            Some(record) => records.push(record),
+            None => return Ok((records, len)),
+        }
+    }
+}
+
+/// The path of the `n`th newest rotated file of the audit log at `path`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+struct AuditFile {
+    file: File,
+    size: u64,
+}
+
+/// Appends the messages sent, received, forwarded and dropped to the file
+/// of `--audit-log`. Once it would grow over `max_size`, it is renamed
+/// to `PATH.1`, the older rotated files to `PATH.2` and so on up to
+/// `PATH.<files>`, dropping the oldest, and a new one is started.
+///
+/// A file starts with `AUDIT_MAGIC`, followed by records encoded with
+/// `AuditRecord::encode`.
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    /// The number of rotated files kept.
+    files: usize,
+    file: Mutex<AuditFile>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, appending to the file there is.
+    pub fn open(path: &Path, max_size: u64, files: usize) -> io::Result<Self> {
+        let file = match fs::read(path) {
+            Ok(data) if data.is_empty() => create(path)?,
+            Ok(data) => {
+                let (_, len) = decode_records(&data)?;
+                let file = OpenOptions::new().append(true).open(path)?;
+                // drops a truncated record, which the next ones would be misread after
+                if len < data.len() {
+                    file.set_len(len as u64)?;
+                }
+                AuditFile {
+                    file,
+                    size: len as u64,
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => create(path)?,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            max_size,
+            files,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, record: &AuditRecord) {
+        let record = record.encode();
+        let mut file = self.file.lock().unwrap();
+        let res = (|| {
+            if file.size + record.len() as u64 > self.max_size
+                && file.size > AUDIT_MAGIC.len() as u64
+            {
+                self.rotate()?;
+                *file = create(&self.path)?;
+            }
+            file.file.write_all(&record)?;
+            file.size += record.len() as u64;
+            Ok::<_, io::Error>(())
+        })();
+        if let Err(e) = res {
+            log(&[
+                b"Failed to write to the audit log, error: ",
+                e.to_string().as_bytes(),
+            ]);
+        }
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..self.files).rev() {
+            match fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+fn create(path: &Path) -> io::Result<AuditFile> {
+    let mut file = File::create(path)?;
+    file.write_all(AUDIT_MAGIC)?;
+    Ok(AuditFile {
+        file,
+        size: AUDIT_MAGIC.len() as u64,
+    })
+}
+
+/// The conditions of `p2p-gossip audit query`, all of which records have to meet.
+#[derive(Default)]
+pub struct AuditQuery {
+    pub peer: Option<SocketAddr>,
+    /// Milliseconds since the Unix epoch.
+    pub since: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    pub until: Option<u64>,
+    /// A prefix of the hexadecimal hash of the message.
+    pub hash: Option<String>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.peer.is_none_or(|peer| peer == record.peer)
+            && self.since.is_none_or(|since| record.time >= since)
+            && self.until.is_none_or(|until| record.time <= until)
+            && self
+                .hash
+                .as_ref()
+                .is_none_or(|hash| hex(&record.hash).starts_with(&hash.to_ascii_lowercase()))
+    }
+}
+
+/// Reads the records matching `query` from the audit log at `path`
+/// and its rotated files, oldest first.
+pub fn query(path: &Path, query: &AuditQuery) -> io::Result<Vec<AuditRecord>> {
+    let mut paths: Vec<_> = (1..)
+        .map(|n| rotated_path(path, n))
+        .take_while(|path| path.exists())
+        .collect();
+    paths.reverse();
+    paths.push(path.to_owned());
+    let mut records = Vec::new();
+    for path in paths {
+        let (file_records, _) = decode_records(&fs::read(path)?)?;
+        records.extend(
+            file_records
+                .into_iter()
+                .filter(|record| query.matches(record)),
+        );
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(event: AuditEvent, nonce: u64, reason: &str) -> AuditRecord {
+        let envelope = Envelope {
+            origin: "[fe80::1%2]:9000".parse().unwrap(),
+            nonce,
+            seq: nonce,
+            timestamp: 1_700_000_000_000,
+            expires: None,
+            body: "hello".to_owned(),
+        };
+        let mut record =
+            AuditRecord::new(event, "127.0.0.1:9001".parse().unwrap(), &envelope, reason);
+        record.time = 1_700_000_000_000 + nonce;
+        record
+    }
+
+    #[test]
+    fn test_encode_record() {
+        let record = record(AuditEvent::Dropped, 1, "replayed");
+        let encoded = record.encode();
+        assert_eq!(encoded.len(), 4 + 8 + 1 + 10 + 26 + 8 + 8 + 32 + 2 + 8);
+        assert_eq!(AuditRecord::decode(&encoded[4..]), Some(record.clone()));
+        assert_eq!(
+            record.to_string(),
+            format!(
+                "2023-11-14T22:13:20.001Z dropped {}, peer 127.0.0.1:9001, \
+                 origin [fe80::1%2]:9000, nonce 1, created 2023-11-14T22:13:20.000Z, \
+                 reason: replayed",
+                hex(&record.hash)
+            )
+        );
+
+        let mut data = AUDIT_MAGIC.to_vec();
+        data.extend_from_slice(&encoded);
+        data.extend_from_slice(&encoded[..20]);
+        assert_eq!(
+            decode_records(&data).unwrap(),
+            (vec![record], AUDIT_MAGIC.len() + encoded.len())
+        );
+        assert!(decode_records(b"P2PGCAP1").is_err());
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let dir = std::env::temp_dir().join(format!("p2p-gossip-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let record_len = record(AuditEvent::Sent, 0, "").encode().len() as u64;
+        // two records per file
+        let max_size = AUDIT_MAGIC.len() as u64 + 2 * record_len;
+        let log = AuditLog::open(&path, max_size, 1).unwrap();
+        for nonce in 0..5 {
+            log.record(&record(AuditEvent::Sent, nonce, ""));
+        }
+        drop(log);
+        // the oldest file is dropped
+        assert!(rotated_path(&path, 1).exists());
+        assert!(!rotated_path(&path, 2).exists());
+        let nonces = |query| {
+            super::query(&path, &query)
+                .unwrap()
+                .iter()
+                .map(|record| record.nonce)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(nonces(AuditQuery::default()), [2, 3, 4]);
+
+        // appended to after a truncated record
+        let mut data = fs::read(&path).unwrap();
+        data.extend_from_slice(&[1, 2, 3]);
+        fs::write(&path, data).unwrap();
+        let log = AuditLog::open(&path, max_size, 1).unwrap();
+        log.record(&record(AuditEvent::Received, 5, ""));
+        drop(log);
+        assert_eq!(nonces(AuditQuery::default()), [2, 3, 4, 5]);
+
+        assert_eq!(
+            nonces(AuditQuery {
+                since: Some(1_700_000_000_003),
+                until: Some(1_700_000_000_004),
+                ..Default::default()
+            }),
+            [3, 4]
+        );
+        assert!(nonces(AuditQuery {
+            peer: Some("127.0.0.1:9002".parse().unwrap()),
+            ..Default::default()
+        })
+        .is_empty());
+        let hash = hex(&record(AuditEvent::Sent, 4, "").hash);
+        assert_eq!(
+            nonces(AuditQuery {
+                hash: Some(hash[..8].to_ascii_uppercase()),
+                ..Default::default()
+            }),
+            [4]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2023-11-14T22:13:20.001Z"),
+            Ok(1_700_000_000_001)
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+}
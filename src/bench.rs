@@ -0,0 +1,201 @@
+use crate::{
+    memory_budget::{Charged, MemoryBudget},
+    message::{Envelope, Message},
+    peer_list::{deserialize_peer_list, serialize_peer_list},
+    transfer, MESSAGE_CHANNEL_CAPACITY,
+};
+use core::{
+    fmt,
+    hint::black_box,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use std::{sync::Arc, time::Instant};
+use tokio::{sync::broadcast, task::JoinSet};
+
+/// The number of peers in the benchmarked peer lists, as many as a node
+/// with a hundred connections sends.
+const PEER_LIST_LEN: usize = 100;
+
+/// The number of alternate addresses of each peer in the benchmarked peer lists.
+const ALTERNATES_PER_PEER: usize = 2;
+
+/// The outcome of one benchmark.
+pub struct BenchResult {
+    pub name: String,
+    /// The number of times the benchmarked operation was performed.
+    pub ops: usize,
+    /// The number of bytes encoded, decoded or sent in total.
+    pub bytes: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    fn per_sec(&self, n: usize) -> f64 {
+        n as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// The results of the benchmarks, formatted as a table.
+pub struct Summary(pub Vec<BenchResult>);
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.0.iter().map(|res| res.name.len()).max().unwrap_or(0);
+        writeln!(
+            f,
+            "{:width$}  {:>10}  {:>12}  {:>10}",
+            "benchmark", "ops", "ops/s", "MB/s"
+        )?;
+        for res in &self.0 {
+            writeln!(
+                f,
+                "{:width$}  {:>10}  {:>12.0}  {:>10.2}",
+                res.name,
+                res.ops,
+                res.per_sec(res.ops),
+                res.per_sec(res.bytes) / 1e6,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the benchmarks of the message and peer-list codecs `iterations` times
+/// each with messages of `body_size` bytes, and of the fan-out of as many
+/// messages to `peers` simulated peers. The message codec is measured
+/// without the framing of transfers, which the fan-out includes.
+pub async fn run(iterations: usize, body_size: usize, peers: usize) -> Summary {
+    let envelope = Arc::new(envelope(body_size));
+    let message = Message::Gossip(envelope.clone());
+    let encoded = bincode::serialize(&message).unwrap();
+    let peer_list = peer_list();
+    let serialized_peer_list =
+        serialize_peer_list(peer_list.iter().map(|(peer, alts)| (*peer, &alts[..])));
+    Summary(vec![
+        measure("message encode", iterations, encoded.len(), || {
+            black_box(bincode::serialize(black_box(&message)).unwrap());
+        }),
+        measure("message decode", iterations, encoded.len(), || {
+            black_box(bincode::deserialize::<Message>(black_box(&encoded)).unwrap());
+        }),
+        measure(
+            "peer list encode",
+            iterations,
+            serialized_peer_list.len(),
+            || {
+                black_box(serialize_peer_list(
+                    black_box(&peer_list)
+                        .iter()
+                        .map(|(peer, alts)| (*peer, &alts[..])),
+                ));
+            },
+        ),
+        measure(
+            "peer list decode",
+            iterations,
+            serialized_peer_list.len(),
+            || {
+                black_box(deserialize_peer_list(black_box(&serialized_peer_list)));
+            },
+        ),
+        fan_out(envelope, iterations, peers).await,
+    ])
+}
+
+fn measure(name: &str, ops: usize, bytes_per_op: usize, mut f: impl FnMut()) -> BenchResult {
+    let start = Instant::now();
+    for _ in 0..ops {
+        f();
+    }
+    BenchResult {
+        name: name.to_owned(),
+        ops,
+        bytes: ops * bytes_per_op,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Broadcasts `envelope` `messages` times to `peers` tasks, which encode it
+/// as the senders to peers do, short of writing it to a connection.
+/// The bytes are the ones encoded by all the peers.
+async fn fan_out(envelope: Arc<Envelope>, messages: usize, peers: usize) -> BenchResult {
+    // the slowest peer holds the producer back rather than missing messages,
+    // as with the memory budget of a node
+    let budget = MemoryBudget::new(MESSAGE_CHANNEL_CAPACITY * envelope.body.len().max(1));
+    let (sender, _) = broadcast::channel(MESSAGE_CHANNEL_CAPACITY);
+    let mut receivers = JoinSet::new();
+    for _ in 0..peers {
+        let mut receiver = sender.subscribe();
+        receivers.spawn(async move {
+            let mut bytes = 0;
+            while let Ok(Charged {
+                value: envelope, ..
+            }) = receiver.recv().await
+            {
+                let message = Message::Gossip(envelope);
+                bytes += transfer::frame(bincode::serialize(&message).unwrap()).len();
+            }
+            bytes
+        });
+    }
+
+    let start = Instant::now();
+    for _ in 0..messages {
+        budget.wait().await;
+        let charge = budget.charge(envelope.body.len().max(1));
+        let _ = sender.send(Charged::new(envelope.clone(), charge));
+    }
+    drop(sender);
+    let mut bytes = 0;
+    while let Some(res) = receivers.join_next().await {
+        bytes += res.unwrap();
+    }
+    BenchResult {
+        name: format!("fan-out to {peers} peers"),
+        ops: messages,
+        bytes,
+        elapsed: start.elapsed(),
+    }
+}
+
+fn envelope(body_size: usize) -> Envelope {
+    Envelope {
+        origin: SocketAddr::from((Ipv4Addr::LOCALHOST, 9000)),
+        nonce: 1,
+        seq: 1,
+        timestamp: 0,
+        expires: None,
+        body: "x".repeat(body_size),
+    }
+}
+
+fn peer_list() -> Vec<(SocketAddr, Vec<SocketAddr>)> {
+    (0..PEER_LIST_LEN as u16)
+        .map(|i| {
+            let addr = |port| SocketAddr::from((Ipv4Addr::new(10, 0, (i >> 8) as _, i as _), port));
+            (
+                addr(9000),
+                (1..=ALTERNATES_PER_PEER as u16)
+                    .map(|j| addr(9000 + j))
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let envelope = Arc::new(envelope(10));
+        let encoded_len =
+            transfer::frame(bincode::serialize(&Message::Gossip(envelope.clone())).unwrap()).len();
+        let res = fan_out(envelope, 100, 3).await;
+        assert_eq!(res.ops, 100);
+        // no peer misses a message
+        assert_eq!(res.bytes, 3 * 100 * encoded_len);
+    }
+}
@@ -0,0 +1,163 @@
+use core::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A blocked peer, either by its listen address or by its IP on all ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Blocked {
+    Ip(IpAddr),
+    Addr(SocketAddr),
+}
+
+impl Blocked {
+    fn matches(self, addr: SocketAddr) -> bool {
+        match self {
+            Self::Ip(ip) => ip == addr.ip(),
+            Self::Addr(blocked) => blocked == addr,
+        }
+    }
+}
+
+impl FromStr for Blocked {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Self::Addr(addr));
+        }
+        s.parse()
+            .map(Self::Ip)
+            .map_err(|_| format!("expected an IP or an `IP:port` address, got `{s}`"))
+    }
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "{ip}"),
+            Self::Addr(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+/// Peers neither accepted nor dialed, kept in the file on `path`
+/// one per line, if there is one.
+#[derive(Default)]
+pub struct Blocklist {
+    blocked: BTreeSet<Blocked>,
+    path: Option<PathBuf>,
+}
+
+impl Blocklist {
+    /// Reads the blocklist from `path`, which is created on the first change
+    /// if it does not exist.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let blocked = match fs::read_to_string(&path) {
+            Ok(contents) => parse_blocklist(&contents).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            blocked,
+            path: Some(path),
+        })
+    }
+
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        self.blocked.iter().any(|blocked| blocked.matches(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Blocked> {
+        self.blocked.iter()
+    }
+
+    /// Blocks `blocked`, returning whether it was not blocked already.
+    pub fn insert(&mut self, blocked: Blocked) -> io::Result<bool> {
+        let inserted = self.blocked.insert(blocked);
+        if inserted {
+            self.save()?;
+        }
+        Ok(inserted)
+    }
+
+    /// Unblocks `blocked`, returning whether it was blocked.
+    pub fn remove(&mut self, blocked: Blocked) -> io::Result<bool> {
+        let removed = self.blocked.remove(&blocked);
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Writes the blocklist to a temporary file renamed over the file on `path`,
+    /// so that it is never left half-written.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut contents = String::new();
+        for blocked in &self.blocked {
+            contents.push_str(&blocked.to_string());
+            contents.push('\n');
+        }
+        let tmp = temporary_path(path);
+        fs::write(&tmp, contents)?;
+        fs::rename(tmp, path)
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Parses a blocklist of one entry per line, skipping empty lines.
+fn parse_blocklist(contents: &str) -> Result<BTreeSet<Blocked>, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist() {
+        let path = std::env::temp_dir().join(format!("blocklist-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut blocklist = Blocklist::load(path.clone()).unwrap();
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        assert!(!blocklist.contains(addr));
+
+        assert!(blocklist.insert("127.0.0.1:9000".parse().unwrap()).unwrap());
+        assert!(!blocklist.insert("127.0.0.1:9000".parse().unwrap()).unwrap());
+        assert!(blocklist.insert("::1".parse().unwrap()).unwrap());
+        assert!(blocklist.contains(addr));
+        assert!(!blocklist.contains("127.0.0.1:9001".parse().unwrap()));
+        assert!(blocklist.contains("[::1]:9001".parse().unwrap()));
+
+        let loaded = Blocklist::load(path.clone()).unwrap();
+        assert_eq!(
+            loaded.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["::1", "127.0.0.1:9000"]
+        );
+        assert!(blocklist.remove("::1".parse().unwrap()).unwrap());
+        assert!(!blocklist.remove("::1".parse().unwrap()).unwrap());
+        assert_eq!(Blocklist::load(path.clone()).unwrap().iter().count(), 1);
+        fs::remove_file(path).unwrap();
+
+        assert!("host:9000".parse::<Blocked>().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+/// The number of the latest samples `ClockEstimate` picks from.
+const WINDOW: usize = 8;
+
+/// A single ping exchange, all times in milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockSample {
+    /// The offset of the remote clock relative to the local one.
+    pub offset: i64,
+    pub rtt: u64,
+}
+
+impl ClockSample {
+    /// Computes a sample NTP-style from a ping sent at local time `t0`,
+    /// received at remote time `t1`, answered at remote time `t2`
+    /// and the answer received at local time `t3`.
+    pub fn new(t0: u64, t1: u64, t2: u64, t3: u64) -> Self {
+        let (t0, t1, t2, t3) = (t0 as i64, t1 as i64, t2 as i64, t3 as i64);
+        Self {
+            offset: ((t1 - t0) + (t2 - t3)) / 2,
+            rtt: ((t3 - t0) - (t2 - t1)).max(0) as _,
+        }
+    }
+}
+
+/// An estimate of a peer's clock offset.
+///
+/// Like the NTP clock filter, uses the latest sample with the lowest
+/// round-trip time, since it is the least affected by queueing delays.
+#[derive(Clone, Debug, Default)]
+pub struct ClockEstimate {
+    samples: Vec<ClockSample>,
+}
+
+impl ClockEstimate {
+    pub fn update(&mut self, sample: ClockSample) {
+        if self.samples.len() == WINDOW {
+            self.samples.remove(0);
+        }
+        self.samples.push(sample);
+    }
+
+    /// Returns the best sample, if any.
+    pub fn best(&self) -> Option<ClockSample> {
+        self.samples
+            .iter()
+            .rev()
+            .min_by_key(|sample| sample.rtt)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_sample() {
+        // the remote clock is 1000 ms ahead, 10 ms each way, 5 ms to answer
+        assert_eq!(
+            ClockSample::new(0, 1010, 1015, 25),
+            ClockSample {
+                offset: 1000,
+                rtt: 20
+            }
+        );
+        // the remote clock is 500 ms behind
+        assert_eq!(
+            ClockSample::new(1000, 510, 510, 1020),
+            ClockSample {
+                offset: -500,
+                rtt: 20
+            }
+        );
+    }
+
+    #[test]
+    fn test_clock_estimate() {
+        let mut estimate = ClockEstimate::default();
+        assert_eq!(estimate.best(), None);
+        estimate.update(ClockSample {
+            offset: 10,
+            rtt: 50,
+        });
+        estimate.update(ClockSample {
+            offset: 20,
+            rtt: 30,
+        });
+        estimate.update(ClockSample {
+            offset: 30,
+            rtt: 40,
+        });
+        assert_eq!(estimate.best().unwrap().offset, 20);
+        for _ in 0..WINDOW {
+            estimate.update(ClockSample {
+                offset: 40,
+                rtt: 60,
+            });
+        }
+        assert_eq!(estimate.best().unwrap().offset, 40);
+    }
+}
@@ -0,0 +1,619 @@
+use crate::{
+    address_rules::{advertised_addr, check_address, InvalidAddress},
+    address_validation::Handshake,
+    config::cert_is_valid_for,
+    error::{
+        is_already_open_or_locally_closed_error, is_already_open_or_locally_closed_reason,
+        AppError, AppResult, CloseReason, Operation, WithContext,
+    },
+    log::{debug_event, json_event, log, log_event},
+    mailbox::Mailbox,
+    message::{unix_millis, Message},
+    observer::Direction,
+    peer_list::{deserialize_peer_list, serialize_peer_list, Hello, MAX_HELLO_LEN},
+    peering::reconnect,
+    peers::PeerState,
+    receiver::{receiver_loop, request_history},
+    sender::{flush_mailbox, mailbox_loop, observe, send_message, send_snapshot, sender_loop},
+    stats::Counter,
+    utils::NotifyOnDrop,
+    Context,
+};
+use core::net::SocketAddr;
+use dns_lookup::lookup_addr;
+use futures::{future::BoxFuture, FutureExt};
+use quinn::{Connecting, Connection, ConnectionError, Endpoint};
+use rustls::Certificate;
+use std::{io, sync::Arc};
+use tokio::time::Instant;
+
+/// Continuesly accepts incoming connections on `endpoint`
+/// and spawns `handle_incoming_connection` on them,
+/// no faster than `ctx.accept_budget` allows.
+/// Fails once the endpoint stops accepting connections.
+pub async fn accept_loop(ctx: Context, endpoint: Endpoint) -> io::Result<()> {
+    while let Some(connecting) = endpoint.accept().await {
+        let handshake = ctx.address_validation.track();
+        if let Some(budget) = &ctx.accept_budget {
+            // the connections over the budget wait in the endpoint meanwhile
+            let delay = budget.lock().await.reserve(Instant::now());
+            tokio::time::sleep(delay).await;
+        }
+        ctx.supervisor.spawn(
+            "incoming_connection",
+            handle_incoming_connection(ctx.clone(), connecting, handshake),
+        );
+    }
+    // the endpoint is closed on shutdown only after the tasks are cancelled
+    Err(io::Error::other(
+        "the endpoint stopped accepting connections",
+    ))
+}
+
+/// Accepts an incoming `connection_in_progress`.
+///
+/// Sends the list of peers to the remote address
+/// and spawns `handle_connection`. Logs errors on failure.
+async fn handle_incoming_connection(
+    ctx: Context,
+    connection_in_progress: Connecting,
+    handshake: Handshake,
+) {
+    let remote_addr = connection_in_progress.remote_address();
+    match accept_connection(&ctx, connection_in_progress, handshake).await {
+        Ok(Some((connection, listen_addr, false))) => {
+            log_event(
+                "connect",
+                listen_addr,
+                &[
+                    b"Accepted a connection from ",
+                    listen_addr.to_string().as_bytes(),
+                ],
+            );
+            handle_connection(ctx, connection, listen_addr).await;
+        }
+        Ok(Some((connection, listen_addr, true))) => {
+            log_event(
+                "connect",
+                listen_addr,
+                &[
+                    b"Accepted a receive-only connection from ",
+                    listen_addr.to_string().as_bytes(),
+                ],
+            );
+            let disconnect_reason = handle_connection_inner(&ctx, &connection, listen_addr).await;
+            if !is_already_open_or_locally_closed_reason(&disconnect_reason) {
+                log_event(
+                    "disconnect",
+                    listen_addr,
+                    &[
+                        b"Closed connection to ",
+                        listen_addr.to_string().as_bytes(),
+                        b", reason: ",
+                        disconnect_reason.to_string().as_bytes(),
+                    ],
+                );
+            }
+        }
+        Err(e) if !is_already_open_or_locally_closed_error(&e) => log_event(
+            "accept_failed",
+            remote_addr,
+            &[
+                b"Failed to accept a connection from ",
+                remote_addr.to_string().as_bytes(),
+                b", error: ",
+                e.to_string().as_bytes(),
+            ],
+        ),
+        Err(_) | Ok(None) => {}
+    }
+}
+
+/// Accepts an incoming `connection_in_progress`.
+///
+/// Reads the listen address advertised by the remote peer
+/// and sends it the list of peers. Receive-only peers,
+/// for which `true` is returned, are not added to the list.
+async fn accept_connection(
+    ctx: &Context,
+    connection_in_progress: Connecting,
+    handshake: Handshake,
+) -> AppResult<Option<(Connection, SocketAddr, bool)>> {
+    let remote_addr = connection_in_progress.remote_address();
+    let connection = connection_in_progress
+        .await
+        .with_context(remote_addr, Operation::Handshake)?;
+    drop(handshake);
+
+    let data = async {
+        let mut recv = connection.accept_uni().await?;
+        let data = recv.read_to_end(MAX_HELLO_LEN).await?;
+        observe(
+            ctx,
+            Direction::Received,
+            &connection,
+            recv.id(),
+            "Hello",
+            &data,
+        );
+        Ok::<_, AppError>(data)
+    }
+    .await
+    .with_context(remote_addr, Operation::Handshake)?;
+    let Hello {
+        listen_addr,
+        receive_only,
+        alternates,
+    } = Hello::deserialize(&data)
+        .inspect_err(|_| CloseReason::ProtocolError.close(&connection))
+        .with_context(remote_addr, Operation::Handshake)?;
+    // otherwise the peers it is gossiped to could not dial it
+    let listen_addr = advertised_addr(listen_addr, remote_addr);
+    if let Err(e) = check_address(listen_addr, ctx.local_addr())
+        .and_then(|()| check_address(listen_addr, ctx.bind_addr()))
+    {
+        CloseReason::ProtocolError.close(&connection);
+        return Err(AppError::InvalidListenAddress(e))
+            .with_context(remote_addr, Operation::Handshake);
+    }
+    if ctx.is_blocked(listen_addr).await || ctx.is_blocked(remote_addr).await {
+        CloseReason::Banned.close(&connection);
+        return Err(AppError::Blocked).with_context(listen_addr, Operation::Handshake);
+    }
+    if ctx.verify_peer_identity && !peer_identity_matches(&connection, listen_addr) {
+        CloseReason::IdentityMismatch.close(&connection);
+        return Err(AppError::IdentityMismatch).with_context(listen_addr, Operation::Handshake);
+    }
+
+    if !receive_only
+        && Some(PeerState::Connected) == ctx.peers.insert(listen_addr, PeerState::Connected)
+    {
+        CloseReason::AlreadyConnected.close(&connection);
+        return Ok(None);
+    }
+    if !receive_only {
+        ctx.peers
+            .set_alternates(listen_addr, valid_alternates(ctx, listen_addr, alternates));
+    }
+
+    async {
+        let mut send = connection.open_uni().await?;
+        let peers: Vec<_> = ctx
+            .peers
+            .snapshot()
+            .keys()
+            .filter(|&&peer| ctx.advertise_private.allows(peer, listen_addr))
+            .map(|&peer| {
+                let alternates: Vec<_> = ctx
+                    .peers
+                    .alternates(peer)
+                    .into_iter()
+                    .filter(|&alternate| ctx.advertise_private.allows(alternate, listen_addr))
+                    .collect();
+                (peer, alternates)
+            })
+            .collect();
+        let peer_list = serialize_peer_list(
+            peers
+                .iter()
+                .map(|(peer, alternates)| (*peer, &alternates[..])),
+        );
+        observe(
+            ctx,
+            Direction::Sent,
+            &connection,
+            send.id(),
+            "Peers",
+            &peer_list,
+        );
+        send.write_all(&peer_list).await?;
+        send.finish().await?;
+        Ok::<_, AppError>(())
+    }
+    .await
+    .with_context(listen_addr, Operation::PeerListSend)?;
+
+    Ok(Some((connection, listen_addr, receive_only)))
+}
+
+/// Whether the certificate presented by the peer of `connection`
+/// is issued for the IP of `listen_addr` or for the hostname it resolves to.
+fn peer_identity_matches(connection: &Connection, listen_addr: SocketAddr) -> bool {
+    let Some(certs) = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+    else {
+        return false;
+    };
+    let Some(cert) = certs.first() else {
+        return false;
+    };
+    cert_is_valid_for(cert, &listen_addr.ip().to_string())
+        || lookup_addr(&listen_addr.ip()).is_ok_and(|name| cert_is_valid_for(cert, &name))
+}
+
+/// Connects to a node with address `remote_addr`. Logs errors on failure.
+pub async fn outgoing_connect(
+    ctx: Context,
+    remote_addr: SocketAddr,
+    notify_on_drop: Arc<NotifyOnDrop<()>>,
+) -> AppResult<Connection> {
+    let local_addr = ctx.local_addr();
+    let res = outgoing_connect_inner(ctx.clone(), remote_addr, notify_on_drop.clone()).await;
+
+    match res.as_ref() {
+        Err(e)
+            if !is_already_open_or_locally_closed_error(e)
+                && !matches!(e.root(), AppError::Blocked) =>
+        {
+            ctx.stats
+                .increment(if matches!(e.root(), AppError::Timeout(_)) {
+                    Counter::DialTimeouts
+                } else {
+                    Counter::DialFailures
+                });
+            log_event(
+                "connect_failed",
+                remote_addr,
+                &[
+                    b"Failed to connect to ",
+                    remote_addr.to_string().as_bytes(),
+                    b", error: ",
+                    e.to_string().as_bytes(),
+                ],
+            );
+        }
+        Err(_) => {}
+        Ok(connection) => {
+            if Some(PeerState::Connected) == ctx.peers.insert(remote_addr, PeerState::Connected)
+                // a hack to avoid both ends closing the connection
+                && local_addr < remote_addr
+            {
+                CloseReason::AlreadyConnected.close(connection);
+            } else {
+                json_event(
+                    "connect",
+                    remote_addr,
+                    &[b"Connected to ", remote_addr.to_string().as_bytes()],
+                );
+            }
+        }
+    }
+
+    res
+}
+
+/// Connects to a node with address `remote_addr`.
+///
+/// Advertises the local listen address, so that the remote peer
+/// does not have to rely on the source address of the connection.
+fn outgoing_connect_inner(
+    ctx: Context,
+    remote_addr: SocketAddr,
+    failed_peers: Arc<NotifyOnDrop<()>>,
+) -> BoxFuture<'static, AppResult<Connection>> {
+    async move {
+        if ctx.is_blocked(remote_addr).await {
+            return Err(AppError::Blocked).with_context(remote_addr, Operation::Dial);
+        }
+        let connection = dial(&ctx, remote_addr).await?;
+        async {
+            let mut send = connection.open_uni().await?;
+            let hello = Hello {
+                listen_addr: ctx.local_addr(),
+                receive_only: ctx.receive_only,
+                alternates: ctx.alternates.to_vec(),
+            }
+            .serialize();
+            observe(
+                &ctx,
+                Direction::Sent,
+                &connection,
+                send.id(),
+                "Hello",
+                &hello,
+            );
+            send.write_all(&hello).await?;
+            send.finish().await?;
+            Ok::<_, AppError>(())
+        }
+        .await
+        .with_context(remote_addr, Operation::Handshake)?;
+        let data = async {
+            let mut recv = connection.accept_uni().await?;
+            let data = recv.read_to_end(10_000).await?;
+            observe(
+                &ctx,
+                Direction::Received,
+                &connection,
+                recv.id(),
+                "Peers",
+                &data,
+            );
+            Ok::<_, AppError>(data)
+        }
+        .await
+        .with_context(remote_addr, Operation::PeerListRead)?;
+        let mut quarantine = ctx.quarantine.lock().await;
+        let mut gossip_scores = ctx.gossip_scores.lock().await;
+
+        for (peer, alternates) in deserialize_peer_list(&data) {
+            if gossip_scores.is_ignored(remote_addr) {
+                break;
+            }
+            match check_address(peer, ctx.local_addr())
+                .and_then(|()| check_address(peer, ctx.bind_addr()))
+            {
+                Ok(()) => {}
+                // peer lists include the peer they are sent to
+                Err(InvalidAddress::Own) => continue,
+                Err(e) => {
+                    debug_event(
+                        "invalid_address",
+                        remote_addr,
+                        &[
+                            b"Skipped ",
+                            peer.to_string().as_bytes(),
+                            b" gossiped by ",
+                            remote_addr.to_string().as_bytes(),
+                            b": ",
+                            e.to_string().as_bytes(),
+                        ],
+                    );
+                    if gossip_scores.record_invalid(remote_addr) {
+                        log(&[
+                            b"Ignoring the peer lists of ",
+                            remote_addr.to_string().as_bytes(),
+                            b", which gossiped too many invalid addresses",
+                        ]);
+                    }
+                    continue;
+                }
+            }
+            if !ctx.advertise_private.allows(peer, remote_addr) {
+                continue;
+            }
+            if !alternates.is_empty() {
+                ctx.peers
+                    .set_alternates(peer, valid_alternates(&ctx, remote_addr, alternates));
+            }
+            if ctx.peers.insert_new(peer) && !quarantine.is_quarantined(peer, Instant::now()) {
+                ctx.supervisor.spawn(
+                    "outgoing_connect",
+                    outgoing_connect(ctx.clone(), peer, failed_peers.clone()),
+                );
+            }
+        }
+        drop(quarantine);
+        drop(gossip_scores);
+        ctx.supervisor.spawn(
+            "connection",
+            handle_connection(ctx.clone(), connection.clone(), remote_addr),
+        );
+        Ok(connection)
+    }
+    .boxed()
+}
+
+/// Dials the peer on `remote_addr` on each of the addresses it advertised in turn,
+/// starting with the one it was last reached on, and records the one that worked.
+async fn dial(ctx: &Context, remote_addr: SocketAddr) -> AppResult<Connection> {
+    let mut last_error = None;
+    for addr in ctx.peers.dial_order(remote_addr) {
+        let res = async {
+            let name = lookup_addr(&addr.ip())?;
+            let connecting = ctx.dialer(addr).connect(addr, &name)?;
+            Ok::<_, AppError>(tokio::time::timeout(ctx.dial_timeout, connecting).await??)
+        }
+        .await;
+        match res {
+            Ok(connection) => {
+                ctx.peers.record_reached(remote_addr, addr);
+                return Ok(connection);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    // the dial order always includes `remote_addr`
+    Err(last_error.unwrap()).with_context(remote_addr, Operation::Dial)
+}
+
+/// The alternate addresses advertised by or for `peer` which may be dialed,
+/// as gossiped by `sender`.
+fn valid_alternates(
+    ctx: &Context,
+    sender: SocketAddr,
+    alternates: Vec<SocketAddr>,
+) -> Vec<SocketAddr> {
+    alternates
+        .into_iter()
+        .filter(|&alternate| {
+            check_address(alternate, ctx.local_addr())
+                .and_then(|()| check_address(alternate, ctx.bind_addr()))
+                .is_ok()
+                && ctx.advertise_private.allows(alternate, sender)
+        })
+        .collect()
+}
+
+/// Handles communication via `connection` with the peer listening
+/// on `remote_addr`. Logs errors on disconnection.
+async fn handle_connection(ctx: Context, connection: Connection, remote_addr: SocketAddr) {
+    let replaced = ctx
+        .connections
+        .lock()
+        .await
+        .insert(remote_addr, connection.clone());
+    if replaced.is_none() {
+        let latency = ctx
+            .sessions
+            .lock()
+            .await
+            .connected(remote_addr, Instant::now());
+        if let Some(latency) = latency {
+            ctx.stats.reconnect_latency(remote_addr, latency);
+        }
+        for handler in &*ctx.handlers {
+            handler.on_peer_joined(remote_addr);
+        }
+    }
+    let disconnect_reason = handle_connection_inner(&ctx, &connection, remote_addr).await;
+    let blocked = ctx.is_blocked(remote_addr).await;
+    let removed = {
+        let mut connections = ctx.connections.lock().await;
+        connections.get(&remote_addr).map(Connection::stable_id) == Some(connection.stable_id())
+            && connections.remove(&remote_addr).is_some()
+    };
+    if removed {
+        ctx.sessions.lock().await.disconnected(
+            remote_addr,
+            disconnect_reason.to_string(),
+            Instant::now(),
+        );
+        for handler in &*ctx.handlers {
+            handler.on_peer_left(remote_addr);
+        }
+    }
+
+    ctx.upload.remove(connection.stable_id());
+    ctx.download.remove(connection.stable_id());
+    drop(connection);
+    if !is_already_open_or_locally_closed_reason(&disconnect_reason) {
+        log_event(
+            "disconnect",
+            remote_addr,
+            &[
+                b"Closed connection to ",
+                remote_addr.to_string().as_bytes(),
+                b", reason: ",
+                disconnect_reason.to_string().as_bytes(),
+            ],
+        );
+        let quarantined = !ctx.is_pinned(remote_addr)
+            && ctx
+                .quarantine
+                .lock()
+                .await
+                .record_disconnect(remote_addr, Instant::now());
+        if quarantined {
+            log_event(
+                "quarantined",
+                remote_addr,
+                &[
+                    b"Quarantined ",
+                    remote_addr.to_string().as_bytes(),
+                    b" for flapping",
+                ],
+            );
+        }
+    }
+
+    ctx.peers.insert(remote_addr, PeerState::Disconnected);
+
+    match disconnect_reason {
+        _ if blocked => {}
+        ConnectionError::TimedOut => {
+            if ctx.history.is_some() {
+                ctx.reconnecting.lock().await.insert(remote_addr);
+            }
+            if ctx.mailbox_limits.messages > 0 {
+                ctx.mailboxes
+                    .lock()
+                    .await
+                    .insert(remote_addr, Mailbox::new(ctx.mailbox_limits));
+                ctx.supervisor
+                    .spawn("mailbox", mailbox_loop(ctx.clone(), remote_addr));
+            }
+            reconnect(&ctx, remote_addr).await;
+        }
+        e if is_already_open_or_locally_closed_reason(&e) => {
+            ctx.peers.insert(remote_addr, PeerState::Connected);
+        }
+        // pinned peers are reconnected to even if they closed the connection
+        _ if ctx.is_pinned(remote_addr) => reconnect(&ctx, remote_addr).await,
+        _ => {}
+    }
+}
+
+/// Handles communication via `connection`.
+async fn handle_connection_inner(
+    ctx: &Context,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+) -> ConnectionError {
+    if !ctx.seed_mode && !ctx.receive_only {
+        // the mailbox is taken along with subscribing, so that
+        // the messages kept in it are not sent again
+        let mut mailboxes = ctx.mailboxes.lock().await;
+        let mut message_receiver = ctx.message_sender.subscribe();
+        let mailbox = mailboxes.remove(&remote_addr);
+        drop(mailboxes);
+        ctx.subscribed.notify_one();
+        // taken along with subscribing too, so that the messages
+        // sent from then on are the changes to it
+        let snapshot = ctx.kv_store.as_ref().map(|kv_store| kv_store.entries());
+        ctx.supervisor.spawn("sender", {
+            let ctx = ctx.clone();
+            let connection = connection.clone();
+            // unsubscribes as soon as the connection is closed,
+            // so that the producer knows when there is no one to send to
+            async move {
+                let sending = async {
+                    if let Some(snapshot) = snapshot {
+                        send_snapshot(&ctx, &connection, remote_addr, snapshot).await?;
+                    }
+                    if let Some(mailbox) = mailbox {
+                        flush_mailbox(&ctx, mailbox, &connection, remote_addr).await?;
+                    }
+                    sender_loop(&ctx, &mut message_receiver, &connection, remote_addr).await
+                };
+                tokio::select! {
+                    _ = sending => {}
+                    _ = connection.closed() => {}
+                }
+            }
+        });
+    }
+    if ctx.reconnecting.lock().await.remove(&remote_addr) {
+        // errors show up in receiving
+        let _ = request_history(ctx, connection).await;
+    }
+    ctx.supervisor
+        .spawn("ping", ping_loop(ctx.clone(), connection.clone()));
+    loop {
+        let receiving_res = receiver_loop(ctx, connection, remote_addr)
+            .await
+            .with_context(remote_addr, Operation::MessageReceive);
+        if let Some(reason) = connection.close_reason() {
+            return reason;
+        }
+        if let Err(e) = receiving_res {
+            log_event(
+                "receive_failed",
+                remote_addr,
+                &[
+                    b"Failed to receive from ",
+                    remote_addr.to_string().as_bytes(),
+                    b", error: ",
+                    e.to_string().as_bytes(),
+                ],
+            );
+        }
+    }
+}
+
+/// Once in `ctx.ping_interval`, pings the peer on the other end of `connection`.
+async fn ping_loop(ctx: Context, connection: Connection) -> AppResult<()> {
+    let mut interval = tokio::time::interval(ctx.ping_interval);
+    loop {
+        interval.tick().await;
+        send_message(
+            &ctx,
+            &connection,
+            &Message::Ping {
+                sent: unix_millis(),
+            },
+        )
+        .await?;
+    }
+}
@@ -0,0 +1,70 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// Remembers the hashes of the latest `capacity` payloads seen, so that
+/// a payload sent again in new messages, e.g. by handlers passing on what
+/// they receive, is processed once rather than going around forever.
+///
+/// Unlike the nonces of `ReplayGuard`, which tell apart the messages
+/// of an origin, the hashes catch the same body from any origin.
+pub struct DedupCache {
+    capacity: usize,
+    /// The time each payload was last seen at, counted in payloads.
+    seen: HashMap<[u8; 32], u64>,
+    /// The payloads by the time they were last seen at, least recent first.
+    by_time: BTreeMap<u64, [u8; 32]>,
+    time: u64,
+}
+
+impl DedupCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: HashMap::new(),
+            by_time: BTreeMap::new(),
+            time: 0,
+        }
+    }
+
+    /// Records `body` as seen, returning whether it was not seen
+    /// among the latest payloads yet. A payload seen again counts
+    /// as the latest one, so that one kept being sent stays remembered.
+    pub fn insert(&mut self, body: &str) -> bool {
+        let hash: [u8; 32] = Sha256::digest(body).into();
+        self.time += 1;
+        if let Some(time) = self.seen.insert(hash, self.time) {
+            self.by_time.remove(&time);
+            self.by_time.insert(self.time, hash);
+            return false;
+        }
+        self.by_time.insert(self.time, hash);
+        if self.seen.len() > self.capacity {
+            let (_, oldest) = self.by_time.pop_first().unwrap();
+            self.seen.remove(&oldest);
+        }
+        true
+    }
+
+    /// The number of payloads remembered.
+    pub fn payload_count(&self) -> usize {
+        self.seen.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_cache() {
+        let mut cache = DedupCache::new(2);
+        assert!(cache.insert("a"));
+        assert!(cache.insert("b"));
+        assert!(!cache.insert("a"));
+        // "b" is the least recently seen
+        assert!(cache.insert("c"));
+        assert_eq!(cache.payload_count(), 2);
+        assert!(!cache.insert("a"));
+        assert!(cache.insert("b"));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    handler::Handler, node::shut_down, producer::broadcast_message, Config, Context, GossipNode,
+};
+use core::{net::SocketAddr, time::Duration};
+use std::{io, sync::Arc};
+use tokio::runtime::Runtime;
+
+/// A `GossipNode` running on a Tokio runtime of its own, for applications
+/// on other executors, e.g. smol or async-std: the futures of its methods
+/// only wait for tasks of that runtime, so any executor can poll them.
+pub struct DetachedNode {
+    /// Taken when the node is dropped, to be shut down without blocking.
+    runtime: Option<Runtime>,
+    ctx: Context,
+    /// Set with `Config::drain_timeout`.
+    drain_timeout: Duration,
+}
+
+impl DetachedNode {
+    /// Starts a node as per `config` on a new multi-threaded runtime,
+    /// as `GossipNode::start` does.
+    pub async fn start(config: Config, handlers: Vec<Arc<dyn Handler>>) -> io::Result<Self> {
+        let runtime = Runtime::new()?;
+        let started = runtime.spawn(GossipNode::start(config, handlers)).await;
+        match started.unwrap() {
+            Ok(node) => Ok(Self {
+                runtime: Some(runtime),
+                ctx: node.ctx,
+                drain_timeout: node.drain_timeout,
+            }),
+            Err(e) => {
+                runtime.shutdown_background();
+                Err(e)
+            }
+        }
+    }
+
+    fn runtime(&self) -> &Runtime {
+        self.runtime.as_ref().unwrap()
+    }
+
+    /// The address the node is known by to its peers.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.ctx.local_addr()
+    }
+
+    /// The address the node listens on.
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.ctx.bind_addr()
+    }
+
+    /// The listen addresses of the peers the node is connected to.
+    pub async fn peers(&self) -> Vec<SocketAddr> {
+        let ctx = self.ctx.clone();
+        self.runtime()
+            .spawn(async move { ctx.connections.lock().await.keys().copied().collect() })
+            .await
+            .unwrap()
+    }
+
+    /// Sends a new message with `body` to all peers, as `GossipNode::broadcast` does.
+    pub async fn broadcast(&self, body: String) {
+        let ctx = self.ctx.clone();
+        self.runtime()
+            .spawn(async move { broadcast_message(&ctx, body).await })
+            .await
+            .unwrap();
+    }
+
+    /// Drains the node for up to `Config::drain_timeout`, stops it and its runtime.
+    pub async fn shutdown(self) {
+        let ctx = self.ctx.clone();
+        let drain_timeout = self.drain_timeout;
+        self.runtime()
+            .spawn(async move { shut_down(&ctx, drain_timeout).await })
+            .await
+            .unwrap();
+    }
+}
+
+impl Drop for DetachedNode {
+    fn drop(&mut self) {
+        // dropping a runtime blocks, which panics within another one
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Envelope;
+    use futures::executor::block_on;
+    use std::{
+        sync::mpsc::{self, Sender},
+        thread::sleep,
+        time::Instant,
+    };
+
+    struct Received(Sender<String>);
+
+    impl Handler for Received {
+        fn on_message(&self, _from: SocketAddr, envelope: &Arc<Envelope>) {
+            let _ = self.0.send(envelope.body.clone());
+        }
+    }
+
+    #[test]
+    fn test_detached_node() {
+        let config = |port, connect: &[&str]| Config {
+            connect: connect.iter().map(|&addr| addr.to_owned()).collect(),
+            cert: "missing-cert.pem".into(),
+            key: "missing-key.pem".into(),
+            ephemeral_cert: true,
+            skip_server_verification: true,
+            ..Config::new(port)
+        };
+        let (sender, received) = mpsc::channel();
+        let handler = Arc::new(Received(sender));
+        let first = block_on(DetachedNode::start(config(8097, &[]), vec![handler])).unwrap();
+        let second = block_on(DetachedNode::start(
+            config(8098, &["127.0.0.1:8097"]),
+            Vec::new(),
+        ))
+        .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while block_on(second.peers()).is_empty() && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(block_on(second.peers()), vec![first.local_addr()]);
+        block_on(second.broadcast("hello".to_owned()));
+        assert_eq!(
+            received.recv_timeout(Duration::from_secs(10)).as_deref(),
+            Ok("hello")
+        );
+
+        block_on(second.shutdown());
+        block_on(first.shutdown());
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+/// The state of draining on shutdown: whether it started,
+/// and the sends not yet acknowledged it waits for.
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+impl Drain {
+    /// Starts draining, after which no new messages or peers are to be taken.
+    pub fn start(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Counts a send as in flight until the returned `InFlight` is dropped.
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// The number of sends in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Waits until no sends are in flight.
+    pub async fn wait_idle(&self) {
+        loop {
+            // created before the check, so that no send finishing is missed
+            let idle = self.idle.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// A send counted by a `Drain`.
+pub struct InFlight(Arc<Drain>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let drain = Arc::new(Drain::default());
+        assert!(!drain.is_draining());
+        drain.wait_idle().await;
+
+        let first = drain.track();
+        let second = drain.track();
+        assert_eq!(drain.in_flight(), 2);
+        drain.start();
+        assert!(drain.is_draining());
+        drop(first);
+        let wait = tokio::time::timeout(Duration::from_millis(10), drain.wait_idle());
+        assert!(wait.await.is_err());
+
+        let waiting = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.wait_idle().await }
+        });
+        tokio::task::yield_now().await;
+        drop(second);
+        waiting.await.unwrap();
+        assert_eq!(drain.in_flight(), 0);
+    }
+}
@@ -0,0 +1,74 @@
+use core::{
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncUdpSocket,
+};
+use std::io::{self, IoSliceMut};
+use tokio::net::UdpSocket;
+
+/// A UDP socket sending packets with the DSCP value set on it.
+///
+/// quinn sets the ECN bits of each packet it sends, which replaces the whole
+/// traffic class set on the socket, so packets are sent over a clone of the socket
+/// without ECN bits and one datagram at a time instead. Packets are received
+/// by the wrapped socket.
+#[derive(Debug)]
+pub struct DscpUdpSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    io: UdpSocket,
+}
+
+impl DscpUdpSocket {
+    /// Sends over `socket`, a clone of the socket `inner` receives on.
+    pub fn new(socket: std::net::UdpSocket, inner: Box<dyn AsyncUdpSocket>) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            inner,
+            io: UdpSocket::from_std(socket)?,
+        })
+    }
+}
+
+impl AsyncUdpSocket for DscpUdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        for (i, transmit) in transmits.iter().enumerate() {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            for segment in transmit.contents.chunks(segment_size.max(1)) {
+                match self.io.poll_send_to(cx, segment, transmit.destination) {
+                    Poll::Ready(Ok(_)) => {}
+                    Poll::Ready(Err(e)) if i == 0 => return Poll::Ready(Err(e)),
+                    Poll::Pending if i == 0 => return Poll::Pending,
+                    // report the transmits which were sent,
+                    // a partially sent one is dropped as if it was lost
+                    Poll::Ready(Err(_)) | Poll::Pending => return Poll::Ready(Ok(i)),
+                }
+            }
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_recv(cx, bufs, meta)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
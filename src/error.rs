@@ -1,4 +1,9 @@
-use quinn::{ApplicationClose, ConnectError, ConnectionError, ReadToEndError, WriteError};
+use crate::address_rules::InvalidAddress;
+use core::{fmt, net::SocketAddr};
+use quinn::{
+    ApplicationClose, ConnectError, Connection, ConnectionError, Endpoint, ReadError,
+    ReadExactError, ReadToEndError, VarInt, WriteError,
+};
 use std::io;
 use thiserror::Error;
 
@@ -10,18 +15,163 @@ pub enum AppError {
     ConnectError(#[from] ConnectError),
     #[error("read error: {0}")]
     ReadToEndError(#[from] ReadToEndError),
+    #[error("read error: {0}")]
+    ReadExactError(#[from] ReadExactError),
+    #[error("read error: {0}")]
+    ReadError(#[from] ReadError),
     #[error("write error: {0}")]
     WriteError(#[from] WriteError),
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
     #[error("bincode error: {0}")]
     Bincode(#[from] bincode::Error),
+    #[error("timed out")]
+    Timeout(#[from] tokio::time::error::Elapsed),
+    #[error("the certificate is not issued for the advertised address")]
+    IdentityMismatch,
+    #[error("the peer is blocked")]
+    Blocked,
+    #[error("the advertised listen address is invalid: {0}")]
+    InvalidListenAddress(InvalidAddress),
+    #[error("the transfer of {len} bytes exceeds the maximum of {max_len}")]
+    TransferTooLarge { len: u64, max_len: usize },
+    #[error("the transfer is not of the length in its header")]
+    TransferLengthMismatch,
+    #[error("the transfer does not match its hash")]
+    TransferHashMismatch,
+    /// An error which occurred during `operation` with `peer`.
+    #[error("{source} (during {operation} with {peer})")]
+    Context {
+        peer: SocketAddr,
+        operation: Operation,
+        source: Box<AppError>,
+    },
+}
+
+impl AppError {
+    /// Returns the error without its context.
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
 
+/// The operations with peers errors are reported for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Dial,
+    /// Exchanging the listen address for the peer list.
+    Handshake,
+    PeerListRead,
+    PeerListSend,
+    MessageSend,
+    MessageReceive,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dial => "dial",
+            Self::Handshake => "handshake",
+            Self::PeerListRead => "peer-list read",
+            Self::PeerListSend => "peer-list send",
+            Self::MessageSend => "message send",
+            Self::MessageReceive => "message receive",
+        })
+    }
+}
+
+/// Attaches the peer and the operation to errors.
+pub trait WithContext<T> {
+    /// Wraps the error into `AppError::Context`, unless it has a context already,
+    /// which is then the more specific one.
+    fn with_context(self, peer: SocketAddr, operation: Operation) -> AppResult<T>;
+}
+
+impl<T, E: Into<AppError>> WithContext<T> for Result<T, E> {
+    fn with_context(self, peer: SocketAddr, operation: Operation) -> AppResult<T> {
+        self.map_err(|e| match e.into() {
+            e @ AppError::Context { .. } => e,
+            e => AppError::Context {
+                peer,
+                operation,
+                source: Box::new(e),
+            },
+        })
+    }
+}
+
+/// The reasons for which connections are closed by the application,
+/// sent to the peers as QUIC application error codes along with their strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Both peers connected to each other, one of the connections is closed.
+    AlreadyConnected = 1,
+    Shutdown = 2,
+    /// The peer belongs to another gossip network.
+    WrongNetwork = 3,
+    Banned = 4,
+    /// The peer cannot accept more connections.
+    Busy = 5,
+    /// The peer sent data which could not be parsed.
+    ProtocolError = 6,
+    /// The peer's certificate is not issued for the address it advertised.
+    IdentityMismatch = 7,
+}
+
+impl CloseReason {
+    pub fn from_code(code: VarInt) -> Option<Self> {
+        Some(match code.into_inner() {
+            1 => Self::AlreadyConnected,
+            2 => Self::Shutdown,
+            3 => Self::WrongNetwork,
+            4 => Self::Banned,
+            5 => Self::Busy,
+            6 => Self::ProtocolError,
+            7 => Self::IdentityMismatch,
+            _ => return None,
+        })
+    }
+
+    pub fn code(self) -> VarInt {
+        VarInt::from_u32(self as _)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AlreadyConnected => "already connected",
+            Self::Shutdown => "shutdown",
+            Self::WrongNetwork => "wrong network",
+            Self::Banned => "banned",
+            Self::Busy => "busy",
+            Self::ProtocolError => "protocol error",
+            Self::IdentityMismatch => "identity mismatch",
+        }
+    }
+
+    /// Closes `connection` for this reason.
+    pub fn close(self, connection: &Connection) {
+        connection.close(self.code(), self.as_str().as_bytes());
+    }
+
+    /// Closes all the connections of `endpoint` for this reason.
+    pub fn close_endpoint(self, endpoint: &Endpoint) {
+        endpoint.close(self.code(), self.as_str().as_bytes());
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 pub fn is_already_open_or_locally_closed_error(e: &AppError) -> bool {
-    if let AppError::ConnectionError(e) = e {
+    if let AppError::ConnectionError(e) = e.root() {
         is_already_open_or_locally_closed_reason(e)
     } else {
         false
@@ -30,9 +180,44 @@ pub fn is_already_open_or_locally_closed_error(e: &AppError) -> bool {
 
 pub fn is_already_open_or_locally_closed_reason(e: &ConnectionError) -> bool {
     if let ConnectionError::ApplicationClosed(ApplicationClose { error_code, .. }) = e {
-        if error_code == &1u8.into() {
-            return true;
-        }
+        return CloseReason::from_code(*error_code) == Some(CloseReason::AlreadyConnected);
     }
     e == &ConnectionError::LocallyClosed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_reason_codes() {
+        for reason in [
+            CloseReason::AlreadyConnected,
+            CloseReason::Shutdown,
+            CloseReason::WrongNetwork,
+            CloseReason::Banned,
+            CloseReason::Busy,
+            CloseReason::ProtocolError,
+            CloseReason::IdentityMismatch,
+        ] {
+            assert_eq!(CloseReason::from_code(reason.code()), Some(reason));
+        }
+        assert_eq!(CloseReason::from_code(VarInt::from_u32(0)), None);
+        assert_eq!(CloseReason::Busy.to_string(), "busy");
+    }
+
+    #[test]
+    fn test_with_context() {
+        let peer = "127.0.0.1:8080".parse().unwrap();
+        let res: AppResult<()> = Err(io::Error::other("reset").into());
+        let e = res
+            .with_context(peer, Operation::PeerListRead)
+            .with_context(peer, Operation::Dial)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "I/O error: reset (during peer-list read with 127.0.0.1:8080)"
+        );
+        assert!(matches!(e.root(), AppError::Io(_)));
+    }
+}
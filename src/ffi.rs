@@ -0,0 +1,346 @@
+use crate::{
+    handler::Handler,
+    log::{log, set_log_levels},
+    message::Envelope,
+    Config, DetachedNode,
+};
+use core::{
+    ffi::{c_char, c_int, CStr},
+    net::SocketAddr,
+    ptr, slice,
+};
+use futures::executor::block_on;
+use std::{
+    ffi::CString,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TryRecvError},
+        Arc,
+    },
+};
+
+/// The number of events queued for `gossip_poll_event`, beyond which new ones are dropped.
+const EVENT_QUEUE_CAPACITY: usize = 1024;
+
+/// The options of `gossip_start`, the other ones have the defaults of the binary.
+#[repr(C)]
+pub struct GossipOptions {
+    /// The port to run on.
+    pub port: u16,
+    /// The addresses of the first nodes to connect to, comma-separated, or NULL.
+    pub connect: *const c_char,
+    /// The path to the certificate PEM file, or NULL for `cert.pem`.
+    pub cert: *const c_char,
+    /// The path to the secret key PEM file, or NULL for `key.pem`.
+    pub key: *const c_char,
+    /// Generate a self-signed certificate if `cert` or `key` does not exist.
+    pub ephemeral_cert: bool,
+    /// Do not verify the peers' certificates.
+    pub skip_server_verification: bool,
+    /// The log levels in the format of `--log-level`, or NULL to keep them,
+    /// shared by all the nodes of the process.
+    pub log_level: *const c_char,
+}
+
+/// The kind of a `GossipEvent`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GossipEventKind {
+    /// A message is received.
+    Message,
+    /// A connection to a peer is established.
+    PeerJoined,
+    /// The last connection to a peer is closed.
+    PeerLeft,
+}
+
+/// An event of a node, filled in by `gossip_poll_event`. The pointers stay valid
+/// until the next call to `gossip_poll_event` or `gossip_stop` on the node.
+#[repr(C)]
+pub struct GossipEvent {
+    pub kind: GossipEventKind,
+    /// The listen address of the peer, or of the one the message is received from.
+    pub peer: *const c_char,
+    /// The listen address of the node which sent the message, NULL for other events.
+    pub origin: *const c_char,
+    /// The body of the message, not NUL-terminated, NULL for other events.
+    pub body: *const u8,
+    pub body_len: usize,
+}
+
+/// A node started with `gossip_start`.
+pub struct GossipHandle {
+    node: DetachedNode,
+    events: Receiver<Event>,
+    /// The event last returned by `gossip_poll_event`, which its pointers point into.
+    last_event: Option<PolledEvent>,
+}
+
+enum Event {
+    Message(SocketAddr, Arc<Envelope>),
+    PeerJoined(SocketAddr),
+    PeerLeft(SocketAddr),
+}
+
+struct PolledEvent {
+    peer: CString,
+    origin: Option<CString>,
+    envelope: Option<Arc<Envelope>>,
+}
+
+/// Queues the events of a node for `gossip_poll_event`.
+struct EventQueue(SyncSender<Event>);
+
+impl EventQueue {
+    fn push(&self, event: Event) {
+        // the caller is not polling, it has no use for the backlog
+        let _ = self.0.try_send(event);
+    }
+}
+
+impl Handler for EventQueue {
+    fn on_message(&self, from: SocketAddr, envelope: &Arc<Envelope>) {
+        self.push(Event::Message(from, envelope.clone()));
+    }
+
+    fn on_peer_joined(&self, addr: SocketAddr) {
+        self.push(Event::PeerJoined(addr));
+    }
+
+    fn on_peer_left(&self, addr: SocketAddr) {
+        self.push(Event::PeerLeft(addr));
+    }
+}
+
+/// Starts a node as per `options` on a runtime of its own.
+/// Returns NULL and logs the error on failure.
+///
+/// # Safety
+///
+/// `options` must point to valid options, whose strings are NUL-terminated or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn gossip_start(options: *const GossipOptions) -> *mut GossipHandle {
+    let options = &*options;
+    let string =
+        |s: *const c_char| (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned());
+    if let Some(spec) = string(options.log_level) {
+        match spec.parse() {
+            Ok(levels) => set_log_levels(levels),
+            Err(e) => {
+                log(&[b"Invalid log level: ", e.as_bytes()]);
+                return ptr::null_mut();
+            }
+        }
+    }
+    let mut config = Config::new(options.port);
+    if let Some(connect) = string(options.connect) {
+        config.connect = connect.split(',').map(str::to_owned).collect();
+    }
+    if let Some(cert) = string(options.cert) {
+        config.cert = PathBuf::from(cert);
+    }
+    if let Some(key) = string(options.key) {
+        config.key = PathBuf::from(key);
+    }
+    config.ephemeral_cert = options.ephemeral_cert;
+    config.skip_server_verification = options.skip_server_verification;
+
+    let (sender, events) = mpsc::sync_channel(EVENT_QUEUE_CAPACITY);
+    let handlers: Vec<Arc<dyn Handler>> = vec![Arc::new(EventQueue(sender))];
+    match block_on(DetachedNode::start(config, handlers)) {
+        Ok(node) => Box::into_raw(Box::new(GossipHandle {
+            node,
+            events,
+            last_event: None,
+        })),
+        Err(e) => {
+            log(&[
+                b"Failed to start the node, error: ",
+                e.to_string().as_bytes(),
+            ]);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Sends a new message with the `len` bytes of `body` to all peers.
+/// Returns 0, or -1 if `body` is not UTF-8.
+///
+/// # Safety
+///
+/// `handle` must be returned by `gossip_start` and not stopped,
+/// `body` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gossip_send(
+    handle: *mut GossipHandle,
+    body: *const u8,
+    len: usize,
+) -> c_int {
+    let handle = &*handle;
+    let body = if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(body, len)
+    };
+    let Ok(body) = String::from_utf8(body.to_vec()) else {
+        return -1;
+    };
+    block_on(handle.node.broadcast(body));
+    0
+}
+
+/// Writes the listen addresses of the connected peers, comma-separated, to `buf`,
+/// truncated to `len - 1` bytes and NUL-terminated, like `snprintf`.
+/// Returns the length of the whole list, without the NUL.
+///
+/// # Safety
+///
+/// `handle` must be returned by `gossip_start` and not stopped,
+/// `buf` must point to `len` writable bytes, or be NULL if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn gossip_peers(
+    handle: *mut GossipHandle,
+    buf: *mut c_char,
+    len: usize,
+) -> usize {
+    let handle = &*handle;
+    let peers = block_on(handle.node.peers());
+    let list = peers
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    if len > 0 {
+        let written = list.len().min(len - 1);
+        ptr::copy_nonoverlapping(list.as_ptr(), buf.cast(), written);
+        *buf.add(written) = 0;
+    }
+    list.len()
+}
+
+/// Fills in `event` with the next event of the node, without waiting for one.
+/// Returns whether there was one.
+///
+/// # Safety
+///
+/// `handle` must be returned by `gossip_start` and not stopped,
+/// `event` must point to a writable `GossipEvent`.
+#[no_mangle]
+pub unsafe extern "C" fn gossip_poll_event(
+    handle: *mut GossipHandle,
+    event: *mut GossipEvent,
+) -> bool {
+    let handle = &mut *handle;
+    let (kind, peer, envelope) = match handle.events.try_recv() {
+        Ok(Event::Message(peer, envelope)) => (GossipEventKind::Message, peer, Some(envelope)),
+        Ok(Event::PeerJoined(peer)) => (GossipEventKind::PeerJoined, peer, None),
+        Ok(Event::PeerLeft(peer)) => (GossipEventKind::PeerLeft, peer, None),
+        Err(TryRecvError::Empty | TryRecvError::Disconnected) => return false,
+    };
+    let c_string = |addr: SocketAddr| CString::new(addr.to_string()).unwrap();
+    let polled = handle.last_event.insert(PolledEvent {
+        peer: c_string(peer),
+        origin: envelope.as_ref().map(|envelope| c_string(envelope.origin)),
+        envelope,
+    });
+    event.write(GossipEvent {
+        kind,
+        peer: polled.peer.as_ptr(),
+        origin: polled.origin.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+        body: polled
+            .envelope
+            .as_ref()
+            .map_or(ptr::null(), |envelope| envelope.body.as_ptr()),
+        body_len: polled
+            .envelope
+            .as_ref()
+            .map_or(0, |envelope| envelope.body.len()),
+    });
+    true
+}
+
+/// Drains the node for up to the drain timeout, stops it and frees `handle`.
+///
+/// # Safety
+///
+/// `handle` must be returned by `gossip_start` and not stopped, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn gossip_stop(handle: *mut GossipHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = Box::from_raw(handle);
+    block_on(handle.node.shutdown());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    /// Polls `handle` until an event of `kind` comes, for up to 10 seconds.
+    fn wait_for(handle: *mut GossipHandle, kind: GossipEventKind) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut event = GossipEvent {
+            kind,
+            peer: ptr::null(),
+            origin: ptr::null(),
+            body: ptr::null(),
+            body_len: 0,
+        };
+        while Instant::now() < deadline {
+            if unsafe { gossip_poll_event(handle, &mut event) } {
+                if event.kind == kind {
+                    return Some(if event.body.is_null() {
+                        Vec::new()
+                    } else {
+                        unsafe { slice::from_raw_parts(event.body, event.body_len) }.to_vec()
+                    });
+                }
+            } else {
+                sleep(Duration::from_millis(10));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_ffi() {
+        let options = |port, connect: Option<&CStr>| GossipOptions {
+            port,
+            connect: connect.map_or(ptr::null(), CStr::as_ptr),
+            cert: c"missing-cert.pem".as_ptr(),
+            key: c"missing-key.pem".as_ptr(),
+            ephemeral_cert: true,
+            skip_server_verification: true,
+            log_level: c"off".as_ptr(),
+        };
+        let first = unsafe { gossip_start(&options(8095, None)) };
+        assert!(!first.is_null());
+        let second = unsafe { gossip_start(&options(8096, Some(c"127.0.0.1:8095"))) };
+        assert!(!second.is_null());
+
+        assert!(wait_for(first, GossipEventKind::PeerJoined).is_some());
+        assert!(wait_for(second, GossipEventKind::PeerJoined).is_some());
+        let mut peers = [0; 64];
+        let len = unsafe { gossip_peers(second, peers.as_mut_ptr(), peers.len()) };
+        let list = c"127.0.0.1:8095";
+        assert_eq!(len, list.count_bytes());
+        assert_eq!(unsafe { CStr::from_ptr(peers.as_ptr()) }, list);
+        assert_eq!(unsafe { gossip_peers(second, ptr::null_mut(), 0) }, len);
+        assert_eq!(unsafe { gossip_send(second, b"hello".as_ptr(), 5) }, 0);
+        assert_eq!(
+            wait_for(first, GossipEventKind::Message).as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(unsafe { gossip_send(second, b"\xff".as_ptr(), 1) }, -1);
+
+        unsafe {
+            gossip_stop(second);
+            gossip_stop(first);
+        }
+    }
+}
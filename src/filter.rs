@@ -0,0 +1,171 @@
+use crate::message::Envelope;
+use core::{fmt, net::SocketAddr, str::FromStr};
+use regex::Regex;
+
+/// What a matching rule prevents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Neither deliver nor forward.
+    Drop,
+    NoDeliver,
+    NoForward,
+}
+
+#[derive(Clone, Debug)]
+pub enum Matcher {
+    /// Matches messages created by the peer listening on the address.
+    Sender(SocketAddr),
+    /// Matches messages the body of which matches the regex.
+    Payload(Regex),
+}
+
+/// A filtering rule in the form of `ACTION MATCHER`, where `ACTION`
+/// is `drop`, `no-deliver` or `no-forward`, and `MATCHER` is
+/// `sender=IP:port` or `payload=REGEX`, e.g. `drop payload=^spam`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub action: Action,
+    pub matcher: Matcher,
+}
+
+impl Rule {
+    fn matches(&self, envelope: &Envelope) -> bool {
+        match &self.matcher {
+            Matcher::Sender(addr) => envelope.origin == *addr,
+            Matcher::Payload(regex) => regex.is_match(&envelope.body),
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, matcher) = s
+            .trim()
+            .split_once(' ')
+            .ok_or("expected `ACTION MATCHER`")?;
+        let action = match action {
+            "drop" => Action::Drop,
+            "no-deliver" => Action::NoDeliver,
+            "no-forward" => Action::NoForward,
+            _ => return Err(format!("unknown action {action:?}")),
+        };
+        let matcher = match matcher.trim_start().split_once('=') {
+            Some(("sender", addr)) => {
+                Matcher::Sender(addr.parse().map_err(|e| format!("invalid sender: {e}"))?)
+            }
+            Some(("payload", regex)) => {
+                Matcher::Payload(Regex::new(regex).map_err(|e| e.to_string())?)
+            }
+            _ => return Err(format!("unknown matcher {matcher:?}")),
+        };
+        Ok(Self { action, matcher })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.action {
+            Action::Drop => "drop",
+            Action::NoDeliver => "no-deliver",
+            Action::NoForward => "no-forward",
+        })?;
+        match &self.matcher {
+            Matcher::Sender(addr) => write!(f, " sender={addr}"),
+            Matcher::Payload(regex) => write!(f, " payload={regex}"),
+        }
+    }
+}
+
+/// Whether a message is to be delivered locally and forwarded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Verdict {
+    pub deliver: bool,
+    pub forward: bool,
+}
+
+/// Applies all the matching `rules` to `envelope`.
+pub fn evaluate(rules: &[Rule], envelope: &Envelope) -> Verdict {
+    let mut verdict = Verdict {
+        deliver: true,
+        forward: true,
+    };
+    for rule in rules.iter().filter(|rule| rule.matches(envelope)) {
+        match rule.action {
+            Action::Drop => {
+                verdict.deliver = false;
+                verdict.forward = false;
+            }
+            Action::NoDeliver => verdict.deliver = false,
+            Action::NoForward => verdict.forward = false,
+        }
+    }
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(origin: &str, body: &str) -> Envelope {
+        Envelope {
+            origin: origin.parse().unwrap(),
+            nonce: 1,
+            seq: 1,
+            timestamp: 0,
+            expires: None,
+            body: body.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        for rule in [
+            "drop sender=127.0.0.1:8080",
+            "no-deliver payload=^a.*b$",
+            "no-forward sender=[::1]:8080",
+        ] {
+            assert_eq!(rule.parse::<Rule>().unwrap().to_string(), rule);
+        }
+        assert!("drop".parse::<Rule>().is_err());
+        assert!("ignore sender=127.0.0.1:8080".parse::<Rule>().is_err());
+        assert!("drop sender=127.0.0.1".parse::<Rule>().is_err());
+        assert!("drop payload=(".parse::<Rule>().is_err());
+        assert!("drop topic=a".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules: Vec<Rule> = [
+            "no-forward sender=127.0.0.1:8080",
+            "no-deliver payload=^spam",
+        ]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+        assert_eq!(
+            evaluate(&rules, &envelope("127.0.0.1:8081", "hello")),
+            Verdict {
+                deliver: true,
+                forward: true
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, &envelope("127.0.0.1:8080", "spam")),
+            Verdict {
+                deliver: false,
+                forward: false
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, &envelope("127.0.0.1:8081", "spam")),
+            Verdict {
+                deliver: false,
+                forward: true
+            }
+        );
+        let drop: Rule = "drop payload=.".parse().unwrap();
+        assert!(!evaluate(&[drop], &envelope("127.0.0.1:8081", "hello")).deliver);
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Limits the bytes sent to a peer but not yet acknowledged by it to `limit`.
+pub struct FlowWindow {
+    limit: usize,
+    outstanding: AtomicUsize,
+}
+
+impl FlowWindow {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            outstanding: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserves `len` bytes, unless the window would be exceeded.
+    ///
+    /// Messages larger than the window are let through
+    /// when nothing is outstanding, so that they are not held back forever.
+    pub fn try_acquire(&self, len: usize) -> bool {
+        self.outstanding
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |outstanding| {
+                (outstanding == 0 || outstanding + len <= self.limit).then_some(outstanding + len)
+            })
+            .is_ok()
+    }
+
+    /// Releases `len` bytes reserved with `try_acquire` once they are acknowledged.
+    pub fn release(&self, len: usize) {
+        self.outstanding.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_window() {
+        let window = FlowWindow::new(100);
+        assert!(window.try_acquire(60));
+        assert!(window.try_acquire(40));
+        assert!(!window.try_acquire(1));
+        window.release(60);
+        assert!(window.try_acquire(50));
+        assert!(!window.try_acquire(20));
+        window.release(40);
+        window.release(50);
+        assert!(window.try_acquire(150));
+        assert!(!window.try_acquire(1));
+    }
+}
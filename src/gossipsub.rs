@@ -0,0 +1,141 @@
+use crate::{handler::Handler, log::log, message::Envelope, producer::broadcast_message, Context};
+use core::{
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    time::Duration,
+};
+use futures::StreamExt;
+use libp2p::{
+    gossipsub::{self, IdentTopic, MessageAuthenticity, MessageId, PublishError},
+    noise,
+    swarm::SwarmEvent,
+    tcp, yamux, Multiaddr, Swarm, SwarmBuilder,
+};
+use std::{collections::hash_map::DefaultHasher, error::Error, sync::Arc};
+use tokio::sync::mpsc;
+
+/// The maximum number of messages waiting to be published.
+const QUEUE_LEN: usize = 1000;
+
+/// Publishes each received message to a libp2p gossipsub topic.
+pub struct GossipsubBridge {
+    queue: mpsc::Sender<Arc<Envelope>>,
+}
+
+impl GossipsubBridge {
+    /// Returns the bridge and the receiver of the messages
+    /// to be passed to `bridge_loop`.
+    pub fn new() -> (Self, mpsc::Receiver<Arc<Envelope>>) {
+        let (queue, receiver) = mpsc::channel(QUEUE_LEN);
+        (Self { queue }, receiver)
+    }
+}
+
+impl Handler for GossipsubBridge {
+    fn on_message(&self, _from: SocketAddr, envelope: &Arc<Envelope>) {
+        if self.queue.try_send(envelope.clone()).is_err() {
+            log(&[
+                b"Skipped publishing message [",
+                envelope.body.as_bytes(),
+                b"] to gossipsub, the queue is full",
+            ]);
+        }
+    }
+}
+
+/// Joins the gossipsub mesh of `topic`, listening on `listen`
+/// and dialing `dial`. Publishes the messages from `receiver` to the topic,
+/// and sends the messages of the topic to all peers. Logs errors on failure.
+pub async fn bridge_loop(
+    ctx: Context,
+    listen: Vec<Multiaddr>,
+    dial: Vec<Multiaddr>,
+    topic: String,
+    mut receiver: mpsc::Receiver<Arc<Envelope>>,
+) {
+    let topic = IdentTopic::new(topic);
+    let mut swarm = match build_swarm(&topic, listen, dial) {
+        Ok(swarm) => swarm,
+        Err(e) => {
+            log(&[
+                b"Failed to join the gossipsub mesh, error: ",
+                e.to_string().as_bytes(),
+            ]);
+            return;
+        }
+    };
+    log(&[
+        b"Gossipsub peer ID is ",
+        swarm.local_peer_id().to_string().as_bytes(),
+    ]);
+
+    loop {
+        tokio::select! {
+            Some(envelope) = receiver.recv() => {
+                match swarm
+                    .behaviour_mut()
+                    .publish(topic.clone(), envelope.body.as_bytes())
+                {
+                    // another bridge has published it already
+                    Ok(_) | Err(PublishError::Duplicate) => {}
+                    Err(e) => log(&[
+                        b"Failed to publish message [",
+                        envelope.body.as_bytes(),
+                        b"] to gossipsub, error: ",
+                        e.to_string().as_bytes(),
+                    ]),
+                }
+            }
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => log(&[
+                    b"Gossipsub listening on ",
+                    address.to_string().as_bytes(),
+                ]),
+                SwarmEvent::Behaviour(gossipsub::Event::Message { message, .. }) => {
+                    let body = String::from_utf8_lossy(&message.data).into_owned();
+                    broadcast_message(&ctx, body).await;
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+fn build_swarm(
+    topic: &IdentTopic,
+    listen: Vec<Multiaddr>,
+    dial: Vec<Multiaddr>,
+) -> Result<Swarm<gossipsub::Behaviour>, Box<dyn Error>> {
+    let mut swarm = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|key| {
+            // identify messages by their contents, so that a message
+            // bridged back by another bridge is dropped as a duplicate
+            let config = gossipsub::ConfigBuilder::default()
+                .message_id_fn(|message| {
+                    let mut hasher = DefaultHasher::new();
+                    message.data.hash(&mut hasher);
+                    MessageId::from(hasher.finish().to_string())
+                })
+                .build()?;
+            Ok(gossipsub::Behaviour::new(
+                MessageAuthenticity::Signed(key.clone()),
+                config,
+            )?)
+        })?
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+    swarm.behaviour_mut().subscribe(topic)?;
+    for addr in listen {
+        swarm.listen_on(addr)?;
+    }
+    for addr in dial {
+        swarm.dial(addr)?;
+    }
+    Ok(swarm)
+}
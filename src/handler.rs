@@ -0,0 +1,341 @@
+use crate::{
+    log::{log, log_message_event},
+    message::{unix_millis, Envelope},
+    token_bucket::TokenBucket,
+};
+use backoff::ExponentialBackoff;
+use clap::ValueEnum;
+use core::{net::SocketAddr, time::Duration};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+    sync::{mpsc, Semaphore},
+    time::Instant,
+};
+
+/// Handles the events of a peer. The methods are called from async tasks,
+/// so they should not block; long work is to be spawned.
+pub trait Handler: Send + Sync {
+    /// Called on each message delivered locally, received from `from`.
+    fn on_message(&self, from: SocketAddr, envelope: &Arc<Envelope>);
+    /// Called when a connection to the peer listening on `addr` is established.
+    fn on_peer_joined(&self, _addr: SocketAddr) {}
+    /// Called when the last connection to the peer listening on `addr` is closed.
+    fn on_peer_left(&self, _addr: SocketAddr) {}
+}
+
+/// The built-in handlers, selected with `--handler`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum HandlerKind {
+    /// Logs received messages.
+    Logger,
+    /// Keeps the last message of each origin, listed with the `kv` admin command.
+    Kv,
+}
+
+/// Logs received messages to stdout.
+pub struct Logger;
+
+impl Handler for Logger {
+    fn on_message(&self, from: SocketAddr, envelope: &Arc<Envelope>) {
+        log_message_event(
+            "message_received",
+            from,
+            envelope,
+            &[
+                b"Received message [",
+                envelope.body.as_bytes(),
+                b"] from ",
+                from.to_string().as_bytes(),
+            ],
+        );
+    }
+}
+
+/// Keeps the last message of each origin, until it expires.
+#[derive(Default)]
+pub struct KvStore {
+    entries: Mutex<HashMap<SocketAddr, Arc<Envelope>>>,
+}
+
+impl KvStore {
+    /// Returns the last messages by their origins, sorted by the origins.
+    /// Expired ones are pruned, by the local clock.
+    pub fn entries(&self) -> Vec<(SocketAddr, Arc<Envelope>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = unix_millis();
+        entries.retain(|_, envelope| !envelope.is_expired(now));
+        let mut entries: Vec<_> = entries
+            .iter()
+            .map(|(&origin, envelope)| (origin, envelope.clone()))
+            .collect();
+        entries.sort_unstable_by_key(|&(origin, _)| origin);
+        entries
+    }
+
+    /// Takes in the entries of a snapshot of another store, where they are newer
+    /// by their nonces than the ones kept. Returns the number of entries taken.
+    pub fn merge(&self, snapshot: Vec<Arc<Envelope>>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let now = unix_millis();
+        let mut taken = 0;
+        for envelope in snapshot {
+            if envelope.is_expired(now)
+                || entries
+                    .get(&envelope.origin)
+                    .is_some_and(|kept| kept.nonce >= envelope.nonce)
+            {
+                continue;
+            }
+            entries.insert(envelope.origin, envelope);
+            taken += 1;
+        }
+        taken
+    }
+}
+
+impl Handler for KvStore {
+    fn on_message(&self, _from: SocketAddr, envelope: &Arc<Envelope>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(envelope.origin, envelope.clone());
+    }
+}
+
+/// Runs a shell command on each received message, with the body on stdin
+/// and the details of the message in environment variables.
+///
+/// At most `concurrency` commands run at once, others wait for them.
+/// Messages exceeding the rate limit are skipped.
+pub struct ExecHook {
+    command: String,
+    semaphore: Arc<Semaphore>,
+    rate_limit: Mutex<TokenBucket>,
+}
+
+impl ExecHook {
+    pub fn new(command: String, concurrency: usize, rate: f64) -> Self {
+        Self {
+            command,
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+            rate_limit: Mutex::new(TokenBucket::new(rate.ceil() as _, rate)),
+        }
+    }
+}
+
+impl Handler for ExecHook {
+    fn on_message(&self, from: SocketAddr, envelope: &Arc<Envelope>) {
+        if !self.rate_limit.lock().unwrap().try_take(Instant::now()) {
+            log(&[
+                b"Skipped --on-message for message [",
+                envelope.body.as_bytes(),
+                b"], rate limit exceeded",
+            ]);
+            return;
+        }
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .env("GOSSIP_PEER", from.to_string())
+            .env("GOSSIP_ORIGIN", envelope.origin.to_string())
+            .env("GOSSIP_NONCE", envelope.nonce.to_string())
+            .env("GOSSIP_SEQ", envelope.seq.to_string())
+            .env("GOSSIP_TIMESTAMP", envelope.timestamp.to_string())
+            .stdin(Stdio::piped());
+        let semaphore = self.semaphore.clone();
+        let envelope = envelope.clone();
+        tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+            let res = async {
+                let mut child = command.spawn()?;
+                let mut stdin = child.stdin.take().unwrap();
+                // the command may not read its stdin
+                let _ = stdin.write_all(envelope.body.as_bytes()).await;
+                drop(stdin);
+                child.wait().await
+            }
+            .await;
+            match res {
+                Ok(status) if status.success() => {}
+                Ok(status) => log(&[
+                    b"The --on-message command failed for message [",
+                    envelope.body.as_bytes(),
+                    b"], ",
+                    status.to_string().as_bytes(),
+                ]),
+                Err(e) => log(&[
+                    b"Failed to run the --on-message command, error: ",
+                    e.to_string().as_bytes(),
+                ]),
+            }
+        });
+    }
+}
+
+/// A received message, queued by `Webhook`.
+struct Delivery {
+    from: SocketAddr,
+    envelope: Arc<Envelope>,
+    received: u64,
+}
+
+/// The JSON body `Webhook` POSTs, times in milliseconds since the Unix epoch.
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    sender: SocketAddr,
+    origin: SocketAddr,
+    nonce: u64,
+    seq: u64,
+    timestamp: u64,
+    received: u64,
+    payload: &'a str,
+}
+
+/// POSTs each received message as JSON to a URL, one at a time.
+///
+/// Failed requests are retried with exponential backoff for up to a minute,
+/// in the meantime messages are queued, up to `queue_len` of them.
+pub struct Webhook {
+    queue: mpsc::Sender<Delivery>,
+}
+
+impl Webhook {
+    pub fn new(url: Url, queue_len: usize) -> Self {
+        let (queue, receiver) = mpsc::channel(queue_len);
+        tokio::spawn(webhook_loop(url, receiver));
+        Self { queue }
+    }
+}
+
+impl Handler for Webhook {
+    fn on_message(&self, from: SocketAddr, envelope: &Arc<Envelope>) {
+        let delivery = Delivery {
+            from,
+            envelope: envelope.clone(),
+            received: unix_millis(),
+        };
+        if self.queue.try_send(delivery).is_err() {
+            log(&[
+                b"Skipped the webhook for message [",
+                envelope.body.as_bytes(),
+                b"], the queue is full",
+            ]);
+        }
+    }
+}
+
+/// POSTs the messages from `receiver` to `url`. Logs errors on failure.
+async fn webhook_loop(url: Url, mut receiver: mpsc::Receiver<Delivery>) {
+    let client = Client::new();
+    while let Some(delivery) = receiver.recv().await {
+        let body = WebhookBody {
+            sender: delivery.from,
+            origin: delivery.envelope.origin,
+            nonce: delivery.envelope.nonce,
+            seq: delivery.envelope.seq,
+            timestamp: delivery.envelope.timestamp,
+            received: delivery.received,
+            payload: &delivery.envelope.body,
+        };
+        let backoff = ExponentialBackoff {
+            max_elapsed_time: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let res = backoff::future::retry(backoff, || async {
+            let response = client
+                .post(url.clone())
+                .json(&body)
+                .send()
+                .await
+                .map_err(backoff::Error::transient)?;
+            response.error_for_status().map(drop).map_err(|e| {
+                // the request will not succeed on retry
+                if e.status().is_some_and(|status| status.is_client_error()) {
+                    backoff::Error::permanent(e)
+                } else {
+                    backoff::Error::transient(e)
+                }
+            })
+        })
+        .await;
+        if let Err(e) = res {
+            log(&[
+                b"Failed to POST message [",
+                delivery.envelope.body.as_bytes(),
+                b"] to the webhook, error: ",
+                e.to_string().as_bytes(),
+            ]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(origin: &str, body: &str) -> Arc<Envelope> {
+        Arc::new(Envelope {
+            origin: origin.parse().unwrap(),
+            nonce: 1,
+            seq: 1,
+            timestamp: 0,
+            expires: None,
+            body: body.into(),
+        })
+    }
+
+    #[test]
+    fn test_kv_store() {
+        let store = KvStore::default();
+        let from = "127.0.0.1:8080".parse().unwrap();
+        store.on_message(from, &envelope("127.0.0.1:8081", "a"));
+        store.on_message(from, &envelope("127.0.0.1:8080", "b"));
+        store.on_message(from, &envelope("127.0.0.1:8081", "c"));
+        let bodies: Vec<_> = store
+            .entries()
+            .into_iter()
+            .map(|(origin, envelope)| (origin.port(), envelope.body.clone()))
+            .collect();
+        assert_eq!(bodies, [(8080, "b".into()), (8081, "c".into())]);
+
+        let expired = Envelope {
+            expires: Some(1),
+            ..(*envelope("127.0.0.1:8081", "d")).clone()
+        };
+        store.on_message(from, &Arc::new(expired));
+        assert_eq!(store.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_kv_merge() {
+        let store = KvStore::default();
+        let from = "127.0.0.1:8080".parse().unwrap();
+        store.on_message(from, &envelope("127.0.0.1:8080", "a"));
+        let newer = Envelope {
+            nonce: 2,
+            ..(*envelope("127.0.0.1:8080", "b")).clone()
+        };
+        let snapshot = vec![
+            envelope("127.0.0.1:8080", "stale"),
+            Arc::new(newer),
+            envelope("127.0.0.1:8081", "c"),
+        ];
+        assert_eq!(store.merge(snapshot.clone()), 2);
+        let bodies: Vec<_> = store
+            .entries()
+            .into_iter()
+            .map(|(_, envelope)| envelope.body.clone())
+            .collect();
+        assert_eq!(bodies, ["b", "c"]);
+        assert_eq!(store.merge(snapshot), 0);
+    }
+}
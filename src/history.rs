@@ -0,0 +1,123 @@
+use crate::message::Envelope;
+use core::{net::SocketAddr, ops::Range};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+/// The maximum number of origins per history request,
+/// which keeps it within the size limit of a message.
+pub const REQUEST_ORIGINS: usize = 32;
+
+/// Keeps the recent messages of each origin, to answer
+/// the history requests of peers which were away.
+pub struct History {
+    /// The maximum number of messages kept per origin.
+    per_origin: usize,
+    messages: HashMap<SocketAddr, VecDeque<Arc<Envelope>>>,
+}
+
+impl History {
+    pub fn new(per_origin: usize) -> Self {
+        Self {
+            per_origin,
+            messages: HashMap::new(),
+        }
+    }
+
+    /// Adds `envelope`, dropping the oldest message of its origin beyond the limit.
+    /// Ignores it if a message of the same origin with a greater nonce is kept already.
+    pub fn record(&mut self, envelope: Arc<Envelope>) {
+        let messages = self.messages.entry(envelope.origin).or_default();
+        if messages
+            .back()
+            .is_some_and(|last| last.nonce >= envelope.nonce)
+        {
+            return;
+        }
+        messages.push_back(envelope);
+        if messages.len() > self.per_origin {
+            messages.pop_front();
+        }
+    }
+
+    /// The number of messages kept of all origins.
+    pub fn message_count(&self) -> usize {
+        self.messages.values().map(VecDeque::len).sum()
+    }
+
+    /// Returns the kept messages of `origin` with nonces greater than `nonce`, oldest first.
+    pub fn since(&self, origin: SocketAddr, nonce: u64) -> Vec<Arc<Envelope>> {
+        let Some(messages) = self.messages.get(&origin) else {
+            return Vec::new();
+        };
+        let start = messages.partition_point(|envelope| envelope.nonce <= nonce);
+        messages.range(start..).cloned().collect()
+    }
+
+    /// Returns the kept messages of `origin` with sequence numbers in `seqs`, oldest first.
+    pub fn seqs(&self, origin: SocketAddr, seqs: Range<u64>) -> Vec<Arc<Envelope>> {
+        self.messages
+            .get(&origin)
+            .map_or_else(Vec::new, |messages| {
+                messages
+                    .iter()
+                    .filter(|envelope| seqs.contains(&envelope.seq))
+                    .cloned()
+                    .collect()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let origin = "127.0.0.1:8080".parse().unwrap();
+        let other = "127.0.0.1:8081".parse().unwrap();
+        let message = |origin, nonce| {
+            Arc::new(Envelope {
+                origin,
+                nonce,
+                seq: nonce,
+                timestamp: 0,
+                expires: None,
+                body: "hello".into(),
+            })
+        };
+        let nonces =
+            |messages: Vec<Arc<Envelope>>| messages.iter().map(|e| e.nonce).collect::<Vec<_>>();
+
+        let mut history = History::new(3);
+        for nonce in [1, 2, 4, 3, 5, 6] {
+            history.record(message(origin, nonce));
+        }
+        history.record(message(other, 1));
+        assert_eq!(nonces(history.since(origin, 0)), [4, 5, 6]);
+        assert_eq!(nonces(history.since(origin, 4)), [5, 6]);
+        assert!(history.since(origin, 6).is_empty());
+        assert_eq!(nonces(history.since(other, 0)), [1]);
+        assert_eq!(nonces(history.seqs(origin, 3..6)), [4, 5]);
+        assert!(history.seqs(other, 2..4).is_empty());
+        assert!(history
+            .since("127.0.0.1:8082".parse().unwrap(), 0)
+            .is_empty());
+
+        let mut history = History::new(0);
+        history.record(message(origin, 1));
+        assert!(history.since(origin, 0).is_empty());
+    }
+
+    #[test]
+    fn test_request_size() {
+        let origin = "[ffff::1%4294967295]:65535".parse().unwrap();
+        let request = crate::message::Message::HistoryRequest {
+            since: vec![(origin, u64::MAX); REQUEST_ORIGINS],
+        };
+        assert!(
+            bincode::serialized_size(&request).unwrap() <= crate::transfer::MAX_MESSAGE_LEN as u64
+        );
+    }
+}
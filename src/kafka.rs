@@ -0,0 +1,119 @@
+use crate::{handler::Handler, log::log, message::Envelope, producer::broadcast_message, Context};
+use chrono::{TimeZone, Utc};
+use core::net::SocketAddr;
+use futures::StreamExt;
+use rskafka::{
+    client::{
+        consumer::{StartOffset, StreamConsumerBuilder},
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        ClientBuilder,
+    },
+    record::Record,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::mpsc;
+
+/// The maximum number of messages waiting to be published.
+const QUEUE_LEN: usize = 1000;
+
+/// Connects to partition 0 of `topic` on the Kafka cluster of `brokers`,
+/// retrying until it succeeds. Logs errors on failure.
+async fn partition_client(brokers: Vec<String>, topic: String) -> Option<Arc<PartitionClient>> {
+    let res = async {
+        let client = ClientBuilder::new(brokers).build().await?;
+        client
+            .partition_client(topic, 0, UnknownTopicHandling::Retry)
+            .await
+    }
+    .await;
+    match res {
+        Ok(client) => Some(Arc::new(client)),
+        Err(e) => {
+            log(&[
+                b"Failed to connect to Kafka, error: ",
+                e.to_string().as_bytes(),
+            ]);
+            None
+        }
+    }
+}
+
+/// Publishes each received message to a Kafka topic, keyed by its origin.
+pub struct KafkaPublisher {
+    queue: mpsc::Sender<Arc<Envelope>>,
+}
+
+impl KafkaPublisher {
+    /// Publishes to partition 0 of `topic` on the Kafka cluster of `brokers`.
+    pub fn new(brokers: Vec<String>, topic: String) -> Self {
+        let (queue, receiver) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(async move {
+            if let Some(client) = partition_client(brokers, topic).await {
+                publish_loop(client, receiver).await;
+            }
+        });
+        Self { queue }
+    }
+}
+
+impl Handler for KafkaPublisher {
+    fn on_message(&self, _from: SocketAddr, envelope: &Arc<Envelope>) {
+        if self.queue.try_send(envelope.clone()).is_err() {
+            log(&[
+                b"Skipped publishing message [",
+                envelope.body.as_bytes(),
+                b"] to Kafka, the queue is full",
+            ]);
+        }
+    }
+}
+
+/// Publishes the messages from `receiver` with `client`. Logs errors on failure.
+async fn publish_loop(client: Arc<PartitionClient>, mut receiver: mpsc::Receiver<Arc<Envelope>>) {
+    while let Some(envelope) = receiver.recv().await {
+        let record = Record {
+            key: Some(envelope.origin.to_string().into_bytes()),
+            value: Some(envelope.body.clone().into_bytes()),
+            headers: BTreeMap::from([
+                ("nonce".to_owned(), envelope.nonce.to_string().into_bytes()),
+                ("seq".to_owned(), envelope.seq.to_string().into_bytes()),
+            ]),
+            timestamp: Utc
+                .timestamp_millis_opt(envelope.timestamp as _)
+                .single()
+                .unwrap_or_else(Utc::now),
+        };
+        if let Err(e) = client
+            .produce(vec![record], Compression::NoCompression)
+            .await
+        {
+            log(&[
+                b"Failed to publish message [",
+                envelope.body.as_bytes(),
+                b"] to Kafka, error: ",
+                e.to_string().as_bytes(),
+            ]);
+        }
+    }
+}
+
+/// Sends each record published to partition 0 of `topic` on the Kafka cluster
+/// of `brokers` from now on to all peers as a new message. Logs errors on failure.
+pub async fn consume_loop(ctx: Context, brokers: Vec<String>, topic: String) {
+    let Some(client) = partition_client(brokers, topic).await else {
+        return;
+    };
+    let mut stream = StreamConsumerBuilder::new(client, StartOffset::Latest).build();
+    while let Some(res) = stream.next().await {
+        match res {
+            Ok((record, _high_watermark)) => {
+                let value = record.record.value.unwrap_or_default();
+                broadcast_message(&ctx, String::from_utf8_lossy(&value).into_owned()).await;
+            }
+            Err(e) => log(&[
+                b"Failed to consume from Kafka, error: ",
+                e.to_string().as_bytes(),
+            ]),
+        }
+    }
+}
@@ -0,0 +1,769 @@
+//! A peer of a gossip network over QUIC.
+//!
+//! The `p2p-gossip` binary runs `GossipNode`s configured by its options.
+//! To run a peer in another Tokio application, start a `GossipNode`
+//! with a `Config`, e.g.:
+//!
+//! ```no_run
+//! use p2p_gossip::{Config, GossipNode};
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let config = Config {
+//!     connect: vec!["10.0.0.1:9000".to_owned()],
+//!     ..Config::new(9000)
+//! };
+//! let node = GossipNode::start(config, Vec::new()).await?;
+//! node.broadcast("hello".to_owned()).await;
+//! node.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Applications on other executors start a `DetachedNode` instead,
+//! which runs on a Tokio runtime of its own.
+//!
+//! The log is process-wide, set up with `set_log_levels`, `set_log_format` and `set_syslog`.
+
+#[cfg(feature = "acme")]
+mod acme;
+mod address_rules;
+mod address_validation;
+mod admin;
+mod aggregation;
+mod audit;
+mod bench;
+mod blocklist;
+mod clock;
+mod config;
+mod connection;
+mod dedup;
+mod detached;
+mod drain;
+mod dscp;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod filter;
+mod flow_window;
+#[cfg(feature = "gossipsub")]
+mod gossipsub;
+mod handler;
+mod history;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod log;
+mod mailbox;
+mod membership;
+mod memory_budget;
+mod message;
+#[cfg(feature = "nats")]
+mod nats;
+mod node;
+mod observer;
+mod pacing;
+mod peer_list;
+mod peering;
+mod peers;
+mod producer;
+mod quarantine;
+mod receiver;
+mod reorder;
+mod rpc;
+mod sender;
+mod sequence;
+mod sessions;
+mod shard;
+mod socket;
+mod socks5;
+#[cfg(feature = "spiffe")]
+mod spiffe;
+mod state_dump;
+mod static_peers;
+mod stats;
+mod statsd;
+mod supervisor;
+mod throttle;
+mod token_bucket;
+mod topology;
+mod transfer;
+mod utils;
+
+pub use address_rules::AdvertisePrivate;
+pub use admin::{print_peers, print_response};
+pub use audit::{parse_time, query as query_audit_log, AuditQuery};
+pub use bench::run as run_bench;
+pub use blocklist::Blocked;
+pub use config::Congestion;
+pub use detached::DetachedNode;
+pub use filter::Rule;
+pub use handler::{Handler, HandlerKind};
+pub use log::{
+    log, set_log_format, set_log_levels, set_syslog, LogFormat, LogLevels, LogTarget, Syslog,
+};
+pub use message::Envelope;
+pub use shard::MAX_SHARDS;
+pub use static_peers::{
+    export_peers, import_peers, merge_into_peers_file, read_peers_file, PeerFormat,
+};
+pub use utils::{PortRange, ScopedIp};
+
+use address_rules::GossipScores;
+use address_validation::AddressValidation;
+use aggregation::Aggregator;
+use audit::{AuditEvent, AuditLog, AuditRecord};
+use blocklist::Blocklist;
+use clock::ClockEstimate;
+use core::{net::SocketAddr, time::Duration};
+use dedup::DedupCache;
+use drain::Drain;
+use handler::KvStore;
+use history::History;
+use mailbox::{Mailbox, MailboxLimits};
+use membership::PartitionDetector;
+use memory_budget::{Charged, MemoryBudget};
+use message::{NonceGenerator, Queued, ReplayGuard};
+use node::{shut_down, start_node};
+use observer::Observer;
+use peers::PeerTable;
+use producer::broadcast_message;
+use quarantine::Quarantine;
+use quinn::{Connection, Endpoint};
+use reorder::Reorderer;
+use rpc::{Calls, ExecResponder};
+use sequence::{SequenceGenerator, SequenceTracker};
+use sessions::SessionTracker;
+use shard::shard_of;
+use static_peers::StaticPeers;
+use stats::Stats;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use supervisor::Supervisor;
+use throttle::Throttle;
+use token_bucket::TokenBucket;
+use tokio::sync::{broadcast, Mutex, Notify};
+
+/// The number of messages queued for the peers, beyond which
+/// the peers which did not take them miss the oldest ones.
+const MESSAGE_CHANNEL_CAPACITY: usize = 16;
+
+/// State shared by all the tasks of a peer.
+#[derive(Clone)]
+struct Context {
+    /// The endpoints sharing the listen address, one with `--endpoints 1`.
+    endpoints: Arc<[Endpoint]>,
+    /// The endpoints to make outgoing connections with,
+    /// differ from `endpoints` when connecting through a proxy.
+    dialers: Arc<[Endpoint]>,
+    peers: Arc<PeerTable>,
+    message_sender: broadcast::Sender<Charged<Queued>>,
+    /// Notified when a connection subscribes to `message_sender`.
+    subscribed: Arc<Notify>,
+    /// Generates the nonces of the messages created by this peer.
+    nonces: Arc<NonceGenerator>,
+    /// Generates the sequence numbers of the messages created by this peer.
+    seqs: Arc<SequenceGenerator>,
+    replay_guard: Arc<Mutex<ReplayGuard>>,
+    /// Sequence numbers received from each origin.
+    sequences: Arc<Mutex<SequenceTracker>>,
+    retransmit_gaps: bool,
+    message_ttl: Option<Duration>,
+    /// Clock offset estimates of the connected peers.
+    clocks: Arc<Mutex<HashMap<SocketAddr, ClockEstimate>>>,
+    ping_interval: Duration,
+    /// Established connections by the listen addresses of the peers.
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    sessions: Arc<Mutex<SessionTracker>>,
+    aggregator: Arc<Mutex<Aggregator>>,
+    quarantine: Arc<Mutex<Quarantine>>,
+    /// Scores of the addresses gossiped by the peers, to ignore the peer lists
+    /// of the ones gossiping invalid addresses.
+    gossip_scores: Arc<Mutex<GossipScores>>,
+    partitions: Arc<Mutex<PartitionDetector>>,
+    advertise_private: AdvertisePrivate,
+    /// The budget of reconnection attempts, shared by all peers.
+    retry_budget: Arc<Mutex<TokenBucket>>,
+    /// The budget of incoming connections, shared by all endpoints, with `--accept-rate`.
+    accept_budget: Option<Arc<Mutex<TokenBucket>>>,
+    /// Set with `--max-upload` and `--max-peer-upload`.
+    upload: Arc<Throttle>,
+    /// Set with `--max-download` and `--max-peer-download`.
+    download: Arc<Throttle>,
+    max_transfer_size: usize,
+    address_validation: Arc<AddressValidation>,
+    dial_timeout: Duration,
+    /// The limit of `FlowWindow` of each peer.
+    flow_window: usize,
+    adaptive_pacing: bool,
+    /// Charged with the messages queued for and not yet acknowledged by the peers.
+    memory_budget: Arc<MemoryBudget>,
+    mailbox_limits: MailboxLimits,
+    /// Messages for the peers being reconnected to, by their listen addresses.
+    mailboxes: Arc<Mutex<HashMap<SocketAddr, Mailbox>>>,
+    /// Recent messages, sent to peers which were away on their request.
+    history: Option<Arc<Mutex<History>>>,
+    /// Set with `--dedup-cache`.
+    dedup: Option<Arc<Mutex<DedupCache>>>,
+    /// Peers whose connections timed out, asked for the messages missed once they are back.
+    reconnecting: Arc<Mutex<HashSet<SocketAddr>>>,
+    stats: Arc<Stats>,
+    /// Started on shutdown.
+    drain: Arc<Drain>,
+    /// Runs the tasks, cancelled on shutdown.
+    supervisor: Arc<Supervisor>,
+    seed_mode: bool,
+    receive_only: bool,
+    relay_only: bool,
+    forward: bool,
+    max_hops: u32,
+    verify_peer_identity: bool,
+    observer: Option<Arc<Observer>>,
+    /// Set with `--audit-log`.
+    audit: Option<Arc<AuditLog>>,
+    /// Rules to filter received messages by, editable with the admin API.
+    filters: Arc<Mutex<Vec<Rule>>>,
+    handlers: Arc<Vec<Arc<dyn Handler>>>,
+    /// Requests sent awaiting their responses.
+    calls: Arc<Calls<String>>,
+    /// Overlay pings sent awaiting their pongs, which carry the hop counts of the pings.
+    pings: Arc<Calls<u32>>,
+    /// Route traces sent awaiting their replies, which carry the traced paths.
+    traces: Arc<Calls<Vec<SocketAddr>>>,
+    /// Neighbor queries sent awaiting their replies.
+    neighbor_queries: Arc<Calls<Vec<SocketAddr>>>,
+    /// Set with `--on-request`.
+    responder: Option<Arc<ExecResponder>>,
+    request_timeout: Duration,
+    /// Set with `--ordered-delivery`.
+    reorderer: Option<Arc<Mutex<Reorderer<Delivery>>>>,
+    /// Set with `--handler kv`.
+    kv_store: Option<Arc<KvStore>>,
+    bootstrap: Vec<Arc<Bootstrap>>,
+    /// Set with `--pin`.
+    pinned: Arc<HashSet<SocketAddr>>,
+    /// Read from `--peers-file`, kept connected to like pinned peers.
+    static_peers: Arc<RwLock<StaticPeers>>,
+    /// Managed with the `block` and `unblock` admin commands.
+    blocklist: Arc<Mutex<Blocklist>>,
+    /// Set with `--external-address`.
+    external_addr: Option<SocketAddr>,
+    /// Set with `--advertise`.
+    alternates: Arc<[SocketAddr]>,
+}
+
+impl Context {
+    /// The address the node is known by to its peers,
+    /// `--external-address` or the one the endpoints listen on.
+    fn local_addr(&self) -> SocketAddr {
+        self.external_addr.unwrap_or_else(|| self.bind_addr())
+    }
+
+    /// The address the endpoints listen on.
+    fn bind_addr(&self) -> SocketAddr {
+        self.endpoints[0].local_addr().unwrap()
+    }
+
+    /// The endpoint to connect to the peer on `remote_addr` from.
+    fn dialer(&self, remote_addr: SocketAddr) -> &Endpoint {
+        &self.dialers[shard_of(remote_addr, self.dialers.len())]
+    }
+
+    /// Whether the peer on `remote_addr` is blocked, unless it is pinned.
+    async fn is_blocked(&self, remote_addr: SocketAddr) -> bool {
+        !self.is_pinned(remote_addr) && self.blocklist.lock().await.contains(remote_addr)
+    }
+
+    /// Whether the peer on `remote_addr` is pinned or listed in the `--peers-file`.
+    fn is_pinned(&self, remote_addr: SocketAddr) -> bool {
+        self.pinned.contains(&remote_addr)
+            || self.static_peers.read().unwrap().contains_key(&remote_addr)
+    }
+}
+
+/// A message to pass to `Context::handlers` along with the peer it is received from,
+/// or `None` for a filtered out one, held back by `Context::reorderer`.
+type Delivery = Option<(SocketAddr, Arc<Envelope>)>;
+
+/// A host passed with `--connect`.
+struct Bootstrap {
+    host: String,
+    /// The address the host was last reached at,
+    /// used to re-resolve the host on reconnect.
+    addr: Mutex<Option<SocketAddr>>,
+}
+
+/// The configuration of a `GossipNode`, with a field for each option
+/// of the `p2p-gossip` binary which concerns a single node.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Period, once in this period a random message is sent to all peers.
+    pub period: Option<Duration>,
+    /// Only serve peer lists to other peers, neither sending nor logging messages.
+    pub seed_mode: bool,
+    /// Only receive messages, neither sending messages nor being advertised to other peers.
+    pub receive_only: bool,
+    /// Only forward received messages to the other peers, without logging them.
+    pub relay_only: bool,
+    /// Forward received messages to the other peers besides delivering them,
+    /// so that they reach the peers not connected to their origins.
+    pub forward: bool,
+    /// Maximum number of links a message is forwarded over from its origin,
+    /// with `forward` or `relay_only`.
+    pub max_hops: u32,
+    /// Rules to filter received messages by, e.g. `drop payload=^spam`.
+    /// The action is `drop`, `no-deliver` or `no-forward`, the matcher
+    /// is `sender=IP:port` or `payload=REGEX`.
+    pub filter: Vec<Rule>,
+    /// Handlers of received messages.
+    pub handler: Vec<HandlerKind>,
+    /// Shell command to run on each received message, with the body on stdin
+    /// and `GOSSIP_PEER`, `GOSSIP_ORIGIN`, `GOSSIP_NONCE`, `GOSSIP_SEQ`
+    /// and `GOSSIP_TIMESTAMP` environment variables.
+    pub on_message: Option<String>,
+    /// Maximum number of `on_message` commands running at once.
+    pub on_message_concurrency: usize,
+    /// Maximum number of `on_message` commands started per second, messages over it are skipped.
+    pub on_message_rate: f64,
+    /// Shell command to answer the requests of the `request` admin command of other peers with,
+    /// with the request on stdin and `GOSSIP_ORIGIN` environment variable.
+    /// Its output is the response.
+    pub on_request: Option<String>,
+    /// Time to wait for the response to a request.
+    pub request_timeout: Duration,
+    /// URL to POST each received message to as JSON.
+    pub webhook: Option<reqwest::Url>,
+    /// Maximum number of messages waiting to be POSTed to `webhook`, messages over it are skipped.
+    pub webhook_queue: usize,
+    /// Kafka brokers to bridge messages with, comma-separated.
+    #[cfg(feature = "kafka")]
+    pub kafka_brokers: Vec<String>,
+    /// Kafka topic to publish received messages to, to partition 0.
+    #[cfg(feature = "kafka")]
+    pub kafka_publish: Option<String>,
+    /// Kafka topic to send new records of to all peers, from partition 0.
+    #[cfg(feature = "kafka")]
+    pub kafka_consume: Option<String>,
+    /// Addresses to accept libp2p gossipsub connections on, e.g. `/ip4/0.0.0.0/tcp/4001`.
+    #[cfg(feature = "gossipsub")]
+    pub gossipsub_listen: Vec<libp2p::Multiaddr>,
+    /// Addresses of libp2p gossipsub nodes to connect to.
+    #[cfg(feature = "gossipsub")]
+    pub gossipsub_dial: Vec<libp2p::Multiaddr>,
+    /// Gossipsub topic to bridge messages with.
+    #[cfg(feature = "gossipsub")]
+    pub gossipsub_topic: String,
+    /// NATS server to bridge messages with, e.g. `nats://127.0.0.1:4222`.
+    #[cfg(feature = "nats")]
+    pub nats: Option<String>,
+    /// NATS subject to publish received messages to.
+    #[cfg(feature = "nats")]
+    pub nats_publish: Option<String>,
+    /// NATS subject to send the messages of to all peers.
+    #[cfg(feature = "nats")]
+    pub nats_subscribe: Option<String>,
+    /// Domain to obtain a certificate for from an ACME server, written to `cert` and `key`
+    /// and renewed when it is 60 days old. A self-signed certificate is used until then.
+    #[cfg(feature = "acme")]
+    pub acme_domain: Option<String>,
+    /// Contact URI of the ACME account, e.g. `mailto:admin@example.com`.
+    #[cfg(feature = "acme")]
+    pub acme_contact: Option<String>,
+    /// Directory URL of the ACME server.
+    #[cfg(feature = "acme")]
+    pub acme_directory: String,
+    /// Address to answer HTTP-01 challenges on, port 80 has to be forwarded to it.
+    #[cfg(feature = "acme")]
+    pub acme_http_listen: SocketAddr,
+    /// Path to the file the ACME account credentials are kept in.
+    #[cfg(feature = "acme")]
+    pub acme_account: PathBuf,
+    /// Address of the SPIFFE Workload API, e.g. `unix:///tmp/spire-agent/public/api.sock`,
+    /// to fetch the certificate from instead of `cert` and `key`. It is rotated as SVIDs are.
+    #[cfg(feature = "spiffe")]
+    pub spiffe_socket: Option<String>,
+    /// Verify peers' certificates against the bundle of this SPIFFE trust domain
+    /// and require their SPIFFE IDs to be members of it.
+    #[cfg(feature = "spiffe")]
+    pub spiffe_trust_domain: Option<String>,
+    /// Log every stream sent or received, with its type and size.
+    pub observe: bool,
+    /// Path to a file to capture the streams logged with `observe` to.
+    pub capture: Option<PathBuf>,
+    /// Path to a file to record every message sent, received, forwarded and dropped to,
+    /// read with `p2p-gossip audit query`.
+    pub audit_log: Option<PathBuf>,
+    /// Size in bytes the audit log file is rotated at.
+    pub audit_log_size: u64,
+    /// Number of rotated audit log files kept, `PATH.1` being the newest.
+    pub audit_log_files: usize,
+    /// Path to a file to dump the state of the peer to on SIGUSR1, instead of the log.
+    pub state_dump: Option<PathBuf>,
+    /// Time after which the messages of this peer expire,
+    /// expired ones are neither delivered nor forwarded.
+    pub message_ttl: Option<Duration>,
+    /// Maximum age of received messages, older ones are rejected as replays.
+    pub max_message_age: Duration,
+    /// Maximum number of bytes sent to a peer but not yet acknowledged by it,
+    /// messages over it are not sent to that peer until it catches up.
+    pub flow_window: usize,
+    /// Send fewer messages to peers whose connections lose packets or whose RTT grows,
+    /// down to an eighth of them, and more again as the connections recover.
+    pub adaptive_pacing: bool,
+    /// Maximum number of bytes of messages queued for or not yet acknowledged by all peers,
+    /// new messages wait for them to drain beyond it.
+    pub memory_budget: usize,
+    /// Maximum number of messages kept for a peer being reconnected to,
+    /// sent to it once it is back, 0 disables keeping them.
+    pub mailbox_messages: usize,
+    /// Maximum number of bytes of messages kept for a peer being reconnected to.
+    pub mailbox_bytes: usize,
+    /// Time messages are kept for a peer being reconnected to.
+    pub mailbox_age: Duration,
+    /// Number of recent messages of each origin kept to send to peers which were away
+    /// on their request, 0 disables both keeping and requesting them.
+    pub history: usize,
+    /// Number of recent message bodies remembered, to drop the messages of any origin
+    /// repeating one of them, e.g. ones handlers pass on, 0 disables it.
+    pub dedup_cache: usize,
+    /// Ask the peer a message comes from for the messages of its origin found missing
+    /// by their sequence numbers, which it sends from its `history`.
+    pub retransmit_gaps: bool,
+    /// Deliver the messages of each origin in the order of their sequence numbers,
+    /// holding back the ones received past a gap until it is filled.
+    pub ordered_delivery: bool,
+    /// Maximum number of messages of an origin held back for a gap
+    /// with `ordered_delivery`, beyond it the gap is skipped.
+    pub reorder_window: usize,
+    /// Period, once in this period peers are pinged to estimate their clock offsets.
+    pub ping_interval: Duration,
+    /// Period, once in this period the membership views of the peers are compared
+    /// by their digests. Peers they keep seeing for two periods without this node knowing them,
+    /// members of another partition which peer exchange did not bridge, are connected to.
+    pub membership_interval: Duration,
+    /// Period, once in this period aggregation state is gossiped to a random peer.
+    pub aggregation_interval: Duration,
+    /// Length of an aggregation epoch, after which aggregation starts over.
+    pub aggregation_epoch: Duration,
+    /// Number of disconnections within `flap_window` after which a peer is quarantined.
+    pub flap_threshold: usize,
+    /// Length of the window in which disconnections of a peer are counted.
+    pub flap_window: Duration,
+    /// Time a quarantined peer is neither dialed nor sent messages for.
+    pub quarantine: Duration,
+    /// Time to wait on shutdown for the messages queued
+    /// to be sent and acknowledged, while not taking new peers or messages.
+    pub drain_timeout: Duration,
+    /// Number of reconnection attempts allowed at once, further ones are spread out by `retry_rate`.
+    pub retry_burst: u32,
+    /// Number of reconnection attempts allowed per second, over all peers.
+    pub retry_rate: f64,
+    /// Number of incoming connections taken at once with `accept_rate`,
+    /// further ones are spread out by it.
+    pub accept_burst: u32,
+    /// Number of incoming connections taken per second, over all endpoints.
+    /// The others wait, so that a flood of joining peers does not starve
+    /// the connected ones. Unlimited by default.
+    pub accept_rate: Option<f64>,
+    /// Maximum number of bytes of messages sent per second, over all peers.
+    /// Unlimited by default.
+    pub max_upload: Option<u32>,
+    /// Maximum number of bytes of messages received per second, over all peers.
+    /// Peers are slowed down by QUIC flow control meanwhile. Unlimited by default.
+    pub max_download: Option<u32>,
+    /// Maximum number of bytes of messages sent per second to each peer.
+    pub max_peer_upload: Option<u32>,
+    /// Maximum number of bytes of messages received per second from each peer.
+    pub max_peer_download: Option<u32>,
+    /// Maximum size in bytes of message bodies, larger ones are neither sent nor received.
+    /// Messages over 1 KiB are sent with a header of their size and hash, which
+    /// peers of versions not supporting them fail to receive.
+    pub max_transfer_size: usize,
+    /// Number of handshakes in progress from which joining peers are required
+    /// to validate their addresses with a stateless retry, costing them a round trip,
+    /// e.g. `0` to always require it, and for a minute after the threshold
+    /// was last reached otherwise. Not required by default.
+    pub retry_threshold: Option<usize>,
+    /// Address to serve the admin API on, e.g. `127.0.0.1:9000`.
+    pub admin: Option<SocketAddr>,
+    /// StatsD server to emit counters and timers to over UDP, e.g. `127.0.0.1:8125`.
+    pub statsd: Option<String>,
+    /// Prefix of the names of the metrics emitted to StatsD.
+    pub statsd_prefix: String,
+    /// Fraction of the metrics emitted to StatsD, between 0 and 1.
+    pub statsd_sample_rate: f64,
+    /// Period, once in this period the QUIC path statistics
+    /// of each connection are emitted to `statsd`.
+    pub path_stats_interval: Duration,
+    /// IP to run on, IPv6 addresses may have a scope, e.g. `fe80::1%eth0`.
+    pub ip: ScopedIp,
+    /// Network interface to run on, instead of `ip`.
+    pub interface: Option<String>,
+    /// Port to run on, or a range of ports to try in turn, e.g. `9000-9010`.
+    pub port: PortRange,
+    /// Address to advertise to peers in place of the one bound to, e.g. the public
+    /// address forwarded to the node by a static NAT.
+    pub external_address: Option<SocketAddr>,
+    /// Other addresses the node is reachable on, e.g. on a LAN or over IPv6, up to 8.
+    /// Dialers try the advertised address and then these in the order given.
+    pub advertise: Vec<SocketAddr>,
+    /// Addresses of the first nodes to connect to, either `IP:port` or `host:port`,
+    /// connected to concurrently.
+    pub connect: Vec<String>,
+    /// Addresses of peers to always stay connected to. Pinned peers
+    /// are never quarantined and are redialed for as long as the node runs,
+    /// even after they close the connection.
+    pub pin: Vec<SocketAddr>,
+    /// Path to a file of peers to always stay connected to like `pin`, one address
+    /// per line, optionally followed by an alias. Read again when it changes.
+    pub peers_file: Option<PathBuf>,
+    /// Period, once in this period the `peers_file` is checked for changes.
+    pub peers_file_refresh: Duration,
+    /// Path to the file the peers blocked with the `block` admin command are kept in,
+    /// one IP or `IP:port` address per line. Without it, blocks last until the node exits.
+    pub blocklist: Option<PathBuf>,
+    /// Which peers private (RFC 1918), unique local and link-local addresses are sent to
+    /// and taken from in peer lists: `lan-only` for the peers on such addresses themselves.
+    pub advertise_private: AdvertisePrivate,
+    /// Allow other sockets to bind to the same port, with SO_REUSEPORT.
+    pub reuse_port: bool,
+    /// Number of QUIC endpoints sharing the port, e.g. one per CPU core, to process
+    /// packets in parallel. Peers are spread over the endpoints. Linux only.
+    pub endpoints: u8,
+    /// Send buffer size of the socket in bytes, instead of the system default.
+    /// Limited by `net.core.wmem_max` on Linux.
+    pub send_buffer: Option<usize>,
+    /// Receive buffer size of the socket in bytes, instead of the system default,
+    /// larger ones avoid drops under high message rates.
+    /// Limited by `net.core.rmem_max` on Linux.
+    pub recv_buffer: Option<usize>,
+    /// DSCP value to mark the packets sent with, from 0 to 63, e.g. `46` for expedited forwarding.
+    /// The packets are then sent without ECN marking and segmentation offload.
+    pub dscp: Option<u8>,
+    /// Do not use generic segmentation offload (GSO) to send packets, where supported.
+    pub no_gso: bool,
+    /// Do not use generic receive offload (GRO) to receive packets, where supported.
+    pub no_gro: bool,
+    /// Do not mark packets with explicit congestion notification (ECN), for networks
+    /// which drop marked packets. Peers then stop marking theirs too.
+    pub no_ecn: bool,
+    /// Congestion controller of the connections.
+    pub congestion_controller: Congestion,
+    /// Initial congestion window in bytes, larger ones suit high bandwidth-delay links.
+    pub initial_window: Option<u64>,
+    /// Time to wait for an outgoing connection to be established.
+    pub dial_timeout: Duration,
+    /// Do not verify peers' TLS certificates.
+    pub skip_server_verification: bool,
+    /// Verify peers' TLS certificates, but not the hostnames they are issued for.
+    pub skip_hostname_verification: bool,
+    /// Path to the certificate PEM file.
+    pub cert: PathBuf,
+    /// Path to the secret key PEM file.
+    pub key: PathBuf,
+    /// Generate a self-signed certificate in memory if `cert` or `key` does not exist,
+    /// instead of failing, e.g. for first runs and throwaway test nodes.
+    pub ephemeral_cert: bool,
+    /// Path to a PKCS#12 bundle with the certificate chain and the secret key,
+    /// instead of `cert` and `key`.
+    pub identity_p12: Option<PathBuf>,
+    /// Path to a file with the passphrase of an encrypted `key` or of `identity_p12`,
+    /// otherwise it is read from the `P2P_GOSSIP_KEY_PASS` environment variable or prompted for.
+    pub key_pass_file: Option<PathBuf>,
+    /// Present the certificate to the peers connected to and require the peers connecting
+    /// to present certificates issued for the IP or the hostname of the listen address
+    /// they advertise, so that no peer can pose as another one.
+    pub verify_peer_identity: bool,
+    /// Path to a PEM file with certificate revocation lists to check the certificates
+    /// of the peers connected to against, and with `verify_peer_identity`
+    /// the ones of the peers connecting too.
+    pub crl: Option<PathBuf>,
+    /// Period, once in this period the `crl` file is read again.
+    pub crl_refresh: Duration,
+    /// SOCKS5 proxy to route outgoing connections through, e.g. `socks5://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+}
+
+impl Config {
+    /// A node on `port` with the defaults of the options of the binary.
+    pub fn new(port: u16) -> Self {
+        Self {
+            period: None,
+            seed_mode: false,
+            receive_only: false,
+            relay_only: false,
+            forward: false,
+            max_hops: 16,
+            filter: Vec::new(),
+            handler: vec![HandlerKind::Logger],
+            on_message: None,
+            on_message_concurrency: 4,
+            on_message_rate: 10.0,
+            on_request: None,
+            request_timeout: Duration::from_secs(10),
+            webhook: None,
+            webhook_queue: 1000,
+            #[cfg(feature = "kafka")]
+            kafka_brokers: Vec::new(),
+            #[cfg(feature = "kafka")]
+            kafka_publish: None,
+            #[cfg(feature = "kafka")]
+            kafka_consume: None,
+            #[cfg(feature = "gossipsub")]
+            gossipsub_listen: Vec::new(),
+            #[cfg(feature = "gossipsub")]
+            gossipsub_dial: Vec::new(),
+            #[cfg(feature = "gossipsub")]
+            gossipsub_topic: "p2p-gossip".to_owned(),
+            #[cfg(feature = "nats")]
+            nats: None,
+            #[cfg(feature = "nats")]
+            nats_publish: None,
+            #[cfg(feature = "nats")]
+            nats_subscribe: None,
+            #[cfg(feature = "acme")]
+            acme_domain: None,
+            #[cfg(feature = "acme")]
+            acme_contact: None,
+            #[cfg(feature = "acme")]
+            acme_directory: instant_acme::LetsEncrypt::Production.url().to_owned(),
+            #[cfg(feature = "acme")]
+            acme_http_listen: "0.0.0.0:80".parse().unwrap(),
+            #[cfg(feature = "acme")]
+            acme_account: PathBuf::from("acme-account.json"),
+            #[cfg(feature = "spiffe")]
+            spiffe_socket: None,
+            #[cfg(feature = "spiffe")]
+            spiffe_trust_domain: None,
+            observe: false,
+            capture: None,
+            audit_log: None,
+            audit_log_size: 67108864,
+            audit_log_files: 4,
+            state_dump: None,
+            message_ttl: None,
+            max_message_age: Duration::from_secs(60),
+            flow_window: 65536,
+            adaptive_pacing: false,
+            memory_budget: 67108864,
+            mailbox_messages: 1000,
+            mailbox_bytes: 1048576,
+            mailbox_age: Duration::from_secs(300),
+            history: 100,
+            dedup_cache: 0,
+            retransmit_gaps: false,
+            ordered_delivery: false,
+            reorder_window: 64,
+            ping_interval: Duration::from_secs(10),
+            membership_interval: Duration::from_secs(30),
+            aggregation_interval: Duration::from_secs(1),
+            aggregation_epoch: Duration::from_secs(60),
+            flap_threshold: 5,
+            flap_window: Duration::from_secs(60),
+            quarantine: Duration::from_secs(300),
+            drain_timeout: Duration::from_secs(5),
+            retry_burst: 10,
+            retry_rate: 2.0,
+            accept_burst: 20,
+            accept_rate: None,
+            max_upload: None,
+            max_download: None,
+            max_peer_upload: None,
+            max_peer_download: None,
+            max_transfer_size: 1048576,
+            retry_threshold: None,
+            admin: None,
+            statsd: None,
+            statsd_prefix: "p2p_gossip.".to_owned(),
+            statsd_sample_rate: 1.0,
+            path_stats_interval: Duration::from_secs(10),
+            ip: "127.0.0.1".parse().unwrap(),
+            interface: None,
+            port: PortRange(port..=port),
+            external_address: None,
+            advertise: Vec::new(),
+            connect: Vec::new(),
+            pin: Vec::new(),
+            peers_file: None,
+            peers_file_refresh: Duration::from_secs(5),
+            blocklist: None,
+            advertise_private: AdvertisePrivate::Always,
+            reuse_port: false,
+            endpoints: 1,
+            send_buffer: None,
+            recv_buffer: None,
+            dscp: None,
+            no_gso: false,
+            no_gro: false,
+            no_ecn: false,
+            congestion_controller: Congestion::Cubic,
+            initial_window: None,
+            dial_timeout: Duration::from_secs(10),
+            skip_server_verification: false,
+            skip_hostname_verification: false,
+            cert: PathBuf::from("cert.pem"),
+            key: PathBuf::from("key.pem"),
+            ephemeral_cert: false,
+            identity_p12: None,
+            key_pass_file: None,
+            verify_peer_identity: false,
+            crl: None,
+            crl_refresh: Duration::from_secs(3600),
+            proxy: None,
+        }
+    }
+}
+
+/// A running node, which connects to its peers, sends them the messages
+/// passed to `broadcast` and passes the ones received to its handlers.
+pub struct GossipNode {
+    ctx: Context,
+    /// Set with `Config::drain_timeout`.
+    drain_timeout: Duration,
+}
+
+impl GossipNode {
+    /// Starts a node as per `config`, passing the received messages to `handlers`
+    /// besides the ones of `Config::handler`. It runs until it is shut down with `shutdown`.
+    pub async fn start(config: Config, handlers: Vec<Arc<dyn Handler>>) -> io::Result<Self> {
+        let drain_timeout = config.drain_timeout;
+        let ctx = start_node(config, handlers).await?;
+        Ok(Self { ctx, drain_timeout })
+    }
+
+    /// The address the node is known by to its peers.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.ctx.local_addr()
+    }
+
+    /// The address the node listens on.
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.ctx.bind_addr()
+    }
+
+    /// The listen addresses of the peers the node is connected to.
+    pub async fn peers(&self) -> Vec<SocketAddr> {
+        self.ctx.connections.lock().await.keys().copied().collect()
+    }
+
+    /// Sends a new message with `body` to all peers, if there are any,
+    /// waiting while the memory budget is exceeded.
+    pub async fn broadcast(&self, body: String) {
+        broadcast_message(&self.ctx, body).await;
+    }
+
+    /// Drains the node for up to `Config::drain_timeout` and stops it.
+    pub async fn shutdown(self) {
+        shut_down(&self.ctx, self.drain_timeout).await;
+    }
+}
+
+/// Records `event` of `envelope` sent to or received from the peer
+/// listening on `peer` to the audit log, if any, with the `reason` of a drop.
+fn audit(ctx: &Context, event: AuditEvent, peer: SocketAddr, envelope: &Envelope, reason: &str) {
+    if let Some(audit) = &ctx.audit {
+        audit.record(&AuditRecord::new(event, peer, envelope, reason));
+    }
+}
@@ -1,24 +1,350 @@
+use crate::{
+    audit::message_hash,
+    message::Envelope,
+    utils::{hex, hostname},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use core::{fmt, net::SocketAddr};
+use serde::Serialize;
 use std::{
-    io::{stdout, Write},
-    sync::OnceLock,
+    io::{self, stdout, Write},
+    net::UdpSocket,
+    os::unix::net::UnixDatagram,
+    process,
+    str::FromStr,
+    sync::{OnceLock, RwLock},
 };
 use tokio::time::Instant;
 
+/// Where log lines are written, selected with `--log-target`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogTarget {
+    Stdout,
+    /// The local syslog socket, or the server of `--syslog-server`.
+    Syslog,
+}
+
+/// How log lines written to stdout are formatted, selected with `--log-format`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// `HH:MM:SS - text`, with the time elapsed since the start.
+    Text,
+    /// A JSON object per line, with the time, the level, the event,
+    /// the peer and the ID of the message the line is about.
+    Json,
+}
+
+/// The verbosity of log lines.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, ValueEnum)]
+pub enum LogLevel {
+    Off,
+    Info,
+    Debug,
+}
+
+/// What log lines are about, each category with its own level.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogCategory {
+    /// Connections to the peers coming and going.
+    Membership,
+    /// Messages received, sent and recovered.
+    Messages,
+    /// The state of the connections.
+    Transport,
+    /// Everything else.
+    General,
+}
+
+impl LogCategory {
+    /// The category of log lines of `event`.
+    fn of(event: &str) -> Self {
+        match event {
+            "connect" | "accept_failed" | "disconnect" | "connect_failed" | "invalid_address"
+            | "partition_healed" | "quarantined" | "reconnect_gave_up" => Self::Membership,
+            "message_received" | "message_sent" | "rejected" | "gap" | "history_sent"
+            | "mailbox_flushed" | "out_of_order" | "snapshot" | "transfer_progress" => {
+                Self::Messages
+            }
+            "receive_failed" | "slow" | "caught_up" | "congested" | "recovered" | "pong" => {
+                Self::Transport
+            }
+            _ => Self::General,
+        }
+    }
+}
+
+/// The level of each log category, parsed from a comma-separated list
+/// of `LEVEL` for all categories or `CATEGORY=LEVEL`,
+/// e.g. `info,messages=debug,transport=off`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogLevels([LogLevel; 4]);
+
+impl LogLevels {
+    const DEFAULT: Self = Self([LogLevel::Info; 4]);
+
+    /// Changes the levels as per `spec`, leaving them as they are if it is invalid.
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let mut levels = self.0;
+        for item in spec.split(',') {
+            match item.split_once('=') {
+                Some((category, level)) => {
+                    levels[LogCategory::from_str(category, true)? as usize] =
+                        LogLevel::from_str(level, true)?;
+                }
+                None => levels = [LogLevel::from_str(item, true)?; 4],
+            }
+        }
+        self.0 = levels;
+        Ok(())
+    }
+
+    fn enabled(&self, category: LogCategory, level: LogLevel) -> bool {
+        level <= self.0[category as usize]
+    }
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = Self::default();
+        levels.apply(s)?;
+        Ok(levels)
+    }
+}
+
+impl fmt::Display for LogLevels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, category) in LogCategory::value_variants().iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(
+                f,
+                "{}={}",
+                category.to_possible_value().unwrap().get_name(),
+                self.0[*category as usize]
+                    .to_possible_value()
+                    .unwrap()
+                    .get_name()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+static LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::DEFAULT);
+
+/// The levels further log lines are written at.
+pub fn log_levels() -> LogLevels {
+    *LOG_LEVELS.read().unwrap()
+}
+
+pub fn set_log_levels(levels: LogLevels) {
+    *LOG_LEVELS.write().unwrap() = levels;
+}
+
+static LOG_FORMAT: RwLock<LogFormat> = RwLock::new(LogFormat::Text);
+
+pub fn set_log_format(format: LogFormat) {
+    *LOG_FORMAT.write().unwrap() = format;
+}
+
+/// The path of the local syslog socket.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The ID of the structured data element of log lines sent to syslog.
+/// 32473 is the enterprise number reserved for documentation (RFC 5612).
+const SD_ID: &str = "gossip@32473";
+
+/// Facility user (1).
+const SYSLOG_FACILITY: u8 = 1;
+
+static SYSLOG: OnceLock<Syslog> = OnceLock::new();
+
+enum SyslogSocket {
+    Local(UnixDatagram),
+    Remote(UdpSocket),
+}
+
+/// Sends log lines to syslog in the RFC 5424 format.
+pub struct Syslog {
+    socket: SyslogSocket,
+    hostname: String,
+    app_name: String,
+}
+
+impl Syslog {
+    /// Sends to the local syslog socket.
+    pub fn local() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SYSLOG_SOCKET)?;
+        Self::new(SyslogSocket::Local(socket))
+    }
+
+    /// Sends to the syslog server at `addr` over UDP.
+    pub fn remote(addr: SocketAddr) -> io::Result<Self> {
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local_addr)?;
+        socket.connect(addr)?;
+        Self::new(SyslogSocket::Remote(socket))
+    }
+
+    fn new(socket: SyslogSocket) -> io::Result<Self> {
+        Ok(Self {
+            socket,
+            hostname: hostname()?,
+            app_name: env!("CARGO_PKG_NAME").to_owned(),
+        })
+    }
+
+    fn send(
+        &self,
+        level: LogLevel,
+        event: Option<&str>,
+        peer: Option<SocketAddr>,
+        message_id: Option<&str>,
+        bufs: &[&[u8]],
+    ) {
+        let line = format_syslog(
+            Utc::now(),
+            level,
+            &self.hostname,
+            &self.app_name,
+            process::id(),
+            event,
+            peer,
+            message_id,
+            bufs,
+        );
+        // there is nowhere to report the failure to
+        let _ = match &self.socket {
+            SyslogSocket::Local(socket) => socket.send(&line),
+            SyslogSocket::Remote(socket) => socket.send(&line),
+        };
+    }
+}
+
+/// Sends all further log lines to `syslog` instead of stdout.
+pub fn set_syslog(syslog: Syslog) {
+    let _ = SYSLOG.set(syslog);
+}
+
 /// Prints `bufs` to stdout, formatted with the time
 /// elapsed since the program was started.
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// // prints "00:00:05 - onetwo\n"
 /// log(&[b"one", b"two"]);
 /// ```
 pub fn log(bufs: &[&[u8]]) {
+    write_log(LogLevel::Info, None, None, None, bufs);
+}
+
+/// Like `log`, but tagged with the type of the event and the peer it concerns,
+/// which are sent to syslog as structured data.
+///
+/// # Examples
+///
+/// ```ignore
+/// // prints "00:00:05 - Connected to 127.0.0.1:8080\n"
+/// log_event("connect", addr, &[b"Connected to ", addr.to_string().as_bytes()]);
+/// ```
+pub fn log_event(event: &str, peer: SocketAddr, bufs: &[&[u8]]) {
+    write_log(LogLevel::Info, Some(event), Some(peer), None, bufs);
+}
+
+/// Like `log_event`, but only written in the JSON format,
+/// for the events the plain text has no line for.
+pub fn json_event(event: &str, peer: SocketAddr, bufs: &[&[u8]]) {
+    if SYSLOG.get().is_none() && *LOG_FORMAT.read().unwrap() == LogFormat::Json {
+        log_event(event, peer, bufs);
+    }
+}
+
+/// Like `log_event`, but only written when the category of `event` is at the debug level.
+pub fn debug_event(event: &str, peer: SocketAddr, bufs: &[&[u8]]) {
+    write_log(LogLevel::Debug, Some(event), Some(peer), None, bufs);
+}
+
+/// Like `log`, but tagged with `event` and the ID of the message `envelope`
+/// it is about, for the lines about a message concerning no single peer.
+pub fn log_message(event: &str, envelope: &Envelope, bufs: &[&[u8]]) {
+    write_log(LogLevel::Info, Some(event), None, Some(envelope), bufs);
+}
+
+/// Like `log_event`, but also tagged with the ID of the message `envelope`
+/// it is about, the hash the audit log records it by.
+pub fn log_message_event(event: &str, peer: SocketAddr, envelope: &Envelope, bufs: &[&[u8]]) {
+    write_log(
+        LogLevel::Info,
+        Some(event),
+        Some(peer),
+        Some(envelope),
+        bufs,
+    );
+}
+
+/// Like `log_message_event`, but only written when the category of `event` is at the debug level.
+pub fn debug_message_event(event: &str, peer: SocketAddr, envelope: &Envelope, bufs: &[&[u8]]) {
+    write_log(
+        LogLevel::Debug,
+        Some(event),
+        Some(peer),
+        Some(envelope),
+        bufs,
+    );
+}
+
+fn write_log(
+    level: LogLevel,
+    event: Option<&str>,
+    peer: Option<SocketAddr>,
+    message: Option<&Envelope>,
+    bufs: &[&[u8]],
+) {
     static START_TIME: OnceLock<Instant> = OnceLock::new();
 
-    let time = format_duration(START_TIME.get_or_init(Instant::now).elapsed().as_secs());
+    let category = event.map_or(LogCategory::General, LogCategory::of);
+    if !log_levels().enabled(category, level) {
+        return;
+    }
+
+    let format = *LOG_FORMAT.read().unwrap();
+    let syslog = SYSLOG.get();
+    // the plain text has no place for it
+    let message_id = message
+        .filter(|_| syslog.is_some() || format == LogFormat::Json)
+        .map(|envelope| hex(&message_hash(envelope)));
+
+    if let Some(syslog) = syslog {
+        syslog.send(level, event, peer, message_id.as_deref(), bufs);
+        return;
+    }
 
     let mut out = stdout().lock();
+    if format == LogFormat::Json {
+        let line = format_json(Utc::now(), level, event, peer, message_id.as_deref(), bufs);
+        out.write_all(&line).unwrap();
+        out.write_all(b"\n").unwrap();
+        return;
+    }
+
+    let time = format_duration(START_TIME.get_or_init(Instant::now).elapsed().as_secs());
+
     out.write_all(time.as_bytes()).unwrap();
     out.write_all(b" - ").unwrap();
     for buf in bufs {
@@ -27,11 +353,89 @@ pub fn log(bufs: &[&[u8]]) {
     out.write_all(b"\n").unwrap();
 }
 
+/// Formats a syslog message as per RFC 5424, with `event` as the message ID
+/// and `event`, `peer` and `message_id` as the parameters of the structured data.
+#[allow(clippy::too_many_arguments)]
+fn format_syslog(
+    time: DateTime<Utc>,
+    level: LogLevel,
+    hostname: &str,
+    app_name: &str,
+    pid: u32,
+    event: Option<&str>,
+    peer: Option<SocketAddr>,
+    message_id: Option<&str>,
+    bufs: &[&[u8]],
+) -> Vec<u8> {
+    let mut params = String::new();
+    if let Some(event) = event {
+        params += &format!(" event=\"{event}\"");
+    }
+    if let Some(peer) = peer {
+        params += &format!(" peer=\"{peer}\"");
+    }
+    if let Some(message_id) = message_id {
+        params += &format!(" message_id=\"{message_id}\"");
+    }
+    let structured_data = if params.is_empty() {
+        "-".to_owned()
+    } else {
+        format!("[{SD_ID}{params}]")
+    };
+    // severity informational (6) or debug (7)
+    let severity = if level == LogLevel::Debug { 7 } else { 6 };
+    let mut line = format!(
+        "<{}>1 {} {hostname} {app_name} {pid} {} {structured_data} ",
+        SYSLOG_FACILITY * 8 + severity,
+        time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        event.unwrap_or("-"),
+    )
+    .into_bytes();
+    for buf in bufs {
+        line.extend_from_slice(buf);
+    }
+    line
+}
+
+/// Formats a log line as a JSON object with the `timestamp`, the `level`,
+/// the `event`, the `peer` and the `message_id` there are and the `text`.
+fn format_json(
+    time: DateTime<Utc>,
+    level: LogLevel,
+    event: Option<&str>,
+    peer: Option<SocketAddr>,
+    message_id: Option<&str>,
+    bufs: &[&[u8]],
+) -> Vec<u8> {
+    #[derive(Serialize)]
+    struct Line<'a> {
+        timestamp: String,
+        level: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        event: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        peer: Option<SocketAddr>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message_id: Option<&'a str>,
+        text: String,
+    }
+
+    serde_json::to_vec(&Line {
+        timestamp: time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        level: level.to_possible_value().unwrap().get_name(),
+        event,
+        peer,
+        message_id,
+        text: String::from_utf8_lossy(&bufs.concat()).into_owned(),
+    })
+    .unwrap()
+}
+
 /// Formats a duration `seconds` in HH:MM:SS format.
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// assert_eq!(format_duration(5 * 60 * 60 + 12 * 60 + 7), "05:12:07");
 /// ```
 fn format_duration(seconds: u64) -> String {
@@ -53,4 +457,106 @@ mod tests {
         assert_eq!(format_duration(0), "00:00:00");
         assert_eq!(format_duration(67), "00:01:07");
     }
+
+    #[test]
+    fn test_format_syslog() {
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let peer = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(
+            format_syslog(
+                time,
+                LogLevel::Info,
+                "host",
+                "p2p-gossip",
+                42,
+                Some("connect"),
+                Some(peer),
+                None,
+                &[b"Connected to ", b"127.0.0.1:8080"],
+            ),
+            b"<14>1 2023-11-14T22:13:20.123Z host p2p-gossip 42 connect \
+              [gossip@32473 event=\"connect\" peer=\"127.0.0.1:8080\"] \
+              Connected to 127.0.0.1:8080"
+        );
+        assert_eq!(
+            format_syslog(
+                time,
+                LogLevel::Info,
+                "host",
+                "p2p-gossip",
+                42,
+                Some("message_received"),
+                Some(peer),
+                Some("ab12"),
+                &[b"Received message [hi]"],
+            ),
+            b"<14>1 2023-11-14T22:13:20.123Z host p2p-gossip 42 message_received \
+              [gossip@32473 event=\"message_received\" peer=\"127.0.0.1:8080\" message_id=\"ab12\"] \
+              Received message [hi]"
+        );
+        assert_eq!(
+            format_syslog(
+                time,
+                LogLevel::Debug,
+                "host",
+                "p2p-gossip",
+                42,
+                None,
+                None,
+                None,
+                &[b"Shutting down"]
+            ),
+            b"<15>1 2023-11-14T22:13:20.123Z host p2p-gossip 42 - - Shutting down"
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let peer = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(
+            String::from_utf8(format_json(
+                time,
+                LogLevel::Debug,
+                Some("message_sent"),
+                Some(peer),
+                Some("ab12"),
+                &[b"Sent message [\"hi\"] to ", b"127.0.0.1:8080"],
+            ))
+            .unwrap(),
+            r#"{"timestamp":"2023-11-14T22:13:20.123Z","level":"debug","event":"message_sent","#
+                .to_owned()
+                + r#""peer":"127.0.0.1:8080","message_id":"ab12","#
+                + r#""text":"Sent message [\"hi\"] to 127.0.0.1:8080"}"#
+        );
+        assert_eq!(
+            format_json(time, LogLevel::Info, None, None, None, &[b"Shutting down"]),
+            br#"{"timestamp":"2023-11-14T22:13:20.123Z","level":"info","text":"Shutting down"}"#
+        );
+    }
+
+    #[test]
+    fn test_log_levels() {
+        let mut levels = LogLevels::default();
+        assert!(levels.enabled(LogCategory::Messages, LogLevel::Info));
+        assert!(!levels.enabled(LogCategory::Messages, LogLevel::Debug));
+
+        levels.apply("messages=debug,Transport=off").unwrap();
+        assert!(levels.enabled(LogCategory::Messages, LogLevel::Debug));
+        assert!(!levels.enabled(LogCategory::Transport, LogLevel::Info));
+        assert_eq!(
+            levels.to_string(),
+            "membership=info,messages=debug,transport=off,general=info"
+        );
+
+        assert!(levels.apply("debug,membership=nope").is_err());
+        assert_eq!(levels.to_string().parse(), Ok(levels));
+        assert_eq!(
+            "debug,general=info".parse::<LogLevels>().unwrap().0[3],
+            LogLevel::Info
+        );
+        assert_eq!("off".parse::<LogLevels>().unwrap().0, [LogLevel::Off; 4]);
+        assert!("quiet".parse::<LogLevels>().is_err());
+        assert_eq!(LogCategory::of("connect"), LogCategory::Membership);
+    }
 }
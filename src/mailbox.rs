@@ -0,0 +1,139 @@
+use crate::{
+    memory_budget::Charged,
+    message::{Envelope, Queued},
+};
+use core::{mem, time::Duration};
+use std::collections::VecDeque;
+use tokio::time::Instant;
+
+/// The bounds of a `Mailbox`, the oldest messages are dropped beyond them.
+#[derive(Clone, Copy, Debug)]
+pub struct MailboxLimits {
+    pub messages: usize,
+    pub bytes: usize,
+    pub age: Duration,
+}
+
+/// Keeps the messages for a peer which is being reconnected to,
+/// to be sent to it once it is back.
+pub struct Mailbox {
+    limits: MailboxLimits,
+    messages: VecDeque<(Instant, Charged<Queued>)>,
+    bytes: usize,
+}
+
+impl Mailbox {
+    pub fn new(limits: MailboxLimits) -> Self {
+        Self {
+            limits,
+            messages: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Adds `message` received at `now`, dropping the oldest messages beyond the limits.
+    pub fn push(&mut self, message: Charged<Queued>, now: Instant) {
+        self.bytes += size(&message.value);
+        self.messages.push_back((now, message));
+        while self.messages.len() > self.limits.messages || self.bytes > self.limits.bytes {
+            self.pop_front();
+        }
+        self.prune(now);
+    }
+
+    /// Drops the messages older than the age limit at `now`.
+    fn prune(&mut self, now: Instant) {
+        while self
+            .messages
+            .front()
+            .is_some_and(|&(received, _)| now.duration_since(received) > self.limits.age)
+        {
+            self.pop_front();
+        }
+    }
+
+    fn pop_front(&mut self) {
+        if let Some((_, message)) = self.messages.pop_front() {
+            self.bytes -= size(&message.value);
+        }
+    }
+
+    /// The number of messages kept.
+    pub fn messages(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// The number of bytes of the messages kept.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Takes the messages not older than the age limit at `now`, oldest first.
+    pub fn drain(mut self, now: Instant) -> impl Iterator<Item = Queued> {
+        self.prune(now);
+        self.messages.into_iter().map(|(_, message)| message.value)
+    }
+}
+
+fn size(message: &Queued) -> usize {
+    mem::size_of::<Envelope>() + message.envelope.body.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_budget::MemoryBudget;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mailbox() {
+        let budget = MemoryBudget::new(usize::MAX);
+        let message = |nonce| {
+            let envelope = Envelope {
+                origin: "127.0.0.1:8080".parse().unwrap(),
+                nonce,
+                seq: nonce,
+                timestamp: 0,
+                expires: None,
+                body: "hello".into(),
+            };
+            let message = Queued {
+                envelope: Arc::new(envelope),
+                hops: 0,
+            };
+            Charged::new(message, budget.charge(0))
+        };
+        let nonces = |mailbox: Mailbox, now| {
+            mailbox
+                .drain(now)
+                .map(|m| m.envelope.nonce)
+                .collect::<Vec<_>>()
+        };
+        let limits = MailboxLimits {
+            messages: 3,
+            bytes: 10_000,
+            age: Duration::from_secs(60),
+        };
+        let now = Instant::now();
+
+        let mut mailbox = Mailbox::new(limits);
+        for nonce in 1..=5 {
+            mailbox.push(message(nonce), now);
+        }
+        assert_eq!(nonces(mailbox, now), [3, 4, 5]);
+
+        let mut mailbox = Mailbox::new(MailboxLimits {
+            bytes: 2 * size(&message(0).value),
+            ..limits
+        });
+        for nonce in 1..=3 {
+            mailbox.push(message(nonce), now);
+        }
+        assert_eq!(nonces(mailbox, now), [2, 3]);
+
+        let mut mailbox = Mailbox::new(limits);
+        mailbox.push(message(1), now);
+        mailbox.push(message(2), now + Duration::from_secs(30));
+        assert_eq!(nonces(mailbox, now + Duration::from_secs(61)), [2]);
+    }
+}
@@ -1,443 +1,843 @@
-mod config;
-mod error;
-mod log;
-mod utils;
-
-use backoff::ExponentialBackoff;
-use clap::Parser;
-use config::{configure_client_without_server_verification, read_certs_from_file};
-use core::{
-    net::{IpAddr, SocketAddr},
-    time::Duration,
-};
-use dns_lookup::lookup_addr;
-use error::{
-    is_already_open_or_locally_closed_error, is_already_open_or_locally_closed_reason, AppError,
-    AppResult,
+use clap::{Parser, Subcommand};
+use core::{net::SocketAddr, time::Duration};
+use futures::future::join_all;
+use p2p_gossip::{
+    export_peers, import_peers, log, merge_into_peers_file, parse_time, print_peers,
+    print_response, query_audit_log, read_peers_file, run_bench, set_log_format, set_log_levels,
+    set_syslog, AdvertisePrivate, AuditQuery, Blocked, Config, Congestion, GossipNode, HandlerKind,
+    LogFormat, LogLevels, LogTarget, PeerFormat, PortRange, Rule, ScopedIp, Syslog, MAX_SHARDS,
 };
-use futures::{future::BoxFuture, FutureExt};
-use log::log;
-use quinn::{ClientConfig, Connecting, Connection, ConnectionError, Endpoint, ServerConfig};
-use rand::{Rng, SeedableRng};
-use rand_pcg::Pcg64Mcg;
-use std::{collections::HashMap, io, path::PathBuf, sync::Arc};
-use tokio::{
-    signal,
-    sync::{broadcast, Mutex},
-    time::Instant,
+use std::{
+    io::{self, Write},
+    path::PathBuf,
 };
-use utils::{deserialize_addresses, format_peers, NotifyOnDrop};
+use tokio::{net::lookup_host, signal};
 
 // this doc comment is printed at the top of the help message
 /// P2P gossip peer.
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Period in seconds, once in this period a random message is sent to all peers.
     #[arg(long)]
-    period: Option<usize>,
-    /// IP to run on.
+    period: Option<u64>,
+    /// Only serve peer lists to other peers, neither sending nor logging messages.
+    #[arg(long, action, conflicts_with("period"))]
+    seed_mode: bool,
+    /// Only receive messages, neither sending messages nor being advertised to other peers.
+    #[arg(long, action, conflicts_with_all(["period", "seed_mode"]))]
+    receive_only: bool,
+    /// Only forward received messages to the other peers, without logging them.
+    #[arg(long, action, conflicts_with_all(["period", "seed_mode", "receive_only"]))]
+    relay_only: bool,
+    /// Forward received messages to the other peers besides delivering them,
+    /// so that they reach the peers not connected to their origins.
+    #[arg(long, action, conflicts_with_all(["seed_mode", "receive_only", "relay_only"]))]
+    forward: bool,
+    /// Maximum number of links a message is forwarded over from its origin,
+    /// with `--forward` or `--relay-only`.
+    #[arg(long, default_value("16"))]
+    max_hops: u32,
+    /// Rule to filter received messages by, e.g. `drop payload=^spam`, may be repeated.
+    /// The action is `drop`, `no-deliver` or `no-forward`, the matcher
+    /// is `sender=IP:port` or `payload=REGEX`.
+    #[arg(long)]
+    filter: Vec<Rule>,
+    /// Handler of received messages, may be repeated.
+    #[arg(long, value_enum, default_values_t = [HandlerKind::Logger])]
+    handler: Vec<HandlerKind>,
+    /// Shell command to run on each received message, with the body on stdin
+    /// and `GOSSIP_PEER`, `GOSSIP_ORIGIN`, `GOSSIP_NONCE`, `GOSSIP_SEQ`
+    /// and `GOSSIP_TIMESTAMP` environment variables.
+    #[arg(long)]
+    on_message: Option<String>,
+    /// Maximum number of `--on-message` commands running at once.
+    #[arg(long, default_value("4"))]
+    on_message_concurrency: usize,
+    /// Maximum number of `--on-message` commands started per second, messages over it are skipped.
+    #[arg(long, default_value("10"))]
+    on_message_rate: f64,
+    /// Shell command to answer the requests of the `request` admin command of other peers with,
+    /// with the request on stdin and `GOSSIP_ORIGIN` environment variable.
+    /// Its output is the response.
+    #[arg(long)]
+    on_request: Option<String>,
+    /// Time in seconds to wait for the response to a request.
+    #[arg(long, default_value("10"))]
+    request_timeout: u64,
+    /// URL to POST each received message to as JSON.
+    #[arg(long)]
+    webhook: Option<reqwest::Url>,
+    /// Maximum number of messages waiting to be POSTed to `--webhook`, messages over it are skipped.
+    #[arg(long, default_value("1000"))]
+    webhook_queue: usize,
+    /// Kafka brokers to bridge messages with, comma-separated.
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_delimiter(','))]
+    kafka_brokers: Vec<String>,
+    /// Kafka topic to publish received messages to, to partition 0.
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires("kafka_brokers"))]
+    kafka_publish: Option<String>,
+    /// Kafka topic to send new records of to all peers, from partition 0.
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires("kafka_brokers"))]
+    kafka_consume: Option<String>,
+    /// Address to accept libp2p gossipsub connections on, e.g. `/ip4/0.0.0.0/tcp/4001`, may be repeated.
+    #[cfg(feature = "gossipsub")]
+    #[arg(long)]
+    gossipsub_listen: Vec<libp2p::Multiaddr>,
+    /// Address of a libp2p gossipsub node to connect to, may be repeated.
+    #[cfg(feature = "gossipsub")]
+    #[arg(long)]
+    gossipsub_dial: Vec<libp2p::Multiaddr>,
+    /// Gossipsub topic to bridge messages with.
+    #[cfg(feature = "gossipsub")]
+    #[arg(long, default_value("p2p-gossip"))]
+    gossipsub_topic: String,
+    /// NATS server to bridge messages with, e.g. `nats://127.0.0.1:4222`.
+    #[cfg(feature = "nats")]
+    #[arg(long)]
+    nats: Option<String>,
+    /// NATS subject to publish received messages to.
+    #[cfg(feature = "nats")]
+    #[arg(long, requires("nats"))]
+    nats_publish: Option<String>,
+    /// NATS subject to send the messages of to all peers.
+    #[cfg(feature = "nats")]
+    #[arg(long, requires("nats"))]
+    nats_subscribe: Option<String>,
+    /// Domain to obtain a certificate for from an ACME server, written to `--cert` and `--key`
+    /// and renewed when it is 60 days old. A self-signed certificate is used until then.
+    #[cfg(feature = "acme")]
+    #[arg(long, conflicts_with_all(["identity_p12", "verify_peer_identity"]))]
+    acme_domain: Option<String>,
+    /// Contact URI of the ACME account, e.g. `mailto:admin@example.com`.
+    #[cfg(feature = "acme")]
+    #[arg(long, requires("acme_domain"))]
+    acme_contact: Option<String>,
+    /// Directory URL of the ACME server.
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value(instant_acme::LetsEncrypt::Production.url()))]
+    acme_directory: String,
+    /// Address to answer HTTP-01 challenges on, port 80 has to be forwarded to it.
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value("0.0.0.0:80"))]
+    acme_http_listen: SocketAddr,
+    /// Path to the file the ACME account credentials are kept in.
+    #[cfg(feature = "acme")]
+    #[arg(long, default_value("acme-account.json"))]
+    acme_account: PathBuf,
+    /// Address of the SPIFFE Workload API, e.g. `unix:///tmp/spire-agent/public/api.sock`,
+    /// to fetch the certificate from instead of `--cert` and `--key`. It is rotated as SVIDs are.
+    #[cfg(feature = "spiffe")]
+    #[arg(long, conflicts_with_all(["identity_p12", "verify_peer_identity"]))]
+    spiffe_socket: Option<String>,
+    /// Verify peers' certificates against the bundle of this SPIFFE trust domain
+    /// and require their SPIFFE IDs to be members of it.
+    #[cfg(feature = "spiffe")]
+    #[arg(
+        long,
+        requires("spiffe_socket"),
+        conflicts_with_all(["skip_server_verification", "skip_hostname_verification", "crl"])
+    )]
+    spiffe_trust_domain: Option<String>,
+    /// Log every stream sent or received, with its type and size.
+    #[arg(long, action)]
+    observe: bool,
+    /// Path to a file to capture the streams logged with `--observe` to.
+    #[arg(long, requires("observe"))]
+    capture: Option<PathBuf>,
+    /// Path to a file to record every message sent, received, forwarded and dropped to,
+    /// read with `p2p-gossip audit query`.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Size in bytes the audit log file is rotated at.
+    #[arg(long, requires("audit_log"), default_value("67108864"))]
+    audit_log_size: u64,
+    /// Number of rotated audit log files kept, `PATH.1` being the newest.
+    #[arg(long, requires("audit_log"), default_value("4"))]
+    audit_log_files: usize,
+    /// Path to a file to dump the state of the peer to on SIGUSR1, instead of the log.
+    #[arg(long)]
+    state_dump: Option<PathBuf>,
+    /// Time in seconds after which the messages of this peer expire,
+    /// expired ones are neither delivered nor forwarded.
+    #[arg(long)]
+    message_ttl: Option<u64>,
+    /// Maximum age in seconds of received messages, older ones are rejected as replays.
+    #[arg(long, default_value("60"))]
+    max_message_age: u64,
+    /// Maximum number of bytes sent to a peer but not yet acknowledged by it,
+    /// messages over it are not sent to that peer until it catches up.
+    #[arg(long, default_value("65536"))]
+    flow_window: usize,
+    /// Send fewer messages to peers whose connections lose packets or whose RTT grows,
+    /// down to an eighth of them, and more again as the connections recover.
+    #[arg(long, action)]
+    adaptive_pacing: bool,
+    /// Maximum number of bytes of messages queued for or not yet acknowledged by all peers,
+    /// new messages wait for them to drain beyond it.
+    #[arg(long, default_value("67108864"))]
+    memory_budget: usize,
+    /// Maximum number of messages kept for a peer being reconnected to,
+    /// sent to it once it is back, 0 disables keeping them.
+    #[arg(long, default_value("1000"))]
+    mailbox_messages: usize,
+    /// Maximum number of bytes of messages kept for a peer being reconnected to.
+    #[arg(long, default_value("1048576"))]
+    mailbox_bytes: usize,
+    /// Time in seconds messages are kept for a peer being reconnected to.
+    #[arg(long, default_value("300"))]
+    mailbox_age: u64,
+    /// Number of recent messages of each origin kept to send to peers which were away
+    /// on their request, 0 disables both keeping and requesting them.
+    #[arg(long, default_value("100"))]
+    history: usize,
+    /// Number of recent message bodies remembered, to drop the messages of any origin
+    /// repeating one of them, e.g. ones handlers pass on, 0 disables it.
+    #[arg(long, default_value("0"))]
+    dedup_cache: usize,
+    /// Ask the peer a message comes from for the messages of its origin found missing
+    /// by their sequence numbers, which it sends from its `--history`.
+    #[arg(long, action)]
+    retransmit_gaps: bool,
+    /// Deliver the messages of each origin in the order of their sequence numbers,
+    /// holding back the ones received past a gap until it is filled.
+    #[arg(long, action)]
+    ordered_delivery: bool,
+    /// Maximum number of messages of an origin held back for a gap
+    /// with `--ordered-delivery`, beyond it the gap is skipped.
+    #[arg(long, default_value("64"), requires("ordered_delivery"))]
+    reorder_window: usize,
+    /// Period in seconds, once in this period peers are pinged to estimate their clock offsets.
+    #[arg(long, default_value("10"))]
+    ping_interval: u64,
+    /// Period in seconds, once in this period the membership views of the peers are compared
+    /// by their digests. Peers they keep seeing for two periods without this node knowing them,
+    /// members of another partition which peer exchange did not bridge, are connected to.
+    #[arg(long, default_value("30"))]
+    membership_interval: u64,
+    /// Period in seconds, once in this period aggregation state is gossiped to a random peer.
+    #[arg(long, default_value("1"))]
+    aggregation_interval: u64,
+    /// Length in seconds of an aggregation epoch, after which aggregation starts over.
+    #[arg(long, default_value("60"))]
+    aggregation_epoch: u64,
+    /// Number of disconnections within `--flap-window` after which a peer is quarantined.
+    #[arg(long, default_value("5"))]
+    flap_threshold: usize,
+    /// Length in seconds of the window in which disconnections of a peer are counted.
+    #[arg(long, default_value("60"))]
+    flap_window: u64,
+    /// Time in seconds a quarantined peer is neither dialed nor sent messages for.
+    #[arg(long, default_value("300"))]
+    quarantine: u64,
+    /// Time in seconds to wait on shutdown for the messages queued
+    /// to be sent and acknowledged, while not taking new peers or messages.
+    #[arg(long, default_value("5"))]
+    drain_timeout: u64,
+    /// Number of reconnection attempts allowed at once, further ones are spread out by `--retry-rate`.
+    #[arg(long, default_value("10"))]
+    retry_burst: u32,
+    /// Number of reconnection attempts allowed per second, over all peers.
+    #[arg(long, default_value("2"), value_parser(parse_rate))]
+    retry_rate: f64,
+    /// Number of incoming connections taken at once with `--accept-rate`,
+    /// further ones are spread out by it.
+    #[arg(long, default_value("20"))]
+    accept_burst: u32,
+    /// Number of incoming connections taken per second, over all endpoints.
+    /// The others wait, so that a flood of joining peers does not starve
+    /// the connected ones. Unlimited by default.
+    #[arg(long, value_parser(parse_rate))]
+    accept_rate: Option<f64>,
+    /// Maximum number of bytes of messages sent per second, over all peers.
+    /// Unlimited by default.
+    #[arg(long, value_parser(clap::value_parser!(u32).range(1..)))]
+    max_upload: Option<u32>,
+    /// Maximum number of bytes of messages received per second, over all peers.
+    /// Peers are slowed down by QUIC flow control meanwhile. Unlimited by default.
+    #[arg(long, value_parser(clap::value_parser!(u32).range(1..)))]
+    max_download: Option<u32>,
+    /// Maximum number of bytes of messages sent per second to each peer.
+    #[arg(long, value_parser(clap::value_parser!(u32).range(1..)))]
+    max_peer_upload: Option<u32>,
+    /// Maximum number of bytes of messages received per second from each peer.
+    #[arg(long, value_parser(clap::value_parser!(u32).range(1..)))]
+    max_peer_download: Option<u32>,
+    /// Maximum size in bytes of message bodies, larger ones are neither sent nor received.
+    /// Messages over 1 KiB are sent with a header of their size and hash, which
+    /// peers of versions not supporting them fail to receive.
+    #[arg(long, default_value("1048576"))]
+    max_transfer_size: usize,
+    /// Number of handshakes in progress from which joining peers are required
+    /// to validate their addresses with a stateless retry, costing them a round trip,
+    /// e.g. `0` to always require it, and for a minute after the threshold
+    /// was last reached otherwise. Not required by default.
+    #[arg(long)]
+    retry_threshold: Option<usize>,
+    /// Where to write the log to.
+    #[arg(long, value_enum, default_value_t = LogTarget::Stdout)]
+    log_target: LogTarget,
+    /// Format of the log lines written to stdout: `text`, or `json` for a JSON object
+    /// per line with the `timestamp`, `level`, `event`, `peer`, `message_id` and `text`.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Levels of the log categories `membership`, `messages`, `transport` and `general`:
+    /// `off`, `info` or `debug` for all of them, followed by any `CATEGORY=LEVEL`,
+    /// e.g. `info,messages=debug`. Can be changed with the `loglevel` admin command.
+    #[arg(long, default_value("info"))]
+    log_level: LogLevels,
+    /// Syslog server to send the log to over UDP with `--log-target syslog`,
+    /// e.g. `127.0.0.1:514`, instead of the local syslog socket.
+    #[arg(long)]
+    syslog_server: Option<String>,
+    /// Address to serve the admin API on, e.g. `127.0.0.1:9000`.
+    #[arg(long)]
+    admin: Option<SocketAddr>,
+    /// StatsD server to emit counters and timers to over UDP, e.g. `127.0.0.1:8125`.
+    #[arg(long)]
+    statsd: Option<String>,
+    /// Prefix of the names of the metrics emitted to StatsD.
+    #[arg(long, default_value("p2p_gossip."), requires("statsd"))]
+    statsd_prefix: String,
+    /// Fraction of the metrics emitted to StatsD, between 0 and 1.
+    #[arg(
+        long,
+        default_value("1"),
+        requires("statsd"),
+        value_parser(parse_sample_rate)
+    )]
+    statsd_sample_rate: f64,
+    /// Period in seconds, once in this period the QUIC path statistics
+    /// of each connection are emitted to `--statsd`.
+    #[arg(long, default_value("10"), requires("statsd"))]
+    path_stats_interval: u64,
+    /// IP to run on, IPv6 addresses may have a scope, e.g. `fe80::1%eth0`.
     #[arg(long, default_value("127.0.0.1"))]
-    ip: IpAddr,
-    /// Port to run on.
+    ip: ScopedIp,
+    /// Network interface to run on, instead of `--ip`.
+    #[arg(long, conflicts_with("ip"))]
+    interface: Option<String>,
+    /// Port to run on, or a range of ports to try in turn, e.g. `9000-9010`.
+    #[arg(long, required = true)]
+    port: Option<PortRange>,
+    /// Number of nodes to run in this process on consecutive ports, each one connecting
+    /// to the ones started before it, e.g. for local experiments and load tests.
+    /// The admin API of each node is served on the port of `--admin` plus its number from 0.
+    #[arg(
+        long,
+        default_value("1"),
+        value_parser(clap::value_parser!(u16).range(1..)),
+        conflicts_with_all(["external_address", "advertise", "blocklist", "capture", "state_dump", "audit_log"])
+    )]
+    nodes: u16,
+    /// Address to advertise to peers in place of the one bound to, e.g. the public
+    /// address forwarded to the node by a static NAT.
     #[arg(long)]
-    port: u16,
-    /// Address of the first node to connect to.
+    external_address: Option<SocketAddr>,
+    /// Other address the node is reachable on, e.g. on a LAN or over IPv6, may be repeated
+    /// up to 8 times. Dialers try the advertised address and then these in the order given.
+    #[arg(long)]
+    advertise: Vec<SocketAddr>,
+    /// Address of the first node to connect to, either `IP:port` or `host:port`,
+    /// may be repeated to connect to several nodes concurrently.
+    #[arg(long)]
+    connect: Vec<String>,
+    /// Address of a peer to always stay connected to, may be repeated. Pinned peers
+    /// are never quarantined and are redialed for as long as the node runs,
+    /// even after they close the connection.
+    #[arg(long)]
+    pin: Vec<SocketAddr>,
+    /// Path to a file of peers to always stay connected to like `--pin`, one address
+    /// per line, optionally followed by an alias. Read again when it changes.
+    #[arg(long)]
+    peers_file: Option<PathBuf>,
+    /// Period in seconds, once in this period the `--peers-file` is checked for changes.
+    #[arg(long, default_value("5"))]
+    peers_file_refresh: u64,
+    /// Path to the file the peers blocked with the `block` admin command are kept in,
+    /// one IP or `IP:port` address per line. Without it, blocks last until the node exits.
+    #[arg(long)]
+    blocklist: Option<PathBuf>,
+    /// Which peers private (RFC 1918), unique local and link-local addresses are sent to
+    /// and taken from in peer lists: `lan-only` for the peers on such addresses themselves.
+    #[arg(long, value_enum, default_value_t = AdvertisePrivate::Always)]
+    advertise_private: AdvertisePrivate,
+    /// Allow other sockets to bind to the same port, with SO_REUSEPORT.
+    #[arg(long, action)]
+    reuse_port: bool,
+    /// Number of QUIC endpoints sharing the port, e.g. one per CPU core, to process
+    /// packets in parallel. Peers are spread over the endpoints. Linux only.
+    #[arg(long, default_value("1"), value_parser = clap::value_parser!(u8).range(1..=MAX_SHARDS as i64))]
+    endpoints: u8,
+    /// Send buffer size of the socket in bytes, instead of the system default.
+    /// Limited by `net.core.wmem_max` on Linux.
     #[arg(long)]
-    connect: Option<SocketAddr>,
+    send_buffer: Option<usize>,
+    /// Receive buffer size of the socket in bytes, instead of the system default,
+    /// larger ones avoid drops under high message rates.
+    /// Limited by `net.core.rmem_max` on Linux.
+    #[arg(long)]
+    recv_buffer: Option<usize>,
+    /// DSCP value to mark the packets sent with, from 0 to 63, e.g. `46` for expedited forwarding.
+    /// The packets are then sent without ECN marking and segmentation offload.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..64))]
+    dscp: Option<u8>,
+    /// Do not use generic segmentation offload (GSO) to send packets, where supported.
+    #[arg(long, action)]
+    no_gso: bool,
+    /// Do not use generic receive offload (GRO) to receive packets, where supported.
+    #[arg(long, action)]
+    no_gro: bool,
+    /// Do not mark packets with explicit congestion notification (ECN), for networks
+    /// which drop marked packets. Peers then stop marking theirs too.
+    #[arg(long, action)]
+    no_ecn: bool,
+    /// Congestion controller of the connections.
+    #[arg(long, value_enum, default_value_t = Congestion::Cubic)]
+    congestion_controller: Congestion,
+    /// Initial congestion window in bytes, larger ones suit high bandwidth-delay links.
+    #[arg(long)]
+    initial_window: Option<u64>,
+    /// Time in seconds to wait for an outgoing connection to be established.
+    #[arg(long, default_value("10"))]
+    dial_timeout: u64,
     /// Do not verify peers' TLS certificates.
     #[arg(long, action)]
     skip_server_verification: bool,
+    /// Verify peers' TLS certificates, but not the hostnames they are issued for.
+    #[arg(long, action, conflicts_with("skip_server_verification"))]
+    skip_hostname_verification: bool,
     /// Path to the certificate PEM file.
     #[arg(long, default_value("cert.pem"))]
     cert: PathBuf,
     /// Path to the secret key PEM file.
     #[arg(long, default_value("key.pem"))]
     key: PathBuf,
+    /// Generate a self-signed certificate in memory if `--cert` or `--key` does not exist,
+    /// instead of failing, e.g. for first runs and throwaway test nodes.
+    #[arg(long, action)]
+    ephemeral_cert: bool,
+    /// Path to a PKCS#12 bundle with the certificate chain and the secret key,
+    /// instead of `--cert` and `--key`.
+    #[arg(long, conflicts_with_all(["cert", "key", "ephemeral_cert"]))]
+    identity_p12: Option<PathBuf>,
+    /// Path to a file with the passphrase of an encrypted `--key` or of `--identity-p12`,
+    /// otherwise it is read from the `P2P_GOSSIP_KEY_PASS` environment variable or prompted for.
+    #[arg(long)]
+    key_pass_file: Option<PathBuf>,
+    /// Present the certificate to the peers connected to and require the peers connecting
+    /// to present certificates issued for the IP or the hostname of the listen address
+    /// they advertise, so that no peer can pose as another one.
+    #[arg(long, action, conflicts_with("skip_server_verification"))]
+    verify_peer_identity: bool,
+    /// Path to a PEM file with certificate revocation lists to check the certificates
+    /// of the peers connected to against, and with `--verify-peer-identity`
+    /// the ones of the peers connecting too.
+    #[arg(long, conflicts_with("skip_server_verification"))]
+    crl: Option<PathBuf>,
+    /// Period in seconds, once in this period the `--crl` file is read again.
+    #[arg(long, default_value("3600"))]
+    crl_refresh: u64,
+    /// SOCKS5 proxy to route outgoing connections through, e.g. `socks5://127.0.0.1:1080`.
+    #[arg(long, value_parser(parse_proxy))]
+    proxy: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let args = Args::parse();
-    let addr = SocketAddr::new(args.ip, args.port);
-
-    let (certs, key) = read_certs_from_file(&args.cert, &args.key)?;
-    let mut endpoint = Endpoint::server(ServerConfig::with_single_cert(certs, key).unwrap(), addr)?;
-    endpoint.set_default_client_config(if args.skip_server_verification {
-        configure_client_without_server_verification()
-    } else {
-        ClientConfig::with_native_roots()
-    });
-
-    tokio::spawn(run_peer(endpoint.clone(), addr, args.connect, args.period));
-
-    signal::ctrl_c().await?;
-    log(&[b"Shutting down"]);
-    endpoint.close(2u8.into(), b"shutdown");
-    endpoint.wait_idle().await;
-
-    Ok(())
+fn parse_proxy(s: &str) -> Result<String, String> {
+    s.strip_prefix("socks5://")
+        .map(str::to_owned)
+        .ok_or_else(|| "only socks5:// proxies are supported".into())
 }
 
-/// Runs a new peer on `endpoint`.
-async fn run_peer(
-    endpoint: Endpoint,
-    addr: SocketAddr,
-    connect: Option<SocketAddr>,
-    period: Option<usize>,
-) {
-    log(&[b"My address is \"", addr.to_string().as_bytes(), b"\""]);
-
-    let (message_sender, _rx) = broadcast::channel::<Arc<str>>(16);
-
-    let peers = if let Some(connect) = connect {
-        initial_connect(endpoint.clone(), connect, message_sender.clone()).await
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
     } else {
-        Arc::new(Mutex::new(HashMap::new()))
-    };
-
-    if let Some(period) = period {
-        tokio::spawn(producer_loop(
-            Duration::from_secs(period as _),
-            peers.clone(),
-            message_sender.clone(),
-        ));
-    }
-
-    accept_loop(endpoint, peers, message_sender).await;
-}
-
-/// Continuesly accepts incoming connections on `Endpoint`
-/// and spawns `handle_incoming_connection` on them
-async fn accept_loop(
-    endpoint: Endpoint,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    message_sender: broadcast::Sender<Arc<str>>,
-) {
-    while let Some(connecting) = endpoint.accept().await {
-        tokio::spawn(handle_incoming_connection(
-            endpoint.clone(),
-            connecting,
-            peers.clone(),
-            message_sender.clone(),
-        ));
+        Err("must be a positive number".into())
     }
 }
 
-/// Accepts an incoming `connection_in_progress`.
-///
-/// Sends the list of peers to the remote address
-/// and spawns `handle_connection`. Logs errors on failure.
-async fn handle_incoming_connection(
-    endpoint: Endpoint,
-    connection_in_progress: Connecting,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    message_sender: broadcast::Sender<Arc<str>>,
-) {
-    let remote_addr = connection_in_progress.remote_address();
-    match accept_connection(connection_in_progress, peers.clone()).await {
-        Ok(Some(connection)) => {
-            log(&[
-                b"Accepted a connection from ",
-                remote_addr.to_string().as_bytes(),
-            ]);
-            handle_connection(endpoint, connection, message_sender, peers).await;
-        }
-        Err(e) if !is_already_open_or_locally_closed_error(&e) => log(&[
-            b"Failed to accept a connection from ",
-            remote_addr.to_string().as_bytes(),
-            b", error: ",
-            e.to_string().as_bytes(),
-        ]),
-        Err(_) | Ok(None) => {}
+fn parse_sample_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err("must be between 0 and 1".into())
     }
 }
 
-/// Accepts an incoming `connection_in_progress`.
-///
-/// Sends the list of peers to the remote address.
-async fn accept_connection(
-    connection_in_progress: Connecting,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-) -> AppResult<Option<Connection>> {
-    let connection = connection_in_progress.await?;
-
-    let mut peers_lock = peers.lock().await;
-    if Some(true) == peers_lock.insert(connection.remote_address(), true) {
-        connection.close(1u8.into(), b"already connected");
-        return Ok(None);
-    }
-
-    let mut send = connection.open_uni().await?;
-    for peer in &*peers_lock {
-        send.write_all(&bincode::serialize(peer.0).unwrap()).await?;
-    }
-    drop(peers_lock);
-    send.finish().await?;
-
-    Ok(Some(connection))
+/// Commands querying a running node instead of running one.
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Print the peers of a node, or their changes as they happen.
+    Peers {
+        /// Address the node serves the admin API on.
+        #[arg(long)]
+        admin: SocketAddr,
+        /// Print the peers as added, then each addition, removal
+        /// and change of state, until interrupted.
+        #[arg(long, action)]
+        watch: bool,
+    },
+    /// Ping a peer through a node, printing the round-trip time and the number of links.
+    Ping {
+        /// Address the node serves the admin API on.
+        #[arg(long)]
+        admin: SocketAddr,
+        /// Address the peer listens on.
+        peer: SocketAddr,
+    },
+    /// Print the levels of the log categories of a node, or change them.
+    Loglevel {
+        /// Address the node serves the admin API on.
+        #[arg(long)]
+        admin: SocketAddr,
+        /// Levels to change to, in the format of `--log-level`.
+        spec: Option<String>,
+    },
+    /// Block a peer of a node, closing the connections to it.
+    Block {
+        /// Address the node serves the admin API on.
+        #[arg(long)]
+        admin: SocketAddr,
+        /// Address the peer listens on, or an IP to block all peers on it.
+        peer: Blocked,
+    },
+    /// Unblock a peer blocked with `block`.
+    Unblock {
+        /// Address the node serves the admin API on.
+        #[arg(long)]
+        admin: SocketAddr,
+        /// Address or IP the peer was blocked by.
+        peer: Blocked,
+    },
+    /// Measure the throughput of the codecs and of the fan-out of messages to peers.
+    ///
+    /// Prints a table of the message and peer-list codecs and the fan-out
+    /// of messages to simulated peers, to compare between builds.
+    Bench {
+        /// Number of times each benchmark is run.
+        #[arg(long, default_value("100000"))]
+        iterations: usize,
+        /// Size of the bodies of the messages in bytes.
+        #[arg(long, default_value("64"))]
+        body_size: usize,
+        /// Number of simulated peers messages are fanned out to.
+        #[arg(long, default_value("8"))]
+        peers: usize,
+    },
+    /// Print the peers of a `--peers-file`, to seed other nodes with.
+    ExportPeers {
+        /// Path to the peers file.
+        #[arg(long)]
+        peers_file: PathBuf,
+        /// Format of the peers.
+        #[arg(long, value_enum, default_value_t = PeerFormat::Json)]
+        format: PeerFormat,
+    },
+    /// Add the peers read from stdin to a `--peers-file`, which running nodes pick up.
+    ///
+    /// Peers already in the file are kept, the aliases of the imported ones take precedence.
+    ImportPeers {
+        /// Path to the peers file, created if there is none.
+        #[arg(long)]
+        peers_file: PathBuf,
+        /// Format of the peers.
+        #[arg(long, value_enum, default_value_t = PeerFormat::Json)]
+        format: PeerFormat,
+    },
+    /// Read the audit log of a node written with `--audit-log`.
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
 }
 
-/// Connects to `first_peer` and then to all the other peers.
-async fn initial_connect(
-    endpoint: Endpoint,
-    first_peer: SocketAddr,
-    message_sender: broadcast::Sender<Arc<str>>,
-) -> Arc<Mutex<HashMap<SocketAddr, bool>>> {
-    let peers = Arc::new(Mutex::new(HashMap::from([(first_peer, false)])));
-    let (failed_peers, finished) = NotifyOnDrop::create(());
-    let _ = outgoing_connect(
-        endpoint,
-        first_peer,
-        message_sender,
-        peers.clone(),
-        Arc::new(failed_peers),
-    )
-    .await;
-    let _ = finished.await;
-    let mut peers_lock = peers.lock().await;
-    log(&[
-        b"Connected to the peers at [",
-        format_peers(&peers_lock).as_bytes(),
-        b"]",
-    ]);
-    peers_lock.retain(|_, &mut v| v);
-    drop(peers_lock);
-    peers
+#[derive(Subcommand, Clone, Debug)]
+enum AuditCommand {
+    /// Print the records of the audit log and its rotated files matching all the
+    /// conditions given, oldest first.
+    Query {
+        /// Path to the audit log.
+        #[arg(long)]
+        audit_log: PathBuf,
+        /// Listen address of the peer the messages are sent to or received from.
+        #[arg(long)]
+        peer: Option<SocketAddr>,
+        /// Time to print the records from, in RFC 3339 format, e.g. `2024-05-01T12:00:00Z`.
+        #[arg(long, value_parser(parse_time))]
+        since: Option<u64>,
+        /// Time to print the records until, in RFC 3339 format.
+        #[arg(long, value_parser(parse_time))]
+        until: Option<u64>,
+        /// Hash of the message in hex, or a prefix of it.
+        #[arg(long)]
+        hash: Option<String>,
+    },
 }
 
-/// Connects to a node with address `remote_addr`. Logs errors on failure.
-async fn outgoing_connect(
-    endpoint: Endpoint,
-    remote_addr: SocketAddr,
-    message_sender: broadcast::Sender<Arc<str>>,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    notify_on_drop: Arc<NotifyOnDrop<()>>,
-) -> AppResult<Connection> {
-    let local_addr = endpoint.local_addr().unwrap();
-    let res = outgoing_connect_inner(
-        endpoint,
-        remote_addr,
-        message_sender,
-        peers.clone(),
-        notify_on_drop.clone(),
-    )
-    .await;
-
-    match res.as_ref() {
-        Err(e) if !is_already_open_or_locally_closed_error(e) => log(&[
-            b"Failed to connect to ",
-            remote_addr.to_string().as_bytes(),
-            b", error: ",
-            e.to_string().as_bytes(),
-        ]),
-        Err(_) => {}
-        Ok(connection) => {
-            if Some(true) == peers.lock().await.insert(remote_addr, true)
-                // a hack to avoid both ends closing the connection
-                && local_addr < remote_addr
-            {
-                connection.close(1u8.into(), b"already connected");
-            }
+impl From<Args> for Config {
+    fn from(args: Args) -> Self {
+        Self {
+            period: args.period.map(Duration::from_secs),
+            seed_mode: args.seed_mode,
+            receive_only: args.receive_only,
+            relay_only: args.relay_only,
+            forward: args.forward,
+            max_hops: args.max_hops,
+            filter: args.filter,
+            handler: args.handler,
+            on_message: args.on_message,
+            on_message_concurrency: args.on_message_concurrency,
+            on_message_rate: args.on_message_rate,
+            on_request: args.on_request,
+            request_timeout: Duration::from_secs(args.request_timeout),
+            webhook: args.webhook,
+            webhook_queue: args.webhook_queue,
+            #[cfg(feature = "kafka")]
+            kafka_brokers: args.kafka_brokers,
+            #[cfg(feature = "kafka")]
+            kafka_publish: args.kafka_publish,
+            #[cfg(feature = "kafka")]
+            kafka_consume: args.kafka_consume,
+            #[cfg(feature = "gossipsub")]
+            gossipsub_listen: args.gossipsub_listen,
+            #[cfg(feature = "gossipsub")]
+            gossipsub_dial: args.gossipsub_dial,
+            #[cfg(feature = "gossipsub")]
+            gossipsub_topic: args.gossipsub_topic,
+            #[cfg(feature = "nats")]
+            nats: args.nats,
+            #[cfg(feature = "nats")]
+            nats_publish: args.nats_publish,
+            #[cfg(feature = "nats")]
+            nats_subscribe: args.nats_subscribe,
+            #[cfg(feature = "acme")]
+            acme_domain: args.acme_domain,
+            #[cfg(feature = "acme")]
+            acme_contact: args.acme_contact,
+            #[cfg(feature = "acme")]
+            acme_directory: args.acme_directory,
+            #[cfg(feature = "acme")]
+            acme_http_listen: args.acme_http_listen,
+            #[cfg(feature = "acme")]
+            acme_account: args.acme_account,
+            #[cfg(feature = "spiffe")]
+            spiffe_socket: args.spiffe_socket,
+            #[cfg(feature = "spiffe")]
+            spiffe_trust_domain: args.spiffe_trust_domain,
+            observe: args.observe,
+            capture: args.capture,
+            audit_log: args.audit_log,
+            audit_log_size: args.audit_log_size,
+            audit_log_files: args.audit_log_files,
+            state_dump: args.state_dump,
+            message_ttl: args.message_ttl.map(Duration::from_secs),
+            max_message_age: Duration::from_secs(args.max_message_age),
+            flow_window: args.flow_window,
+            adaptive_pacing: args.adaptive_pacing,
+            memory_budget: args.memory_budget,
+            mailbox_messages: args.mailbox_messages,
+            mailbox_bytes: args.mailbox_bytes,
+            mailbox_age: Duration::from_secs(args.mailbox_age),
+            history: args.history,
+            dedup_cache: args.dedup_cache,
+            retransmit_gaps: args.retransmit_gaps,
+            ordered_delivery: args.ordered_delivery,
+            reorder_window: args.reorder_window,
+            ping_interval: Duration::from_secs(args.ping_interval),
+            membership_interval: Duration::from_secs(args.membership_interval),
+            aggregation_interval: Duration::from_secs(args.aggregation_interval),
+            aggregation_epoch: Duration::from_secs(args.aggregation_epoch),
+            flap_threshold: args.flap_threshold,
+            flap_window: Duration::from_secs(args.flap_window),
+            quarantine: Duration::from_secs(args.quarantine),
+            drain_timeout: Duration::from_secs(args.drain_timeout),
+            retry_burst: args.retry_burst,
+            retry_rate: args.retry_rate,
+            accept_burst: args.accept_burst,
+            accept_rate: args.accept_rate,
+            max_upload: args.max_upload,
+            max_download: args.max_download,
+            max_peer_upload: args.max_peer_upload,
+            max_peer_download: args.max_peer_download,
+            max_transfer_size: args.max_transfer_size,
+            retry_threshold: args.retry_threshold,
+            admin: args.admin,
+            statsd: args.statsd,
+            statsd_prefix: args.statsd_prefix,
+            statsd_sample_rate: args.statsd_sample_rate,
+            path_stats_interval: Duration::from_secs(args.path_stats_interval),
+            ip: args.ip,
+            interface: args.interface,
+            port: args.port.unwrap(),
+            external_address: args.external_address,
+            advertise: args.advertise,
+            connect: args.connect,
+            pin: args.pin,
+            peers_file: args.peers_file,
+            peers_file_refresh: Duration::from_secs(args.peers_file_refresh),
+            blocklist: args.blocklist,
+            advertise_private: args.advertise_private,
+            reuse_port: args.reuse_port,
+            endpoints: args.endpoints,
+            send_buffer: args.send_buffer,
+            recv_buffer: args.recv_buffer,
+            dscp: args.dscp,
+            no_gso: args.no_gso,
+            no_gro: args.no_gro,
+            no_ecn: args.no_ecn,
+            congestion_controller: args.congestion_controller,
+            initial_window: args.initial_window,
+            dial_timeout: Duration::from_secs(args.dial_timeout),
+            skip_server_verification: args.skip_server_verification,
+            skip_hostname_verification: args.skip_hostname_verification,
+            cert: args.cert,
+            key: args.key,
+            ephemeral_cert: args.ephemeral_cert,
+            identity_p12: args.identity_p12,
+            key_pass_file: args.key_pass_file,
+            verify_peer_identity: args.verify_peer_identity,
+            crl: args.crl,
+            crl_refresh: Duration::from_secs(args.crl_refresh),
+            proxy: args.proxy,
         }
     }
-
-    res
 }
 
-/// Connects to a node with address `remote_addr`.
-fn outgoing_connect_inner(
-    endpoint: Endpoint,
-    remote_addr: SocketAddr,
-    message_sender: broadcast::Sender<Arc<str>>,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    failed_peers: Arc<NotifyOnDrop<()>>,
-) -> BoxFuture<'static, AppResult<Connection>> {
-    async move {
-        let name = lookup_addr(&remote_addr.ip())?;
-        let connection = endpoint.connect(remote_addr, &name)?.await?;
-        let mut recv = connection.accept_uni().await?;
-        let data = recv.read_to_end(10_000).await?;
-        let mut peers_lock = peers.lock().await;
-
-        for peer in deserialize_addresses(&data) {
-            if peer != endpoint.local_addr().unwrap() && !peers_lock.contains_key(&peer) {
-                peers_lock.insert(peer, false);
-                tokio::spawn(outgoing_connect(
-                    endpoint.clone(),
-                    peer,
-                    message_sender.clone(),
-                    peers.clone(),
-                    failed_peers.clone(),
-                ));
-            }
-        }
-        drop(peers_lock);
-        tokio::spawn(handle_connection(
-            endpoint,
-            connection.clone(),
-            message_sender,
-            peers,
-        ));
-        Ok(connection)
-    }
-    .boxed()
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    run(Args::parse()).await
 }
 
-/// Once in `duration`, sends a random message to `message_sender`.
-async fn producer_loop(
-    duration: Duration,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    message_sender: broadcast::Sender<Arc<str>>,
-) {
-    fn generate_random_message(rng: &mut impl Rng) -> String {
-        let mut message = [0; 32];
-        rng.fill_bytes(&mut message);
-        bs58::encode(message).into_string()
-    }
-
-    let mut rng = Pcg64Mcg::from_entropy();
-
-    let mut deadline = Instant::now() + duration;
-    loop {
-        tokio::time::sleep_until(deadline).await;
-        deadline += duration;
-
-        let formatted_peers = format_peers(&*peers.lock().await);
-        if !formatted_peers.is_empty() {
-            let msg = generate_random_message(&mut rng);
-            log(&[
-                b"Sending message [",
-                msg.as_bytes(),
-                b"] to [",
-                formatted_peers.as_bytes(),
-                b"]",
-            ]);
-            message_sender.send(msg.into()).unwrap();
+/// Runs the subcommand of `args`, or else the nodes of `args`
+/// until SIGINT arrives.
+async fn run(args: Args) -> io::Result<()> {
+    match args.command {
+        Some(Command::Peers { admin, watch }) => return print_peers(admin, watch).await,
+        Some(Command::Ping { admin, peer }) => {
+            return print_response(admin, &format!("ping {peer}")).await;
         }
-    }
-}
-
-/// Handles communication via `connection`. Logs errors on disconnection.
-async fn handle_connection(
-    endpoint: Endpoint,
-    connection: Connection,
-    message_sender: broadcast::Sender<Arc<str>>,
-    peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-) {
-    async fn retry_connection(
-        endpoint: Endpoint,
-        remote_addr: SocketAddr,
-        message_sender: broadcast::Sender<Arc<str>>,
-        peers: Arc<Mutex<HashMap<SocketAddr, bool>>>,
-    ) -> Result<bool, backoff::Error<AppError>> {
-        if Some(&true) == peers.lock().await.get(&remote_addr) {
-            return Ok(false);
+        Some(Command::Loglevel { admin, spec }) => {
+            let command = match spec {
+                Some(spec) => format!("loglevel {spec}"),
+                None => "loglevel".to_owned(),
+            };
+            return print_response(admin, &command).await;
+        }
+        Some(Command::Block { admin, peer }) => {
+            return print_response(admin, &format!("block {peer}")).await;
         }
-        let (notify_on_drop, finished) = NotifyOnDrop::create(());
-        let res = outgoing_connect(
-            endpoint,
-            remote_addr,
-            message_sender,
+        Some(Command::Unblock { admin, peer }) => {
+            return print_response(admin, &format!("unblock {peer}")).await;
+        }
+        Some(Command::Bench {
+            iterations,
+            body_size,
             peers,
-            Arc::new(notify_on_drop),
-        )
-        .await
-        .map_err(|e| backoff::Error::Transient {
-            err: e,
-            retry_after: None,
-        });
-        let _ = finished.await;
-        res.map(|_| true)
-    }
-
-    let disconnect_reason = handle_connection_inner(&connection, message_sender.subscribe()).await;
-    let remote_addr = connection.remote_address();
-
-    drop(connection);
-    if !is_already_open_or_locally_closed_reason(&disconnect_reason) {
-        log(&[
-            b"Closed connection to ",
-            remote_addr.to_string().as_bytes(),
-            b", reason: ",
-            disconnect_reason.to_string().as_bytes(),
-        ]);
-    }
-
-    peers.lock().await.insert(remote_addr, false);
-
-    match disconnect_reason {
-        ConnectionError::TimedOut => {
-            // we need to reconnect even if the peer connects to us
-            // to potentially get newer peers
-            if backoff::future::retry(ExponentialBackoff::default(), || {
-                retry_connection(
-                    endpoint.clone(),
-                    remote_addr,
-                    message_sender.clone(),
-                    peers.clone(),
-                )
-            })
-            .await
-            .unwrap()
-            {
-                log(&[b"Reconnected to ", remote_addr.to_string().as_bytes()]);
-            }
+        }) => {
+            print!("{}", run_bench(iterations, body_size, peers).await);
+            return Ok(());
+        }
+        Some(Command::ExportPeers { peers_file, format }) => {
+            print!("{}", export_peers(&read_peers_file(&peers_file)?, format));
+            return Ok(());
         }
-        e if is_already_open_or_locally_closed_reason(&e) => {
-            peers.lock().await.insert(remote_addr, true);
+        Some(Command::ImportPeers { peers_file, format }) => {
+            let input = io::read_to_string(io::stdin())?;
+            let peers = import_peers(&input, format).map_err(io::Error::other)?;
+            let imported = merge_into_peers_file(&peers_file, &peers)?;
+            println!("imported {imported} of {} peers", peers.len());
+            return Ok(());
+        }
+        Some(Command::Audit {
+            command:
+                AuditCommand::Query {
+                    audit_log,
+                    peer,
+                    since,
+                    until,
+                    hash,
+                },
+        }) => {
+            let query = AuditQuery {
+                peer,
+                since,
+                until,
+                hash,
+            };
+            let mut out = io::stdout().lock();
+            for record in query_audit_log(&audit_log, &query)? {
+                match writeln!(out, "{record}") {
+                    // piped to e.g. `head`, which has seen enough
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                    res => res?,
+                }
+            }
+            return Ok(());
         }
-        _ => {}
+        None => {}
     }
-}
-
-/// Handles communication via `connection`.
-async fn handle_connection_inner(
-    connection: &Connection,
-    mut message_receiver: broadcast::Receiver<Arc<str>>,
-) -> ConnectionError {
-    tokio::spawn({
-        let connection = connection.clone();
-        async move { sender_loop(&mut message_receiver, &connection).await }
-    });
-    loop {
-        let receiving_res = receiver_loop(connection).await;
-        if let Some(reason) = connection.close_reason() {
-            return reason;
+    // the log is shared by all the nodes of the process
+    set_log_levels(args.log_level);
+    set_log_format(args.log_format);
+    if args.log_target == LogTarget::Syslog {
+        set_syslog(match &args.syslog_server {
+            Some(server) => Syslog::remote(
+                lookup_host(server)
+                    .await?
+                    .next()
+                    .ok_or(io::ErrorKind::NotFound)?,
+            )?,
+            None => Syslog::local()?,
+        });
+    }
+    let count = args.nodes;
+    let config = Config::from(args);
+    let mut nodes = Vec::with_capacity(count.into());
+    let PortRange(ports) = config.port.clone();
+    for i in 0..count {
+        let mut node_config = config.clone();
+        if let Some(previous) = nodes.last().map(GossipNode::bind_addr) {
+            // the first node may have taken a later port of the range
+            let previous = previous.port();
+            node_config.port =
+                PortRange(previous.saturating_add(1)..=ports.end().saturating_add(i));
+            // rather than to the first one only, which may not know
+            // of the nodes started just before yet
+            node_config
+                .connect
+                .extend(nodes.iter().map(|node| node.local_addr().to_string()));
+            node_config.admin = config
+                .admin
+                .map(|admin| SocketAddr::new(admin.ip(), admin.port().saturating_add(i)));
         }
-        log(&[
-            b"Failed to receive from ",
-            connection.remote_address().to_string().as_bytes(),
-            b", error:",
-            format!("{receiving_res:?}").as_bytes(),
-        ]);
+        nodes.push(GossipNode::start(node_config, Vec::new()).await?);
     }
-}
 
-/// Logs messages received from `connection`.
-async fn receiver_loop(connection: &Connection) -> AppResult<()> {
-    let peer_addr = connection.remote_address().to_string();
-    loop {
-        let mut recv = connection.accept_uni().await?;
-        let msg = recv.read_to_end(1024).await?;
-        log(&[
-            b"Received message [",
-            &msg,
-            b"] from ",
-            peer_addr.as_bytes(),
-        ]);
-    }
+    signal::ctrl_c().await?;
+    log(&[b"Shutting down"]);
+    join_all(nodes.into_iter().map(GossipNode::shutdown)).await;
+
+    Ok(())
 }
 
-/// Sends messages received from `message_receiver` to `connection`.
-async fn sender_loop(
-    message_receiver: &mut broadcast::Receiver<Arc<str>>,
-    connection: &Connection,
-) -> AppResult<()> {
-    while let Ok(msg) = message_receiver.recv().await {
-        let mut send = connection.open_uni().await?;
-        send.write_all(msg.as_bytes()).await?;
-        send.finish().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        let args = Args::parse_from(["p2p-gossip", "--port", "9000"]);
+        assert_eq!(
+            format!("{:?}", Config::from(args)),
+            format!("{:?}", Config::new(9000))
+        );
     }
-
-    Ok(())
 }
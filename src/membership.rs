@@ -0,0 +1,92 @@
+use crate::utils::serialize_address;
+use core::{net::SocketAddr, time::Duration};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tokio::time::Instant;
+
+/// A digest of the membership view `view`, which is the same
+/// for the same peers in any order.
+pub fn digest(view: &[SocketAddr]) -> u64 {
+    let mut view = view.to_vec();
+    view.sort_unstable();
+    view.dedup();
+    let mut hasher = Sha256::new();
+    for addr in view {
+        hasher.update(serialize_address(&addr));
+    }
+    u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap())
+}
+
+/// Tells the peers which the neighbors have kept seeing for `threshold`
+/// without this node knowing them, the members of another partition
+/// which peer exchange did not bridge.
+pub struct PartitionDetector {
+    threshold: Duration,
+    /// When each unknown peer was first and last seen in a view of a neighbor.
+    unknown: HashMap<SocketAddr, (Instant, Instant)>,
+}
+
+impl PartitionDetector {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            unknown: HashMap::new(),
+        }
+    }
+
+    /// Records the peers `unknown` to this node seen in the view of a neighbor
+    /// at `now`, returning the ones seen for at least `threshold`, which are
+    /// forgotten then. The peers not seen for longer than `threshold` are forgotten too,
+    /// they were found by other means or left.
+    pub fn observe(
+        &mut self,
+        unknown: impl IntoIterator<Item = SocketAddr>,
+        now: Instant,
+    ) -> Vec<SocketAddr> {
+        self.unknown
+            .retain(|_, &mut (_, last_seen)| now.duration_since(last_seen) <= self.threshold);
+        let mut long_lived = Vec::new();
+        for peer in unknown {
+            let (first_seen, last_seen) = self.unknown.entry(peer).or_insert((now, now));
+            *last_seen = now;
+            if now.duration_since(*first_seen) >= self.threshold {
+                long_lived.push(peer);
+            }
+        }
+        for peer in &long_lived {
+            self.unknown.remove(peer);
+        }
+        long_lived
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        assert_eq!(
+            digest(&[addr(1), addr(2)]),
+            digest(&[addr(2), addr(1), addr(2)])
+        );
+        assert_ne!(digest(&[addr(1), addr(2)]), digest(&[addr(1)]));
+    }
+
+    #[test]
+    fn test_partition_detector() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let now = Instant::now();
+        let secs = |secs| now + Duration::from_secs(secs);
+        let mut detector = PartitionDetector::new(Duration::from_secs(10));
+        assert!(detector.observe([addr(1), addr(2)], now).is_empty());
+        assert!(detector.observe([addr(1)], secs(5)).is_empty());
+        assert_eq!(detector.observe([addr(1)], secs(10)), [addr(1)]);
+        // forgotten once returned
+        assert!(detector.observe([addr(1)], secs(11)).is_empty());
+        // not seen for longer than the threshold
+        assert!(detector.observe([addr(2)], secs(21)).is_empty());
+        assert_eq!(detector.observe([addr(2)], secs(31)), [addr(2)]);
+    }
+}
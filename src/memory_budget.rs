@@ -0,0 +1,116 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+/// Counts the bytes buffered across all peers against `limit`,
+/// so that producers of messages can wait for them to drain.
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    released: Notify,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            used: AtomicUsize::new(0),
+            released: Notify::new(),
+        })
+    }
+
+    /// Charges `len` bytes to the budget until the returned `Charge` is dropped.
+    /// Never waits, the bytes are buffered already.
+    pub fn charge(self: &Arc<Self>, len: usize) -> Charge {
+        self.used.fetch_add(len, Ordering::Relaxed);
+        Charge {
+            budget: self.clone(),
+            len,
+        }
+    }
+
+    /// Waits until the budget is not exceeded.
+    pub async fn wait(&self) {
+        loop {
+            // created before the check, so that no release is missed
+            let released = self.released.notified();
+            if !self.is_exceeded() {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// The number of bytes charged.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used.load(Ordering::Relaxed) >= self.limit
+    }
+}
+
+/// Bytes charged to a `MemoryBudget`, released on drop.
+pub struct Charge {
+    budget: Arc<MemoryBudget>,
+    len: usize,
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.len, Ordering::Relaxed);
+        self.budget.released.notify_waiters();
+    }
+}
+
+/// A value charged to a `MemoryBudget` for as long as any clone of it is kept.
+#[derive(Clone)]
+pub struct Charged<T> {
+    pub value: T,
+    _charge: Arc<Charge>,
+}
+
+impl<T> Charged<T> {
+    pub fn new(value: T, charge: Charge) -> Self {
+        Self {
+            value,
+            _charge: Arc::new(charge),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    #[tokio::test]
+    async fn test_memory_budget() {
+        let budget = MemoryBudget::new(100);
+        let first = Charged::new((), budget.charge(60));
+        let second = budget.charge(40);
+        assert!(budget.is_exceeded());
+        let clone = first.clone();
+        drop(first);
+        assert!(budget.is_exceeded());
+
+        let waiter = tokio::spawn({
+            let budget = budget.clone();
+            async move { budget.wait().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiter.is_finished());
+        drop(clone);
+        waiter.await.unwrap();
+        assert!(!budget.is_exceeded());
+        drop(second);
+        budget.wait().await;
+    }
+}
@@ -0,0 +1,469 @@
+use crate::aggregation::Share;
+use core::{net::SocketAddr, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+/// A message sent over a unidirectional stream.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    Gossip(Arc<Envelope>),
+    /// Sent by one end of a connection at `sent`, answered with `Pong`.
+    Ping {
+        sent: u64,
+    },
+    /// An answer to a `Ping` sent at `ping_sent`, received at `ping_received`.
+    Pong {
+        ping_sent: u64,
+        ping_received: u64,
+        sent: u64,
+    },
+    Aggregate(Share),
+    /// Asks for the messages of each origin with nonces greater than the paired one,
+    /// sent by a peer which was away, answered with `History`.
+    HistoryRequest {
+        since: Vec<(SocketAddr, u64)>,
+    },
+    /// Asks for the messages of `origin` with sequence numbers in `seqs`,
+    /// found missing by the receiver, answered with `History`.
+    RetransmitRequest {
+        origin: SocketAddr,
+        seqs: Range<u64>,
+    },
+    /// A message for the peer listening on `to` only, forwarded to it by relays.
+    Direct {
+        to: SocketAddr,
+        kind: DirectKind,
+        /// The number of links the message traversed, counting the one it is sent over.
+        hops: u32,
+        envelope: Arc<Envelope>,
+    },
+    /// A message sent again in answer to a `HistoryRequest` or a `RetransmitRequest`.
+    History(Arc<Envelope>),
+    /// The last message of each origin kept by the sender with the kv handler,
+    /// sent on connecting before the messages sent from then on.
+    Snapshot(Vec<Arc<Envelope>>),
+    /// The digest of the membership view of the sender, answered with `Membership`
+    /// by a receiver whose view has a different digest.
+    MembershipDigest(u64),
+    /// The membership view of the sender: the peers it is connected to and itself.
+    Membership(Vec<SocketAddr>),
+    /// A gossip message forwarded by a peer other than its origin,
+    /// which traversed `hops` links, counting the one it is sent over.
+    Relayed {
+        hops: u32,
+        envelope: Arc<Envelope>,
+    },
+}
+
+impl Message {
+    /// Returns the name of the variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Gossip(_) => "Gossip",
+            Self::Ping { .. } => "Ping",
+            Self::Pong { .. } => "Pong",
+            Self::Aggregate(_) => "Aggregate",
+            Self::HistoryRequest { .. } => "HistoryRequest",
+            Self::RetransmitRequest { .. } => "RetransmitRequest",
+            Self::Direct { .. } => "Direct",
+            Self::History(_) => "History",
+            Self::Snapshot(_) => "Snapshot",
+            Self::MembershipDigest(_) => "MembershipDigest",
+            Self::Membership(_) => "Membership",
+            Self::Relayed { .. } => "Relayed",
+        }
+    }
+
+    /// Returns the gossip or direct message carried, if any.
+    pub fn envelope(&self) -> Option<&Arc<Envelope>> {
+        match self {
+            Self::Gossip(envelope)
+            | Self::History(envelope)
+            | Self::Direct { envelope, .. }
+            | Self::Relayed { envelope, .. } => Some(envelope),
+            _ => None,
+        }
+    }
+}
+
+/// What a direct message is for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DirectKind {
+    /// Delivered like gossip messages.
+    Message,
+    /// An RPC request with an ID, answered with a `Response` or a `Failure` with the same ID.
+    Request(u64),
+    Response(u64),
+    /// The error of a request, in the body.
+    Failure(u64),
+    /// A ping with an ID, answered with a `Pong` with the same ID.
+    Ping(u64),
+    /// The answer to a ping, which took `hops` links.
+    Pong {
+        id: u64,
+        hops: u32,
+    },
+    /// A route trace with an ID, to which each relay on the way appends its listen address,
+    /// answered with a `TraceReply` with the same ID.
+    Trace {
+        id: u64,
+        path: Vec<SocketAddr>,
+    },
+    /// The answer to a route trace, with the relays on its way and the traced peer.
+    TraceReply {
+        id: u64,
+        path: Vec<SocketAddr>,
+    },
+    /// A query with an ID for the peers connected to, answered with
+    /// a `NeighborsReply` with the same ID.
+    Neighbors(u64),
+    NeighborsReply {
+        id: u64,
+        neighbors: Vec<SocketAddr>,
+    },
+}
+
+impl DirectKind {
+    /// Returns the kind of the message as forwarded by the relay listening on `relay`.
+    pub fn forwarded_by(self, relay: SocketAddr) -> Self {
+        match self {
+            Self::Trace { id, mut path } => {
+                path.push(relay);
+                Self::Trace { id, path }
+            }
+            kind => kind,
+        }
+    }
+}
+
+/// A gossip message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope {
+    /// The listen address of the peer which created the message.
+    pub origin: SocketAddr,
+    /// Strictly increasing across all the messages of `origin`.
+    pub nonce: u64,
+    /// Numbers the messages of `origin` one by one from 1,
+    /// starting over when it restarts, so that receivers can tell the missing ones.
+    /// 0 for direct messages, which are not numbered.
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch, by the clock of `origin`.
+    pub timestamp: u64,
+    /// Milliseconds since the Unix epoch, by the clock of `origin`, after which
+    /// the message is neither delivered nor forwarded.
+    pub expires: Option<u64>,
+    pub body: String,
+}
+
+/// A gossip message queued for the peers.
+#[derive(Clone, Debug)]
+pub struct Queued {
+    pub envelope: Arc<Envelope>,
+    /// The number of links the message traversed to this node, 0 for its own messages.
+    pub hops: u32,
+}
+
+impl Queued {
+    /// The message to send it to a peer in.
+    pub fn message(&self) -> Message {
+        match self.hops {
+            0 => Message::Gossip(self.envelope.clone()),
+            hops => Message::Relayed {
+                hops: hops + 1,
+                envelope: self.envelope.clone(),
+            },
+        }
+    }
+}
+
+impl Envelope {
+    /// Whether the message is expired at `origin_now`, the time by the clock of `origin`.
+    pub fn is_expired(&self, origin_now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires < origin_now)
+    }
+}
+
+/// Returns the milliseconds elapsed since the Unix epoch.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as _
+}
+
+/// Generates nonces for the messages of this peer.
+///
+/// The nonces are based on the current time, so that they keep
+/// increasing across restarts of the peer.
+#[derive(Default)]
+pub struct NonceGenerator(AtomicU64);
+
+impl NonceGenerator {
+    pub fn next(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        let prev = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |prev| {
+                Some(now.max(prev + 1))
+            })
+            .unwrap();
+        now.max(prev + 1)
+    }
+}
+
+/// The reason a message was rejected by `ReplayGuard`.
+#[derive(Error, Debug, PartialEq)]
+pub enum Rejection {
+    #[error("too old")]
+    TooOld,
+    #[error("timestamp in the future")]
+    FromTheFuture,
+    #[error("expired")]
+    Expired,
+    #[error("nonce already seen")]
+    Replayed,
+    #[error("origin is not the sending peer")]
+    OriginMismatch,
+}
+
+/// The number of relayed nonces at which the ones of messages too old are first forgotten.
+const MIN_PRUNE_AT: usize = 1024;
+
+/// Rejects messages older than `max_age`, expired ones and ones with nonces
+/// not greater than the last nonce seen from the same origin.
+///
+/// Since envelopes are not signed, only the messages received from their origins
+/// move the last nonce, so that no peer can make the later messages of another
+/// origin rejected by relaying one with a made-up nonce. The nonces of relayed
+/// messages are remembered one by one for `max_age` instead.
+pub struct ReplayGuard {
+    max_age: Duration,
+    last_nonces: HashMap<SocketAddr, u64>,
+    /// The timestamps of the messages by their origins and nonces,
+    /// for the messages above the last nonces received from others than their origins.
+    relayed: HashMap<(SocketAddr, u64), u64>,
+    /// The number of relayed nonces at which the ones too old are forgotten next.
+    prune_at: usize,
+}
+
+impl ReplayGuard {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            last_nonces: HashMap::new(),
+            relayed: HashMap::new(),
+            prune_at: MIN_PRUNE_AT,
+        }
+    }
+
+    /// Checks `envelope` received at `now` milliseconds since the Unix epoch,
+    /// `from_origin` or relayed by another peer, and records its nonce if it is accepted.
+    pub fn check(
+        &mut self,
+        envelope: &Envelope,
+        now: u64,
+        from_origin: bool,
+    ) -> Result<(), Rejection> {
+        let max_age = self.max_age.as_millis() as u64;
+        if envelope.timestamp.saturating_add(max_age) < now {
+            return Err(Rejection::TooOld);
+        }
+        if envelope.timestamp > now.saturating_add(max_age) {
+            return Err(Rejection::FromTheFuture);
+        }
+        if envelope.is_expired(now) {
+            return Err(Rejection::Expired);
+        }
+        let last_nonce = self.last_nonces.get(&envelope.origin).copied();
+        if envelope.nonce <= last_nonce.unwrap_or(0) {
+            return Err(Rejection::Replayed);
+        }
+        let key = (envelope.origin, envelope.nonce);
+        if from_origin {
+            self.last_nonces.insert(envelope.origin, envelope.nonce);
+            if self.relayed.remove(&key).is_some() {
+                return Err(Rejection::Replayed);
+            }
+        } else {
+            if self.relayed.insert(key, envelope.timestamp).is_some() {
+                return Err(Rejection::Replayed);
+            }
+            if self.relayed.len() >= self.prune_at {
+                self.prune(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the relayed nonces of the messages which are too old at `now`
+    /// or not above the last nonces of their origins.
+    fn prune(&mut self, now: u64) {
+        let max_age = self.max_age.as_millis() as u64;
+        let last_nonces = &self.last_nonces;
+        self.relayed.retain(|(origin, nonce), timestamp| {
+            timestamp.saturating_add(max_age) >= now
+                && last_nonces.get(origin).is_none_or(|last| nonce > last)
+        });
+        self.prune_at = (2 * self.relayed.len()).max(MIN_PRUNE_AT);
+    }
+
+    /// The number of origins seen.
+    pub fn origin_count(&self) -> usize {
+        let relayed: HashSet<_> = self
+            .relayed
+            .keys()
+            .map(|(origin, _)| origin)
+            .filter(|origin| !self.last_nonces.contains_key(origin))
+            .collect();
+        self.last_nonces.len() + relayed.len()
+    }
+
+    /// Returns the last nonce received from each origin itself.
+    pub fn last_nonces(&self) -> impl Iterator<Item = (SocketAddr, u64)> + '_ {
+        self.last_nonces
+            .iter()
+            .map(|(&origin, &nonce)| (origin, nonce))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(nonce: u64, timestamp: u64) -> Envelope {
+        Envelope {
+            origin: "127.0.0.1:8080".parse().unwrap(),
+            nonce,
+            seq: nonce,
+            timestamp,
+            expires: None,
+            body: "hello".into(),
+        }
+    }
+
+    #[test]
+    fn test_forwarded_by() {
+        let relay = "127.0.0.1:8081".parse().unwrap();
+        let trace = DirectKind::Trace {
+            id: 1,
+            path: Vec::new(),
+        };
+        assert_eq!(
+            trace.forwarded_by(relay).forwarded_by(relay),
+            DirectKind::Trace {
+                id: 1,
+                path: vec![relay, relay]
+            }
+        );
+        assert_eq!(DirectKind::Ping(1).forwarded_by(relay), DirectKind::Ping(1));
+    }
+
+    #[test]
+    fn test_queued_message() {
+        let envelope = Arc::new(envelope(1, 0));
+        let queued = |hops| Queued {
+            envelope: envelope.clone(),
+            hops,
+        };
+        assert_eq!(queued(0).message(), Message::Gossip(envelope.clone()));
+        // counting the link it is sent over
+        assert_eq!(
+            queued(1).message(),
+            Message::Relayed {
+                hops: 2,
+                envelope: envelope.clone()
+            }
+        );
+    }
+
+    #[test]
+    fn test_nonce_generator() {
+        let nonces = NonceGenerator::default();
+        let mut prev = nonces.next();
+        for _ in 0..1000 {
+            let nonce = nonces.next();
+            assert!(nonce > prev);
+            prev = nonce;
+        }
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let mut guard = ReplayGuard::new(Duration::from_secs(60));
+        let now = 1_000_000;
+        assert_eq!(guard.check(&envelope(5, now), now, true), Ok(()));
+        assert_eq!(
+            guard.check(&envelope(5, now), now, true),
+            Err(Rejection::Replayed)
+        );
+        assert_eq!(
+            guard.check(&envelope(4, now), now, true),
+            Err(Rejection::Replayed)
+        );
+        assert_eq!(guard.check(&envelope(6, now - 60_000), now, true), Ok(()));
+        assert_eq!(
+            guard.check(&envelope(7, now - 60_001), now, true),
+            Err(Rejection::TooOld)
+        );
+        assert_eq!(
+            guard.check(&envelope(7, now + 60_001), now, true),
+            Err(Rejection::FromTheFuture)
+        );
+        assert_eq!(guard.check(&envelope(7, now), now, true), Ok(()));
+        let expired = Envelope {
+            expires: Some(now - 1),
+            ..envelope(8, now - 1000)
+        };
+        assert_eq!(guard.check(&expired, now, true), Err(Rejection::Expired));
+        let expiring = Envelope {
+            expires: Some(now),
+            ..envelope(8, now - 1000)
+        };
+        assert_eq!(guard.check(&expiring, now, true), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_guard_relayed() {
+        let mut guard = ReplayGuard::new(Duration::from_secs(60));
+        let now = 1_000_000;
+        // a made-up nonce relayed by another peer
+        assert_eq!(guard.check(&envelope(u64::MAX, now), now, false), Ok(()));
+        assert_eq!(
+            guard.check(&envelope(u64::MAX, now), now, false),
+            Err(Rejection::Replayed)
+        );
+        // does not stop the later messages of the origin
+        assert_eq!(guard.check(&envelope(5, now), now, true), Ok(()));
+        // relayed messages are taken once, whichever path they come over first
+        assert_eq!(guard.check(&envelope(7, now), now, false), Ok(()));
+        assert_eq!(
+            guard.check(&envelope(7, now), now, true),
+            Err(Rejection::Replayed)
+        );
+        assert_eq!(
+            guard.check(&envelope(6, now), now, false),
+            Err(Rejection::Replayed)
+        );
+        assert_eq!(guard.origin_count(), 1);
+
+        // forgotten once too old
+        for nonce in 10..10 + MIN_PRUNE_AT as u64 {
+            guard.check(&envelope(nonce, now), now, false).unwrap();
+        }
+        assert!(guard.relayed.len() <= MIN_PRUNE_AT + 1);
+        guard.prune(now + 60_001);
+        assert!(guard.relayed.is_empty());
+    }
+}
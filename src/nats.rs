@@ -0,0 +1,106 @@
+use crate::{handler::Handler, log::log, message::Envelope, producer::broadcast_message, Context};
+use async_nats::{Client, ConnectOptions};
+use core::net::SocketAddr;
+use futures::StreamExt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// The maximum number of messages waiting to be published.
+const QUEUE_LEN: usize = 1000;
+
+/// Publishes each received message to a NATS subject.
+pub struct NatsPublisher {
+    queue: mpsc::Sender<Arc<Envelope>>,
+}
+
+impl NatsPublisher {
+    /// Returns the publisher and the receiver of the messages
+    /// to be passed to `bridge_loop`.
+    pub fn new() -> (Self, mpsc::Receiver<Arc<Envelope>>) {
+        let (queue, receiver) = mpsc::channel(QUEUE_LEN);
+        (Self { queue }, receiver)
+    }
+}
+
+impl Handler for NatsPublisher {
+    fn on_message(&self, _from: SocketAddr, envelope: &Arc<Envelope>) {
+        if self.queue.try_send(envelope.clone()).is_err() {
+            log(&[
+                b"Skipped publishing message [",
+                envelope.body.as_bytes(),
+                b"] to NATS, the queue is full",
+            ]);
+        }
+    }
+}
+
+/// Connects to the NATS server at `url`, reconnecting whenever
+/// the connection is lost. Publishes the messages from `receiver`
+/// to `publish` and sends the messages of `subscribe` to all peers.
+/// Logs errors on failure.
+pub async fn bridge_loop(
+    ctx: Context,
+    url: String,
+    publish: Option<String>,
+    subscribe: Option<String>,
+    receiver: Option<mpsc::Receiver<Arc<Envelope>>>,
+) {
+    let res = ConnectOptions::new()
+        .retry_on_initial_connect()
+        // do not bridge the published messages back
+        .no_echo()
+        .event_callback(|event| async move {
+            log(&[b"NATS ", event.to_string().as_bytes()]);
+        })
+        .connect(url)
+        .await;
+    let client = match res {
+        Ok(client) => client,
+        Err(e) => {
+            log(&[
+                b"Failed to connect to NATS, error: ",
+                e.to_string().as_bytes(),
+            ]);
+            return;
+        }
+    };
+
+    if let (Some(subject), Some(receiver)) = (publish, receiver) {
+        tokio::spawn(publish_loop(client.clone(), subject, receiver));
+    }
+    if let Some(subject) = subscribe {
+        let mut subscriber = match client.subscribe(subject).await {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                log(&[
+                    b"Failed to subscribe to NATS, error: ",
+                    e.to_string().as_bytes(),
+                ]);
+                return;
+            }
+        };
+        while let Some(message) = subscriber.next().await {
+            let body = String::from_utf8_lossy(&message.payload).into_owned();
+            broadcast_message(&ctx, body).await;
+        }
+    }
+}
+
+/// Publishes the messages from `receiver` to `subject`. Logs errors on failure.
+async fn publish_loop(
+    client: Client,
+    subject: String,
+    mut receiver: mpsc::Receiver<Arc<Envelope>>,
+) {
+    while let Some(envelope) = receiver.recv().await {
+        let payload = envelope.body.clone().into_bytes().into();
+        if let Err(e) = client.publish(subject.clone(), payload).await {
+            log(&[
+                b"Failed to publish message [",
+                envelope.body.as_bytes(),
+                b"] to NATS, error: ",
+                e.to_string().as_bytes(),
+            ]);
+        }
+    }
+}
@@ -0,0 +1,772 @@
+#[cfg(feature = "acme")]
+use crate::acme;
+#[cfg(feature = "gossipsub")]
+use crate::gossipsub;
+#[cfg(feature = "kafka")]
+use crate::kafka;
+#[cfg(feature = "nats")]
+use crate::nats;
+use crate::{
+    address_rules::GossipScores,
+    address_validation::AddressValidation,
+    admin::admin_loop,
+    aggregation::Aggregator,
+    audit::AuditLog,
+    blocklist::Blocklist,
+    config::{
+        configure_client, configure_server, configure_transport, read_certs_from_file,
+        read_crls_from_file, read_identity_from_p12, self_signed, Crls, Verification,
+    },
+    connection::accept_loop,
+    dedup::DedupCache,
+    drain::Drain,
+    dscp::DscpUdpSocket,
+    error::CloseReason,
+    handler::{ExecHook, Handler, HandlerKind, KvStore, Logger, Webhook},
+    history::History,
+    log::log,
+    mailbox::MailboxLimits,
+    membership::PartitionDetector,
+    memory_budget::MemoryBudget,
+    message::{NonceGenerator, ReplayGuard},
+    observer::Observer,
+    peer_list::MAX_ALTERNATES,
+    peering::{
+        aggregation_loop, connect_pinned, initial_connect, membership_loop, peers_file_loop,
+    },
+    peers::PeerTable,
+    producer::producer_loop,
+    quarantine::Quarantine,
+    reorder::Reorderer,
+    rpc::{Calls, ExecResponder},
+    sequence::{SequenceGenerator, SequenceTracker},
+    sessions::SessionTracker,
+    shard::{attach_steering_program, ShardedCidGenerator},
+    socket::{self, disable_gro, TunedUdpSocket},
+    socks5::Socks5UdpSocket,
+    state_dump,
+    static_peers::read_peers_file,
+    stats::{PathStats, Stats},
+    statsd::Statsd,
+    supervisor::{RestartPolicy, Supervisor},
+    throttle::Throttle,
+    token_bucket::TokenBucket,
+    utils::{interface_addresses, resolve, set_tclass_v6, PortRange, ScopedIp},
+    Bootstrap, Config, Context, MESSAGE_CHANNEL_CAPACITY,
+};
+#[cfg(feature = "spiffe")]
+use crate::{config, spiffe};
+use core::{net::SocketAddr, time::Duration};
+use quinn::{udp::UdpState, Endpoint, EndpointConfig, TokioRuntime};
+use rustls::{Certificate, PrivateKey};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, Mutex, Notify},
+    time::Instant,
+};
+
+/// Starts a node as per `config` with `extra_handlers`, which runs until
+/// it is shut down with `shut_down`.
+pub async fn start_node(
+    config: Config,
+    extra_handlers: Vec<Arc<dyn Handler>>,
+) -> io::Result<Context> {
+    let ip = match &config.interface {
+        Some(interface) => interface_ip(interface)?,
+        None => config.ip,
+    };
+    let socket_options = SocketOptions {
+        interface: config.interface.clone(),
+        reuse_port: config.reuse_port || config.endpoints > 1,
+        send_buffer: config.send_buffer,
+        recv_buffer: config.recv_buffer,
+        dscp: config.dscp,
+    };
+    let (socket, addr) = bind_port_range(ip, config.port, &socket_options)?;
+    let mut sockets = vec![socket];
+    for _ in 1..config.endpoints {
+        sockets.push(bind_socket(addr, &socket_options)?);
+    }
+    if config.endpoints > 1 {
+        attach_steering_program(&sockets[0], config.endpoints)?;
+    }
+    if config.advertise.len() > MAX_ALTERNATES {
+        return Err(io::Error::other(format!(
+            "at most {MAX_ALTERNATES} addresses can be advertised with --advertise"
+        )));
+    }
+    // the address the node is known by
+    let addr = config.external_address.unwrap_or(addr);
+
+    #[cfg(feature = "spiffe")]
+    let mut spiffe = match &config.spiffe_socket {
+        Some(socket) => Some(
+            spiffe::Spiffe::connect(socket, config.spiffe_trust_domain.as_deref())
+                .await
+                .map_err(io::Error::other)
+                .inspect_err(|e| {
+                    log(&[
+                        b"Failed to connect to the Workload API, error: ",
+                        e.to_string().as_bytes(),
+                    ])
+                })?,
+        ),
+        None => None,
+    };
+    #[cfg(feature = "spiffe")]
+    let res = match &mut spiffe {
+        Some(spiffe) => spiffe.fetch().await.map_err(io::Error::other),
+        None => read_identity(
+            config.identity_p12.as_deref(),
+            &config.cert,
+            &config.key,
+            config.key_pass_file.as_deref(),
+        ),
+    };
+    #[cfg(not(feature = "spiffe"))]
+    let res = read_identity(
+        config.identity_p12.as_deref(),
+        &config.cert,
+        &config.key,
+        config.key_pass_file.as_deref(),
+    );
+    #[cfg(feature = "acme")]
+    let res = match (res, &config.acme_domain) {
+        // until a certificate is obtained
+        (Err(_), Some(domain)) => self_signed(vec![domain.clone()]).map_err(io::Error::other),
+        (res, _) => res,
+    };
+    let res = match res {
+        Err(_) if config.ephemeral_cert && !(config.cert.exists() && config.key.exists()) => {
+            log(&[
+                b"WARNING: ",
+                config.cert.display().to_string().as_bytes(),
+                b" or ",
+                config.key.display().to_string().as_bytes(),
+                b" not found, using an ephemeral self-signed certificate, \
+                  which peers verifying certificates reject",
+            ]);
+            self_signed(vec![addr.ip().to_string(), "localhost".to_owned()])
+                .map_err(io::Error::other)
+        }
+        res => res,
+    };
+    let (certs, key) = res.inspect_err(|e| {
+        log(&[
+            b"Failed to load the certificate, error: ",
+            e.to_string().as_bytes(),
+        ]);
+        if e.kind() == io::ErrorKind::NotFound && !config.ephemeral_cert {
+            log(&[b"Pass --ephemeral-cert to run with a self-signed certificate instead"]);
+        }
+    })?;
+    let verification = if config.skip_server_verification {
+        Verification::Skip
+    } else if config.skip_hostname_verification {
+        Verification::SkipHostname
+    } else {
+        Verification::Full
+    };
+    let static_peers = config
+        .peers_file
+        .as_deref()
+        .map(read_peers_file)
+        .transpose()?
+        .unwrap_or_default();
+    let blocklist = match config.blocklist {
+        Some(path) => Blocklist::load(path)?,
+        None => Blocklist::default(),
+    };
+    let statsd = config
+        .statsd
+        .map(|addr| Statsd::new(&addr, config.statsd_prefix, config.statsd_sample_rate))
+        .transpose()?;
+    let emit_path_stats = statsd.is_some();
+    let stats = Arc::new(Stats::new(statsd));
+    let supervisor = Arc::new(Supervisor::new(stats.clone()));
+    let crls = match &config.crl {
+        Some(crl) => {
+            let crls = Arc::new(RwLock::new(read_crls_from_file(crl)?));
+            supervisor.spawn(
+                "crl_refresh",
+                crl_refresh_loop(config.crl_refresh, crl.clone(), crls.clone()),
+            );
+            Some(crls)
+        }
+        None => None,
+    };
+    let transport = configure_transport(config.congestion_controller, config.initial_window);
+    let client_config = configure_client(
+        verification,
+        crls.clone(),
+        config
+            .verify_peer_identity
+            .then(|| (certs.clone(), key.clone())),
+        transport.clone(),
+    )?;
+    #[cfg(feature = "spiffe")]
+    let client_config = match spiffe.as_ref().and_then(spiffe::Spiffe::verifier) {
+        Some(verifier) => config::client_config(verifier, None, transport.clone())?,
+        None => client_config,
+    };
+    let server_config = configure_server(
+        certs,
+        key,
+        config.verify_peer_identity,
+        crls,
+        transport.clone(),
+    )?;
+    let shards = config.endpoints;
+    let endpoints: Arc<[Endpoint]> = sockets
+        .into_iter()
+        .zip(0..)
+        .map(|(socket, shard)| {
+            let mut endpoint_config = EndpointConfig::default();
+            if shards > 1 {
+                endpoint_config
+                    .cid_generator(move || Box::new(ShardedCidGenerator::new(shard, shards)));
+            }
+            // quinn turns GRO on when it takes the socket
+            let gro_socket = config.no_gro.then(|| socket.try_clone()).transpose()?;
+            let dscp_socket = config.dscp.map(|_| socket.try_clone()).transpose()?;
+            let socket =
+                TunedUdpSocket::new(socket, !config.no_gso, !config.no_ecn, stats.clone())?;
+            let mut endpoint = match dscp_socket {
+                Some(dscp_socket) => Endpoint::new_with_abstract_socket(
+                    endpoint_config,
+                    Some(server_config.clone()),
+                    DscpUdpSocket::new(dscp_socket, Box::new(socket))?,
+                    Arc::new(TokioRuntime),
+                )?,
+                None => Endpoint::new_with_abstract_socket(
+                    endpoint_config,
+                    Some(server_config.clone()),
+                    socket,
+                    Arc::new(TokioRuntime),
+                )?,
+            };
+            if let Some(socket) = gro_socket {
+                disable_gro(&socket)?;
+            }
+            endpoint.set_default_client_config(client_config.clone());
+            Ok(endpoint)
+        })
+        .collect::<io::Result<_>>()?;
+    let udp_state = UdpState::new();
+    log(&[
+        b"Segmentation offload: ",
+        socket::describe(
+            "GSO",
+            udp_state.max_gso_segments(),
+            !config.no_gso && config.dscp.is_none(),
+        )
+        .as_bytes(),
+        b"; ",
+        socket::describe("GRO", udp_state.gro_segments(), !config.no_gro).as_bytes(),
+    ]);
+    let address_validation = Arc::new(AddressValidation::new(
+        endpoints.clone(),
+        config.retry_threshold,
+    ));
+    address_validation.set_server_config(Some(server_config));
+    #[cfg(feature = "acme")]
+    if let Some(domain) = config.acme_domain {
+        supervisor.spawn(
+            "acme_renew",
+            acme::renew_loop(
+                acme::Acme {
+                    domain,
+                    contact: config.acme_contact,
+                    directory: config.acme_directory,
+                    http_listen: config.acme_http_listen,
+                    account: config.acme_account,
+                    cert: config.cert.clone(),
+                    key: config.key.clone(),
+                },
+                address_validation.clone(),
+                transport.clone(),
+            ),
+        );
+    }
+    #[cfg(feature = "spiffe")]
+    if let Some(spiffe) = spiffe {
+        supervisor.spawn(
+            "spiffe_rotate",
+            spiffe::rotate_loop(spiffe, address_validation.clone(), transport.clone()),
+        );
+    }
+    let dialers = if let Some(proxy) = config.proxy {
+        let proxy = *resolve(&proxy)
+            .await?
+            .first()
+            .ok_or(io::ErrorKind::NotFound)?;
+        let mut dialer = Endpoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            None,
+            Socks5UdpSocket::associate(proxy).await?,
+            Arc::new(TokioRuntime),
+        )?;
+        dialer.set_default_client_config(client_config);
+        Arc::from([dialer])
+    } else {
+        endpoints.clone()
+    };
+
+    let kv_store = config
+        .handler
+        .contains(&HandlerKind::Kv)
+        .then(|| Arc::new(KvStore::default()));
+    let mut handlers: Vec<_> = config
+        .handler
+        .iter()
+        .map(|kind| -> Arc<dyn Handler> {
+            match kind {
+                HandlerKind::Logger => Arc::new(Logger),
+                HandlerKind::Kv => kv_store.clone().unwrap(),
+            }
+        })
+        .collect();
+    handlers.extend(extra_handlers);
+    if let Some(command) = config.on_message {
+        handlers.push(Arc::new(ExecHook::new(
+            command,
+            config.on_message_concurrency,
+            config.on_message_rate,
+        )));
+    }
+    if let Some(url) = config.webhook {
+        handlers.push(Arc::new(Webhook::new(url, config.webhook_queue)));
+    }
+    #[cfg(feature = "gossipsub")]
+    let gossipsub_receiver =
+        if config.gossipsub_listen.is_empty() && config.gossipsub_dial.is_empty() {
+            None
+        } else {
+            let (bridge, receiver) = gossipsub::GossipsubBridge::new();
+            handlers.push(Arc::new(bridge));
+            Some(receiver)
+        };
+    #[cfg(feature = "nats")]
+    let nats_receiver = config.nats_publish.is_some().then(|| {
+        let (publisher, receiver) = nats::NatsPublisher::new();
+        handlers.push(Arc::new(publisher));
+        receiver
+    });
+    #[cfg(feature = "kafka")]
+    if let Some(topic) = config.kafka_publish {
+        handlers.push(Arc::new(kafka::KafkaPublisher::new(
+            config.kafka_brokers.clone(),
+            topic,
+        )));
+    }
+
+    let (message_sender, _) = broadcast::channel(MESSAGE_CHANNEL_CAPACITY);
+    let ctx = Context {
+        endpoints: endpoints.clone(),
+        dialers: dialers.clone(),
+        peers: Arc::new(PeerTable::default()),
+        message_sender,
+        subscribed: Arc::new(Notify::new()),
+        nonces: Arc::new(NonceGenerator::default()),
+        seqs: Arc::new(SequenceGenerator::default()),
+        replay_guard: Arc::new(Mutex::new(ReplayGuard::new(config.max_message_age))),
+        sequences: Arc::new(Mutex::new(SequenceTracker::default())),
+        retransmit_gaps: config.retransmit_gaps,
+        message_ttl: config.message_ttl,
+        clocks: Arc::new(Mutex::new(HashMap::new())),
+        ping_interval: config.ping_interval,
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        sessions: Arc::new(Mutex::new(SessionTracker::default())),
+        aggregator: Arc::new(Mutex::new(Aggregator::new(addr))),
+        quarantine: Arc::new(Mutex::new(Quarantine::new(
+            config.flap_threshold,
+            config.flap_window,
+            config.quarantine,
+        ))),
+        gossip_scores: Arc::new(Mutex::new(GossipScores::default())),
+        partitions: Arc::new(Mutex::new(PartitionDetector::new(
+            2 * config.membership_interval,
+        ))),
+        advertise_private: config.advertise_private,
+        retry_budget: Arc::new(Mutex::new(TokenBucket::new(
+            config.retry_burst,
+            config.retry_rate,
+        ))),
+        accept_budget: config
+            .accept_rate
+            .map(|rate| Arc::new(Mutex::new(TokenBucket::new(config.accept_burst, rate)))),
+        upload: Arc::new(Throttle::new(config.max_upload, config.max_peer_upload)),
+        download: Arc::new(Throttle::new(config.max_download, config.max_peer_download)),
+        max_transfer_size: config.max_transfer_size,
+        address_validation,
+        dial_timeout: config.dial_timeout,
+        flow_window: config.flow_window,
+        adaptive_pacing: config.adaptive_pacing,
+        memory_budget: MemoryBudget::new(config.memory_budget),
+        mailbox_limits: MailboxLimits {
+            messages: config.mailbox_messages,
+            bytes: config.mailbox_bytes,
+            age: config.mailbox_age,
+        },
+        mailboxes: Arc::new(Mutex::new(HashMap::new())),
+        history: (config.history > 0).then(|| Arc::new(Mutex::new(History::new(config.history)))),
+        dedup: (config.dedup_cache > 0)
+            .then(|| Arc::new(Mutex::new(DedupCache::new(config.dedup_cache)))),
+        reconnecting: Arc::new(Mutex::new(HashSet::new())),
+        stats,
+        drain: Arc::new(Drain::default()),
+        supervisor: supervisor.clone(),
+        seed_mode: config.seed_mode,
+        receive_only: config.receive_only,
+        relay_only: config.relay_only,
+        forward: config.forward,
+        max_hops: config.max_hops,
+        verify_peer_identity: config.verify_peer_identity,
+        filters: Arc::new(Mutex::new(config.filter)),
+        handlers: Arc::new(handlers),
+        calls: Arc::new(Calls::default()),
+        pings: Arc::new(Calls::default()),
+        traces: Arc::new(Calls::default()),
+        neighbor_queries: Arc::new(Calls::default()),
+        responder: config
+            .on_request
+            .map(|command| Arc::new(ExecResponder::new(command))),
+        request_timeout: config.request_timeout,
+        reorderer: config
+            .ordered_delivery
+            .then(|| Arc::new(Mutex::new(Reorderer::new(config.reorder_window)))),
+        kv_store,
+        observer: if config.observe {
+            Some(Arc::new(Observer::new(config.capture.as_deref())?))
+        } else {
+            None
+        },
+        audit: match &config.audit_log {
+            Some(path) => Some(Arc::new(AuditLog::open(
+                path,
+                config.audit_log_size,
+                config.audit_log_files,
+            )?)),
+            None => None,
+        },
+        bootstrap: config
+            .connect
+            .into_iter()
+            .map(|host| {
+                Arc::new(Bootstrap {
+                    host,
+                    addr: Mutex::new(None),
+                })
+            })
+            .collect(),
+        pinned: Arc::new(config.pin.into_iter().collect()),
+        static_peers: Arc::new(RwLock::new(static_peers)),
+        blocklist: Arc::new(Mutex::new(blocklist)),
+        external_addr: config.external_address,
+        alternates: config.advertise.into(),
+    };
+    if let Some(path) = config.peers_file {
+        ctx.supervisor.spawn(
+            "peers_file",
+            peers_file_loop(config.peers_file_refresh, path, ctx.clone()),
+        );
+    }
+
+    if let Some(admin) = config.admin {
+        // bound here, so that the node does not start if the address is taken
+        let mut listener = Some(TcpListener::bind(admin).await?);
+        let ctx = ctx.clone();
+        ctx.supervisor.clone().spawn_restarting(
+            "the admin server",
+            RestartPolicy { max_restarts: 5 },
+            move || {
+                let listener = listener.take();
+                let ctx = ctx.clone();
+                async move {
+                    let listener = match listener {
+                        Some(listener) => listener,
+                        None => TcpListener::bind(admin).await?,
+                    };
+                    admin_loop(ctx, listener).await
+                }
+            },
+        );
+    }
+    if emit_path_stats {
+        ctx.supervisor.spawn(
+            "path_stats",
+            path_stats_loop(config.path_stats_interval, ctx.clone()),
+        );
+    }
+    ctx.supervisor.spawn(
+        "membership",
+        membership_loop(config.membership_interval, ctx.clone()),
+    );
+    if !config.receive_only {
+        ctx.supervisor.spawn(
+            "aggregation",
+            aggregation_loop(
+                config.aggregation_interval,
+                config.aggregation_epoch,
+                ctx.clone(),
+            ),
+        );
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(topic) = config.kafka_consume {
+        ctx.supervisor.spawn(
+            "kafka_consume",
+            kafka::consume_loop(ctx.clone(), config.kafka_brokers, topic),
+        );
+    }
+    #[cfg(feature = "gossipsub")]
+    if let Some(receiver) = gossipsub_receiver {
+        ctx.supervisor.spawn(
+            "gossipsub_bridge",
+            gossipsub::bridge_loop(
+                ctx.clone(),
+                config.gossipsub_listen,
+                config.gossipsub_dial,
+                config.gossipsub_topic,
+                receiver,
+            ),
+        );
+    }
+    #[cfg(feature = "nats")]
+    if let Some(url) = config.nats {
+        ctx.supervisor.spawn(
+            "nats_bridge",
+            nats::bridge_loop(
+                ctx.clone(),
+                url,
+                config.nats_publish,
+                config.nats_subscribe,
+                nats_receiver,
+            ),
+        );
+    }
+    ctx.supervisor.spawn(
+        "state_dump",
+        state_dump::dump_loop(ctx.clone(), config.state_dump),
+    );
+    ctx.supervisor
+        .spawn("peer", run_peer(ctx.clone(), addr, config.period));
+
+    Ok(ctx)
+}
+
+/// Drains the node of `ctx` for up to `drain_timeout` and stops it.
+pub async fn shut_down(ctx: &Context, drain_timeout: Duration) {
+    drain(ctx, drain_timeout).await;
+    ctx.supervisor.shutdown().await;
+    for endpoint in ctx.endpoints.iter().chain(&*ctx.dialers) {
+        CloseReason::Shutdown.close_endpoint(endpoint);
+    }
+    for endpoint in ctx.endpoints.iter().chain(&*ctx.dialers) {
+        endpoint.wait_idle().await;
+    }
+}
+
+/// Stops taking new peers and messages, and waits up to `timeout`
+/// for the messages queued to be sent to the peers and acknowledged.
+pub async fn drain(ctx: &Context, timeout: Duration) {
+    ctx.drain.start();
+    ctx.address_validation.set_server_config(None);
+    let drained = tokio::time::timeout(timeout, async {
+        // the queue is emptied by the senders and the mailboxes
+        while !ctx.message_sender.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        ctx.drain.wait_idle().await;
+    })
+    .await;
+    if drained.is_err() {
+        log(&[
+            b"Timed out draining, ",
+            ctx.message_sender.len().to_string().as_bytes(),
+            b" messages queued, ",
+            ctx.drain.in_flight().to_string().as_bytes(),
+            b" being sent",
+        ]);
+    }
+}
+
+/// Reads the certificate chain and the key from `--identity-p12`, or from `--cert` and `--key`.
+fn read_identity(
+    identity_p12: Option<&Path>,
+    cert: &Path,
+    key: &Path,
+    key_pass_file: Option<&Path>,
+) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+    Ok(match identity_p12 {
+        Some(p12) => read_identity_from_p12(p12, key_pass_file),
+        None => read_certs_from_file(cert, key, key_pass_file),
+    }?)
+}
+
+/// Once in `duration`, reads the certificate revocation lists
+/// from `path` into `crls`. Logs errors on failure.
+async fn crl_refresh_loop(duration: Duration, path: PathBuf, crls: Crls) {
+    let mut interval = tokio::time::interval_at(Instant::now() + duration, duration);
+    loop {
+        interval.tick().await;
+        match read_crls_from_file(&path) {
+            Ok(new_crls) => *crls.write().unwrap() = new_crls,
+            Err(e) => log(&[
+                b"Failed to read the certificate revocation lists, error: ",
+                e.to_string().as_bytes(),
+            ]),
+        }
+    }
+}
+
+/// Picks an address of the network `interface` to run on, preferring IPv4.
+fn interface_ip(interface: &str) -> io::Result<ScopedIp> {
+    let addrs = interface_addresses(interface)?;
+    addrs
+        .iter()
+        .find(|addr| addr.ip.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no addresses on interface {interface:?}"),
+            )
+        })
+}
+
+/// Options of the UDP socket of the endpoint.
+struct SocketOptions {
+    /// The network interface to bind to, if possible.
+    interface: Option<String>,
+    reuse_port: bool,
+    send_buffer: Option<usize>,
+    recv_buffer: Option<usize>,
+    dscp: Option<u8>,
+}
+
+/// Binds a UDP socket to the first port of `ports` which is not in use.
+fn bind_port_range(
+    ip: ScopedIp,
+    ports: PortRange,
+    options: &SocketOptions,
+) -> io::Result<(UdpSocket, SocketAddr)> {
+    let last_port = *ports.0.end();
+    for port in ports.0 {
+        let addr = ip.with_port(port);
+        match bind_socket(addr, options) {
+            Ok(socket) => return Ok((socket, addr)),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && port != last_port => {}
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("the port range is not empty")
+}
+
+/// Binds a UDP socket with `options` to `addr`.
+fn bind_socket(addr: SocketAddr, options: &SocketOptions) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    if let Some(interface) = &options.interface {
+        // requires CAP_NET_RAW on older kernels,
+        // binding to an address of the interface is enough otherwise
+        if let Err(e) = socket.bind_device(Some(interface.as_bytes())) {
+            log(&[
+                b"Failed to bind to interface ",
+                interface.as_bytes(),
+                b", error: ",
+                e.to_string().as_bytes(),
+            ]);
+        }
+    }
+    if options.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size)?;
+        warn_if_buffer_capped("Send", socket.send_buffer_size()?, size);
+    }
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+        warn_if_buffer_capped("Receive", socket.recv_buffer_size()?, size);
+    }
+    if let Some(dscp) = options.dscp {
+        // the lower 2 bits are left for ECN
+        let tos = dscp << 2;
+        if addr.is_ipv4() {
+            socket.set_tos(tos.into())?;
+        } else {
+            set_tclass_v6(&socket, tos)?;
+        }
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Logs if the system gave a socket buffer of `actual` bytes
+/// smaller than the `requested` one.
+fn warn_if_buffer_capped(kind: &str, actual: usize, requested: usize) {
+    if actual < requested {
+        log(&[
+            kind.as_bytes(),
+            b" buffer of the socket is capped to ",
+            actual.to_string().as_bytes(),
+            b" bytes instead of ",
+            requested.to_string().as_bytes(),
+            b", raise the system limit to get more",
+        ]);
+    }
+}
+
+/// Runs a new peer on `ctx.endpoints`, known to its peers by `addr`.
+async fn run_peer(ctx: Context, addr: SocketAddr, period: Option<Duration>) {
+    log(&[b"My address is \"", addr.to_string().as_bytes(), b"\""]);
+
+    if !ctx.bootstrap.is_empty() {
+        initial_connect(ctx.clone()).await;
+    }
+    let static_peers: Vec<_> = ctx.static_peers.read().unwrap().keys().copied().collect();
+    for &peer in ctx.pinned.iter().chain(&static_peers) {
+        ctx.supervisor
+            .spawn("connect_pinned", connect_pinned(ctx.clone(), peer));
+    }
+
+    if let Some(period) = period {
+        ctx.supervisor
+            .spawn("producer", producer_loop(period, ctx.clone()));
+    }
+
+    for endpoint in ctx.endpoints.iter() {
+        let ctx = ctx.clone();
+        let endpoint = endpoint.clone();
+        ctx.supervisor.clone().spawn_restarting(
+            "the accept loop",
+            RestartPolicy { max_restarts: 10 },
+            move || accept_loop(ctx.clone(), endpoint.clone()),
+        );
+    }
+}
+
+/// Once in `duration`, emits the QUIC path statistics of each connection.
+async fn path_stats_loop(duration: Duration, ctx: Context) {
+    let mut interval = tokio::time::interval(duration);
+    loop {
+        interval.tick().await;
+        let sessions = ctx.sessions.lock().await;
+        for (&peer, connection) in ctx.connections.lock().await.iter() {
+            ctx.stats.path(peer, &PathStats::of(connection));
+            if let Some(stats) = sessions.stats(peer, Instant::now()) {
+                ctx.stats.sessions(peer, &stats);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    log::{log, log_event},
+    message::unix_millis,
+    utils::serialize_address,
+};
+use core::net::SocketAddr;
+use quinn::{StreamId, VarInt};
+use std::{fs::File, io::Write, path::Path, sync::Mutex};
+
+/// Written at the start of a capture file.
+const CAPTURE_MAGIC: &[u8] = b"P2PGCAP1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Logs every stream sent or received with `--observe`,
+/// and optionally writes it to a capture file.
+///
+/// A capture file starts with `CAPTURE_MAGIC`, followed by records of:
+/// - milliseconds since the Unix epoch, `u64`
+/// - direction, `u8`, 0 for sent and 1 for received
+/// - the remote address, serialized with `serialize_address`
+/// - the stream ID, `u64`
+/// - the length of the data, `u32`
+/// - the data
+///
+/// All integers are little-endian.
+pub struct Observer {
+    capture: Option<Mutex<File>>,
+}
+
+impl Observer {
+    pub fn new(capture: Option<&Path>) -> std::io::Result<Self> {
+        let capture = match capture {
+            Some(path) => {
+                let mut file = File::create(path)?;
+                file.write_all(CAPTURE_MAGIC)?;
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+        Ok(Self { capture })
+    }
+
+    /// Records `data` of type `kind` sent or received on `stream`.
+    pub fn record(
+        &self,
+        direction: Direction,
+        remote_addr: SocketAddr,
+        stream: StreamId,
+        kind: &str,
+        data: &[u8],
+    ) {
+        let (event, verb, preposition): (_, &[u8], &[u8]) = match direction {
+            Direction::Sent => ("stream_sent", b"Sent ", b" to "),
+            Direction::Received => ("stream_received", b"Received ", b" from "),
+        };
+        log_event(
+            event,
+            remote_addr,
+            &[
+                verb,
+                kind.as_bytes(),
+                b" (",
+                data.len().to_string().as_bytes(),
+                b" bytes)",
+                preposition,
+                remote_addr.to_string().as_bytes(),
+                b" on ",
+                stream.to_string().as_bytes(),
+            ],
+        );
+
+        if let Some(capture) = &self.capture {
+            let record = encode_record(unix_millis(), direction, remote_addr, stream, data);
+            if let Err(e) = capture.lock().unwrap().write_all(&record) {
+                log(&[
+                    b"Failed to write to the capture file, error: ",
+                    e.to_string().as_bytes(),
+                ]);
+            }
+        }
+    }
+}
+
+fn encode_record(
+    time: u64,
+    direction: Direction,
+    remote_addr: SocketAddr,
+    stream: StreamId,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut record = Vec::with_capacity(64 + data.len());
+    record.extend_from_slice(&time.to_le_bytes());
+    record.push(match direction {
+        Direction::Sent => 0,
+        Direction::Received => 1,
+    });
+    record.extend_from_slice(&serialize_address(&remote_addr));
+    record.extend_from_slice(&VarInt::from(stream).into_inner().to_le_bytes());
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_record() {
+        let record = encode_record(
+            1,
+            Direction::Received,
+            "127.0.0.1:8080".parse().unwrap(),
+            // the second client-initiated unidirectional stream
+            StreamId::from(VarInt::from_u32(6)),
+            b"abc",
+        );
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0, 1];
+        expected.extend_from_slice(&serialize_address(&"127.0.0.1:8080".parse().unwrap()));
+        expected.extend_from_slice(&[6, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+        expected.extend_from_slice(b"abc");
+        assert_eq!(record, expected);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::stats::PathStats;
+use core::time::Duration;
+
+/// How often the ratio is adjusted to the path statistics.
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// The share of lost datagrams over which a path is considered congested.
+const LOSS_THRESHOLD: f64 = 0.02;
+/// How many times the lowest RTT seen the RTT may grow to before the path is considered congested.
+const RTT_FACTOR: u32 = 2;
+/// The RTT growth below which the path is not considered congested, as RTTs
+/// of local links are too small for `RTT_FACTOR` to tell anything.
+const MIN_RTT_GROWTH: Duration = Duration::from_millis(10);
+/// The lowest share of messages sent to a congested peer.
+const MIN_RATIO: f64 = 0.125;
+/// The share of messages the ratio grows by per update while the path is not congested.
+const RECOVERY_STEP: f64 = 0.125;
+
+/// Thins out the messages sent to a peer while its QUIC path shows loss or
+/// growing RTT, halving the share of messages sent on each congested update
+/// and growing it back by `RECOVERY_STEP` on each uncongested one.
+pub struct Pacer {
+    ratio: f64,
+    /// Accumulates `ratio` per message, a message is sent each time it reaches 1.
+    credit: f64,
+    /// Lost packets and sent datagrams at the last update.
+    last: Option<(u64, u64)>,
+    min_rtt: Option<Duration>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            credit: 0.0,
+            last: None,
+            min_rtt: None,
+        }
+    }
+}
+
+impl Pacer {
+    /// The share of messages which are sent.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Adjusts the ratio to the path statistics since the last update.
+    pub fn update(&mut self, stats: &PathStats) {
+        let min_rtt = self
+            .min_rtt
+            .map_or(stats.rtt, |min_rtt| min_rtt.min(stats.rtt));
+        self.min_rtt = Some(min_rtt);
+        let Some((lost, sent)) = self
+            .last
+            .replace((stats.lost_packets, stats.datagrams_sent))
+        else {
+            return;
+        };
+        let sent = stats.datagrams_sent.saturating_sub(sent);
+        if sent == 0 {
+            return;
+        }
+        let loss = stats.lost_packets.saturating_sub(lost) as f64 / sent as f64;
+        let max_rtt = (min_rtt * RTT_FACTOR).max(min_rtt + MIN_RTT_GROWTH);
+        if loss > LOSS_THRESHOLD || stats.rtt > max_rtt {
+            self.ratio = (self.ratio / 2.0).max(MIN_RATIO);
+        } else {
+            self.ratio = (self.ratio + RECOVERY_STEP).min(1.0);
+        }
+    }
+
+    /// Whether the next message is sent.
+    pub fn admit(&mut self) -> bool {
+        self.credit += self.ratio;
+        if self.credit >= 1.0 {
+            self.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(rtt: u64, lost_packets: u64, datagrams_sent: u64) -> PathStats {
+        PathStats {
+            rtt: Duration::from_millis(rtt),
+            cwnd: 12000,
+            lost_packets,
+            congestion_events: 0,
+            datagrams_sent,
+        }
+    }
+
+    #[test]
+    fn test_pacer() {
+        let mut pacer = Pacer::default();
+        pacer.update(&stats(10, 0, 100));
+        pacer.update(&stats(10, 0, 200));
+        assert_eq!(pacer.ratio(), 1.0);
+        assert!((0..10).all(|_| pacer.admit()));
+
+        // 10% loss
+        pacer.update(&stats(10, 10, 300));
+        assert_eq!(pacer.ratio(), 0.5);
+        assert_eq!((0..10).filter(|_| pacer.admit()).count(), 5);
+        // the RTT grew
+        pacer.update(&stats(30, 10, 400));
+        assert_eq!(pacer.ratio(), 0.25);
+        for i in 0..10 {
+            pacer.update(&stats(100, 20 + i, 500 + i * 100));
+        }
+        assert_eq!(pacer.ratio(), MIN_RATIO);
+
+        pacer.update(&stats(10, 30, 2000));
+        assert_eq!(pacer.ratio(), MIN_RATIO + RECOVERY_STEP);
+        for i in 0..10 {
+            pacer.update(&stats(10, 30, 2100 + i * 100));
+        }
+        assert_eq!(pacer.ratio(), 1.0);
+    }
+}
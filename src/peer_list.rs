@@ -0,0 +1,155 @@
+use crate::utils::{
+    deserialize_address, deserialize_addresses, serialize_address, serialized_len,
+    SCOPED_IPV6_SERIALIZED_LEN,
+};
+use core::net::SocketAddr;
+
+/// The maximum number of addresses a peer advertises besides its listen address.
+pub const MAX_ALTERNATES: usize = 8;
+
+/// The maximum length of a hello.
+pub const MAX_HELLO_LEN: usize = (MAX_ALTERNATES + 1) * SCOPED_IPV6_SERIALIZED_LEN + 1;
+
+/// Marks the start of the alternate addresses in a peer list. It is an invalid
+/// enum tag for `deserialize_address`, so that nodes not knowing of alternate
+/// addresses stop reading there.
+const ALTERNATES_MARKER: [u8; 4] = [0xff; 4];
+
+/// The hello a dialer opens a connection with.
+#[derive(Debug, PartialEq)]
+pub struct Hello {
+    pub listen_addr: SocketAddr,
+    pub receive_only: bool,
+    /// The other addresses the dialer is reachable on, in the order to try them in.
+    pub alternates: Vec<SocketAddr>,
+}
+
+impl Hello {
+    /// The listen address, followed by the receive-only flag
+    /// and then by the alternate addresses if there are any.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = serialize_address(&self.listen_addr);
+        data.push(self.receive_only.into());
+        for alternate in &self.alternates {
+            data.extend(serialize_address(alternate));
+        }
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> bincode::Result<Self> {
+        let listen_addr = deserialize_address(data)?;
+        let rest = &data[serialized_len(listen_addr)..];
+        Ok(Self {
+            listen_addr,
+            receive_only: rest.first() == Some(&1),
+            alternates: deserialize_addresses(rest.get(1..).unwrap_or_default())
+                .take(MAX_ALTERNATES)
+                .collect(),
+        })
+    }
+}
+
+/// Serializes the listen addresses of `peers`, followed by their alternate
+/// addresses for the ones which have any.
+pub fn serialize_peer_list<'a>(
+    peers: impl IntoIterator<Item = (SocketAddr, &'a [SocketAddr])> + Clone,
+) -> Vec<u8> {
+    let mut data: Vec<_> = peers
+        .clone()
+        .into_iter()
+        .flat_map(|(peer, _)| serialize_address(&peer))
+        .collect();
+    let mut alternates = peers
+        .into_iter()
+        .filter(|(_, alternates)| !alternates.is_empty())
+        .peekable();
+    if alternates.peek().is_some() {
+        data.extend(ALTERNATES_MARKER);
+        for (peer, alternates) in alternates {
+            let alternates = &alternates[..alternates.len().min(MAX_ALTERNATES)];
+            data.extend(serialize_address(&peer));
+            data.push(alternates.len() as _);
+            for alternate in alternates {
+                data.extend(serialize_address(alternate));
+            }
+        }
+    }
+    data
+}
+
+/// Deserializes a peer list serialized with `serialize_peer_list`,
+/// stopping at the first invalid entry.
+pub fn deserialize_peer_list(data: &[u8]) -> Vec<(SocketAddr, Vec<SocketAddr>)> {
+    let mut peers: Vec<_> = deserialize_addresses(data)
+        .map(|peer| (peer, Vec::new()))
+        .collect();
+    let listed_len: usize = peers.iter().map(|&(peer, _)| serialized_len(peer)).sum();
+    let Some(mut rest) = data[listed_len..].strip_prefix(&ALTERNATES_MARKER[..]) else {
+        return peers;
+    };
+    while let Ok(peer) = deserialize_address(rest) {
+        rest = &rest[serialized_len(peer)..];
+        let Some((&count, tail)) = rest.split_first() else {
+            break;
+        };
+        let alternates: Vec<_> = deserialize_addresses(tail)
+            .take(usize::from(count).min(MAX_ALTERNATES))
+            .collect();
+        rest = &tail[alternates
+            .iter()
+            .copied()
+            .map(serialized_len)
+            .sum::<usize>()..];
+        if let Some((_, known)) = peers.iter_mut().find(|(known, _)| *known == peer) {
+            *known = alternates;
+        }
+    }
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hello() {
+        let hello = Hello {
+            listen_addr: "127.0.0.1:9000".parse().unwrap(),
+            receive_only: true,
+            alternates: vec!["[::1]:9000".parse().unwrap()],
+        };
+        let data = hello.serialize();
+        assert!(data.len() <= MAX_HELLO_LEN);
+        assert_eq!(Hello::deserialize(&data).unwrap(), hello);
+
+        // as sent before alternate addresses
+        let data = serialize_address(&hello.listen_addr);
+        let hello = Hello::deserialize(&data).unwrap();
+        assert!(!hello.receive_only);
+        assert!(hello.alternates.is_empty());
+    }
+
+    #[test]
+    fn test_peer_list() {
+        let (first, second) = (
+            "127.0.0.1:9000".parse().unwrap(),
+            "[::1]:9001".parse().unwrap(),
+        );
+        let alternates = ["10.0.0.1:9000".parse().unwrap()];
+        let peers = [(first, &[][..]), (second, &alternates[..])];
+        let data = serialize_peer_list(peers);
+        assert_eq!(
+            deserialize_peer_list(&data),
+            [(first, vec![]), (second, alternates.to_vec())]
+        );
+        // read as by the nodes not knowing of alternate addresses
+        assert_eq!(
+            deserialize_addresses(&data).collect::<Vec<_>>(),
+            [first, second]
+        );
+
+        let data = serialize_peer_list([(first, &[][..])]);
+        assert_eq!(data, serialize_address(&first));
+        assert_eq!(deserialize_peer_list(&data), [(first, vec![])]);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{
+    address_rules::check_address,
+    connection::outgoing_connect,
+    error::{AppError, AppResult},
+    log::{log, log_event},
+    membership,
+    message::{unix_millis, Message},
+    peers::PeerState,
+    sender::send_message,
+    static_peers::read_peers_file,
+    stats::Counter,
+    utils::{resolve, NotifyOnDrop},
+    Bootstrap, Context,
+};
+use backoff::ExponentialBackoff;
+use core::{net::SocketAddr, time::Duration};
+use futures::{stream::FuturesUnordered, StreamExt};
+use quinn::Connection;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::time::Instant;
+
+/// Once in `duration`, reads the peers of the file on `path` into
+/// `ctx.static_peers` if it is modified, and connects to the added ones.
+/// Logs errors on failure.
+pub async fn peers_file_loop(duration: Duration, path: PathBuf, ctx: Context) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval_at(Instant::now() + duration, duration);
+    loop {
+        interval.tick().await;
+        let now_modified = modified(&path);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        match read_peers_file(&path) {
+            Ok(peers) => {
+                let added: Vec<_> = {
+                    let mut static_peers = ctx.static_peers.write().unwrap();
+                    let added = peers
+                        .keys()
+                        .filter(|peer| !static_peers.contains_key(peer))
+                        .copied()
+                        .collect();
+                    *static_peers = peers;
+                    added
+                };
+                for peer in added {
+                    ctx.supervisor
+                        .spawn("connect_pinned", connect_pinned(ctx.clone(), peer));
+                }
+            }
+            Err(e) => log(&[
+                b"Failed to read the peers file, error: ",
+                e.to_string().as_bytes(),
+            ]),
+        }
+    }
+}
+
+/// Connects to the bootstrap hosts concurrently and then to all the other peers.
+///
+/// Proceeds as soon as the peers learned from one of the hosts are connected to,
+/// the connections to the other hosts are left to finish in the background.
+pub async fn initial_connect(ctx: Context) {
+    let mut attempts: FuturesUnordered<_> = ctx
+        .bootstrap
+        .iter()
+        .map(|bootstrap| {
+            let (failed_peers, finished) = NotifyOnDrop::create(());
+            let res = connect_to_bootstrap(ctx.clone(), bootstrap.clone(), Arc::new(failed_peers));
+            tokio::spawn(async move {
+                let res = res.await;
+                let _ = finished.await;
+                res.is_ok()
+            })
+        })
+        .collect();
+    let mut connected = false;
+    while let Some(res) = attempts.next().await {
+        if let Ok(true) = res {
+            connected = true;
+            break;
+        }
+    }
+    if !connected {
+        log(&[b"Failed to connect to any of the bootstrap hosts"]);
+    }
+
+    log(&[
+        b"Connected to the peers at [",
+        ctx.peers.formatted().as_bytes(),
+        b"]",
+    ]);
+    ctx.peers.retain_connected();
+}
+
+/// Resolves the `bootstrap` host and connects to its addresses
+/// in turn until a connection succeeds. Logs errors on failure.
+async fn connect_to_bootstrap(
+    ctx: Context,
+    bootstrap: Arc<Bootstrap>,
+    notify_on_drop: Arc<NotifyOnDrop<()>>,
+) -> AppResult<Connection> {
+    let addrs = resolve(&bootstrap.host).await.inspect_err(|e| {
+        log(&[
+            b"Failed to resolve ",
+            bootstrap.host.as_bytes(),
+            b", error: ",
+            e.to_string().as_bytes(),
+        ])
+    })?;
+
+    let mut res = Err(AppError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no addresses to connect to",
+    )));
+    for addr in addrs {
+        ctx.peers.insert_new(addr);
+        res = outgoing_connect(ctx.clone(), addr, notify_on_drop.clone()).await;
+        if res.is_ok() {
+            *bootstrap.addr.lock().await = Some(addr);
+            break;
+        }
+    }
+    res
+}
+
+/// Once in `duration`, sends the digest of the membership view to each peer.
+pub async fn membership_loop(duration: Duration, ctx: Context) {
+    let mut interval = tokio::time::interval(duration);
+    loop {
+        interval.tick().await;
+        let digest = membership::digest(&membership_view(&ctx));
+        let connections: Vec<_> = ctx.connections.lock().await.values().cloned().collect();
+        for connection in connections {
+            // a peer missing a digest compares views a period later
+            let _ = send_message(&ctx, &connection, &Message::MembershipDigest(digest)).await;
+        }
+    }
+}
+
+/// The peers this node is connected to and itself, unless it is receive-only,
+/// as the peers do not know of it then.
+pub fn membership_view(ctx: &Context) -> Vec<SocketAddr> {
+    let mut view: Vec<_> = ctx
+        .peers
+        .snapshot()
+        .iter()
+        .filter(|&(_, &state)| state == PeerState::Connected)
+        .map(|(&peer, _)| peer)
+        .collect();
+    if !ctx.receive_only {
+        view.push(ctx.local_addr());
+    }
+    view
+}
+
+/// Connects to the peers in the membership `view` of the peer on `remote_addr`
+/// which the peers have kept seeing for two membership periods
+/// without this node knowing them.
+pub async fn heal_partition(ctx: &Context, remote_addr: SocketAddr, view: Vec<SocketAddr>) {
+    let known = ctx.peers.snapshot();
+    let unknown = view.into_iter().filter(|&peer| {
+        !known.contains_key(&peer)
+            && check_address(peer, ctx.local_addr())
+                .and_then(|()| check_address(peer, ctx.bind_addr()))
+                .is_ok()
+            && ctx.advertise_private.allows(peer, remote_addr)
+    });
+    let other_partition = ctx.partitions.lock().await.observe(unknown, Instant::now());
+    if other_partition.is_empty() {
+        return;
+    }
+    let formatted_peers = other_partition
+        .iter()
+        .map(|peer| format!("\"{peer}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    log_event(
+        "partition_healed",
+        remote_addr,
+        &[
+            b"Partition healed, connecting to the peers of the other partition at [",
+            formatted_peers.as_bytes(),
+            b"] seen by ",
+            remote_addr.to_string().as_bytes(),
+        ],
+    );
+    let mut quarantine = ctx.quarantine.lock().await;
+    for peer in other_partition {
+        if ctx.peers.insert_new(peer) && !quarantine.is_quarantined(peer, Instant::now()) {
+            let (failed_peers, _) = NotifyOnDrop::create(());
+            ctx.supervisor.spawn(
+                "outgoing_connect",
+                outgoing_connect(ctx.clone(), peer, Arc::new(failed_peers)),
+            );
+        }
+    }
+}
+
+/// Once in `duration`, gives half of the aggregation state to a random peer.
+///
+/// Epochs are numbered by the time since the Unix epoch divided by `epoch`,
+/// the number of connected peers is the metric aggregated.
+pub async fn aggregation_loop(duration: Duration, epoch: Duration, ctx: Context) {
+    let mut rng = Pcg64Mcg::from_entropy();
+    let mut interval = tokio::time::interval(duration);
+    loop {
+        interval.tick().await;
+        let connection = {
+            let connections = ctx.connections.lock().await;
+            if connections.is_empty() {
+                continue;
+            }
+            connections
+                .values()
+                .nth(rng.gen_range(0..connections.len()))
+                .unwrap()
+                .clone()
+        };
+        let share = ctx.aggregator.lock().await.share(
+            unix_millis() / epoch.as_millis() as u64,
+            connected_peers_count(&ctx),
+        );
+        // on failure the share is lost, skewing the estimates until the next epoch
+        let _ = send_message(&ctx, &connection, &Message::Aggregate(share)).await;
+    }
+}
+
+pub fn connected_peers_count(ctx: &Context) -> f64 {
+    ctx.peers.connected_count() as _
+}
+
+/// Connects to the peer listening on `remote_addr` once its quarantine is over,
+/// unless it is connected by then. Returns whether it connected.
+async fn retry_connection(
+    ctx: Context,
+    remote_addr: SocketAddr,
+) -> Result<bool, backoff::Error<AppError>> {
+    let quarantined_until = ctx.quarantine.lock().await.quarantined_until(remote_addr);
+    if let Some(until) = quarantined_until {
+        tokio::time::sleep_until(until).await;
+    }
+    if ctx.peers.is_connected(remote_addr) {
+        return Ok(false);
+    }
+    let delay = ctx.retry_budget.lock().await.reserve(Instant::now());
+    tokio::time::sleep(delay).await;
+    if ctx.peers.is_connected(remote_addr)
+        || ctx.drain.is_draining()
+        || ctx.is_blocked(remote_addr).await
+    {
+        return Ok(false);
+    }
+    let (notify_on_drop, finished) = NotifyOnDrop::create(());
+    let notify_on_drop = Arc::new(notify_on_drop);
+    let mut reached_bootstrap = None;
+    for bootstrap in &ctx.bootstrap {
+        if Some(remote_addr) == *bootstrap.addr.lock().await {
+            reached_bootstrap = Some(bootstrap.clone());
+            break;
+        }
+    }
+    let res = match reached_bootstrap {
+        Some(bootstrap) => connect_to_bootstrap(ctx, bootstrap, notify_on_drop).await,
+        None => outgoing_connect(ctx, remote_addr, notify_on_drop).await,
+    }
+    .map_err(|e| backoff::Error::Transient {
+        err: e,
+        retry_after: None,
+    });
+    let _ = finished.await;
+    res.map(|_| true)
+}
+
+/// Connects to the peer listening on `remote_addr` with backoff, without an end
+/// for pinned peers unless they are unpinned meanwhile. Returns whether it connected,
+/// not when the backoff ran out of time.
+async fn retry_with_backoff(ctx: &Context, remote_addr: SocketAddr) -> bool {
+    let pinned = ctx.is_pinned(remote_addr);
+    let backoff = ExponentialBackoff {
+        max_elapsed_time: if pinned {
+            None
+        } else {
+            ExponentialBackoff::default().max_elapsed_time
+        },
+        ..Default::default()
+    };
+    backoff::future::retry(backoff, || async move {
+        if pinned && !ctx.is_pinned(remote_addr) {
+            return Ok(false);
+        }
+        retry_connection(ctx.clone(), remote_addr).await
+    })
+    .await
+    .unwrap_or_else(|e| {
+        log_event(
+            "reconnect_gave_up",
+            remote_addr,
+            &[
+                b"Gave up reconnecting to ",
+                remote_addr.to_string().as_bytes(),
+                b", error: ",
+                e.to_string().as_bytes(),
+            ],
+        );
+        false
+    })
+}
+
+/// Reconnects to the peer listening on `remote_addr` with backoff.
+pub async fn reconnect(ctx: &Context, remote_addr: SocketAddr) {
+    // we need to reconnect even if the peer connects to us
+    // to potentially get newer peers
+    if retry_with_backoff(ctx, remote_addr).await {
+        ctx.stats.increment(Counter::Reconnects);
+        log_event(
+            "connect",
+            remote_addr,
+            &[b"Reconnected to ", remote_addr.to_string().as_bytes()],
+        );
+    }
+}
+
+/// Connects to the pinned peer listening on `remote_addr` unless it is connected,
+/// retrying until it is.
+pub async fn connect_pinned(ctx: Context, remote_addr: SocketAddr) {
+    retry_with_backoff(&ctx, remote_addr).await;
+}
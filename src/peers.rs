@@ -0,0 +1,297 @@
+use crate::utils::format_peers;
+use core::net::SocketAddr;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::watch;
+
+/// The state of a known peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerState {
+    /// Being connected to, or the connection to it is closed.
+    Disconnected,
+    /// A connection to it is established.
+    Connected,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    peers: Arc<HashMap<SocketAddr, PeerState>>,
+    /// The connected peers as formatted by `format_peers`.
+    formatted: Arc<str>,
+}
+
+/// Known peers by their listen addresses.
+///
+/// Readers take snapshots of the table, which writers copy on write
+/// while snapshots are held, so that neither waits for the other
+/// for longer than a copy takes. The connected peers are formatted
+/// on each change rather than on each read.
+#[derive(Default)]
+pub struct PeerTable {
+    snapshot: RwLock<Snapshot>,
+    /// Notified on each write.
+    changed: watch::Sender<()>,
+    alternates: RwLock<HashMap<SocketAddr, Alternates>>,
+}
+
+/// The addresses a peer advertised besides its listen address.
+#[derive(Default)]
+struct Alternates {
+    /// In the order to try them in.
+    addrs: Vec<SocketAddr>,
+    /// The address the peer was last reached on.
+    reached: Option<SocketAddr>,
+}
+
+impl PeerTable {
+    /// The table as it is now.
+    pub fn snapshot(&self) -> Arc<HashMap<SocketAddr, PeerState>> {
+        self.snapshot.read().unwrap().peers.clone()
+    }
+
+    /// Notified whenever the table is written to. Writes made while
+    /// the receiver is not waiting are seen as one.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    /// The connected peers as formatted by `format_peers`.
+    pub fn formatted(&self) -> Arc<str> {
+        self.snapshot.read().unwrap().formatted.clone()
+    }
+
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.snapshot.read().unwrap().peers.get(&addr) == Some(&PeerState::Connected)
+    }
+
+    pub fn connected_count(&self) -> usize {
+        self.snapshot
+            .read()
+            .unwrap()
+            .peers
+            .values()
+            .filter(|&&state| state == PeerState::Connected)
+            .count()
+    }
+
+    /// Sets the state of the peer on `addr`, returning its previous state.
+    pub fn insert(&self, addr: SocketAddr, state: PeerState) -> Option<PeerState> {
+        self.update(|peers| peers.insert(addr, state))
+    }
+
+    /// Adds the peer on `addr` as disconnected unless it is known,
+    /// returning whether it is added.
+    pub fn insert_new(&self, addr: SocketAddr) -> bool {
+        self.update(|peers| {
+            let added = !peers.contains_key(&addr);
+            if added {
+                peers.insert(addr, PeerState::Disconnected);
+            }
+            added
+        })
+    }
+
+    /// Forgets the peers not connected.
+    pub fn retain_connected(&self) {
+        self.update(|peers| peers.retain(|_, state| *state == PeerState::Connected));
+    }
+
+    /// The addresses the peer on `addr` advertised besides it.
+    pub fn alternates(&self, addr: SocketAddr) -> Vec<SocketAddr> {
+        self.alternates
+            .read()
+            .unwrap()
+            .get(&addr)
+            .map(|alternates| alternates.addrs.clone())
+            .unwrap_or_default()
+    }
+
+    /// Sets the addresses the peer on `addr` advertised besides it.
+    pub fn set_alternates(&self, addr: SocketAddr, addrs: Vec<SocketAddr>) {
+        let mut alternates = self.alternates.write().unwrap();
+        if addrs.is_empty() {
+            alternates.remove(&addr);
+            return;
+        }
+        let alternates = alternates.entry(addr).or_default();
+        if alternates
+            .reached
+            .is_some_and(|reached| !addrs.contains(&reached))
+        {
+            alternates.reached = None;
+        }
+        alternates.addrs = addrs;
+    }
+
+    /// The addresses to dial the peer on `addr` on in turn: the one it was
+    /// last reached on first, then `addr` and then its alternate addresses.
+    pub fn dial_order(&self, addr: SocketAddr) -> Vec<SocketAddr> {
+        let alternates = self.alternates.read().unwrap();
+        let Some(alternates) = alternates.get(&addr) else {
+            return vec![addr];
+        };
+        let mut order: Vec<_> = alternates.reached.into_iter().collect();
+        for &candidate in [addr].iter().chain(&alternates.addrs) {
+            if !order.contains(&candidate) {
+                order.push(candidate);
+            }
+        }
+        order
+    }
+
+    /// Records that the peer on `addr` was reached on `reached`.
+    pub fn record_reached(&self, addr: SocketAddr, reached: SocketAddr) {
+        if let Some(alternates) = self.alternates.write().unwrap().get_mut(&addr) {
+            alternates.reached = (reached != addr).then_some(reached);
+        }
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut HashMap<SocketAddr, PeerState>) -> R) -> R {
+        let mut snapshot = self.snapshot.write().unwrap();
+        let res = f(Arc::make_mut(&mut snapshot.peers));
+        snapshot.formatted = format_peers(&snapshot.peers).into();
+        drop(snapshot);
+        self.changed.send_replace(());
+        res
+    }
+}
+
+/// A change between two snapshots of the peer table.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeerChange {
+    Added(SocketAddr, PeerState),
+    Changed(SocketAddr, PeerState),
+    Removed(SocketAddr),
+}
+
+impl fmt::Display for PeerChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = |state| match state {
+            PeerState::Disconnected => "disconnected",
+            PeerState::Connected => "connected",
+        };
+        match *self {
+            Self::Added(addr, s) => write!(f, "{addr} added, {}", state(s)),
+            Self::Changed(addr, s) => write!(f, "{addr} {}", state(s)),
+            Self::Removed(addr) => write!(f, "{addr} removed"),
+        }
+    }
+}
+
+/// The changes from `old` to `new`, ordered by address.
+pub fn diff(
+    old: &HashMap<SocketAddr, PeerState>,
+    new: &HashMap<SocketAddr, PeerState>,
+) -> Vec<PeerChange> {
+    let mut changes: Vec<_> = new
+        .iter()
+        .filter_map(|(&addr, &state)| match old.get(&addr) {
+            None => Some(PeerChange::Added(addr, state)),
+            Some(&old) if old != state => Some(PeerChange::Changed(addr, state)),
+            Some(_) => None,
+        })
+        .chain(
+            old.keys()
+                .filter(|addr| !new.contains_key(addr))
+                .map(|&addr| PeerChange::Removed(addr)),
+        )
+        .collect();
+    changes.sort_by_key(|change| match *change {
+        PeerChange::Added(addr, _) | PeerChange::Changed(addr, _) | PeerChange::Removed(addr) => {
+            addr
+        }
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_table() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let table = PeerTable::default();
+        assert!(table.insert_new(addr(1)));
+        assert!(!table.insert_new(addr(1)));
+        assert!(!table.is_connected(addr(1)));
+
+        let snapshot = table.snapshot();
+        assert_eq!(
+            table.insert(addr(1), PeerState::Connected),
+            Some(PeerState::Disconnected)
+        );
+        assert_eq!(table.insert(addr(2), PeerState::Disconnected), None);
+        assert!(table.is_connected(addr(1)));
+        assert_eq!(table.connected_count(), 1);
+        assert_eq!(&*table.formatted(), "\"127.0.0.1:1\"");
+        // the snapshot is not changed by the writes
+        assert_eq!(snapshot[&addr(1)], PeerState::Disconnected);
+        assert_eq!(snapshot.len(), 1);
+
+        table.retain_connected();
+        assert_eq!(
+            *table.snapshot(),
+            HashMap::from([(addr(1), PeerState::Connected)])
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let table = PeerTable::default();
+        let mut changed = table.subscribe();
+        let old = table.snapshot();
+        table.insert(addr(1), PeerState::Disconnected);
+        table.insert(addr(2), PeerState::Disconnected);
+        assert!(changed.has_changed().unwrap());
+        let changes = diff(&old, &table.snapshot());
+        assert_eq!(
+            changes,
+            [
+                PeerChange::Added(addr(1), PeerState::Disconnected),
+                PeerChange::Added(addr(2), PeerState::Disconnected),
+            ]
+        );
+        assert_eq!(changes[0].to_string(), "127.0.0.1:1 added, disconnected");
+
+        changed.mark_unchanged();
+        let old = table.snapshot();
+        table.insert(addr(1), PeerState::Connected);
+        table.retain_connected();
+        assert!(changed.has_changed().unwrap());
+        let changes = diff(&old, &table.snapshot());
+        assert_eq!(
+            changes,
+            [
+                PeerChange::Changed(addr(1), PeerState::Connected),
+                PeerChange::Removed(addr(2)),
+            ]
+        );
+        assert_eq!(changes[0].to_string(), "127.0.0.1:1 connected");
+        assert_eq!(changes[1].to_string(), "127.0.0.1:2 removed");
+    }
+
+    #[test]
+    fn test_alternates() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let table = PeerTable::default();
+        assert_eq!(table.dial_order(addr(1)), [addr(1)]);
+        table.set_alternates(addr(1), vec![addr(2), addr(3)]);
+        assert_eq!(table.alternates(addr(1)), [addr(2), addr(3)]);
+        assert_eq!(table.dial_order(addr(1)), [addr(1), addr(2), addr(3)]);
+        table.record_reached(addr(1), addr(3));
+        assert_eq!(table.dial_order(addr(1)), [addr(3), addr(1), addr(2)]);
+        table.record_reached(addr(1), addr(1));
+        assert_eq!(table.dial_order(addr(1)), [addr(1), addr(2), addr(3)]);
+
+        table.record_reached(addr(1), addr(2));
+        table.set_alternates(addr(1), vec![addr(3)]);
+        assert_eq!(table.dial_order(addr(1)), [addr(1), addr(3)]);
+        table.set_alternates(addr(1), Vec::new());
+        assert_eq!(table.dial_order(addr(1)), [addr(1)]);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    audit,
+    audit::AuditEvent,
+    log::{log, log_message},
+    memory_budget::Charged,
+    message::{unix_millis, DirectKind, Envelope, Message, Queued},
+    rpc::RpcError,
+    sender::send_message,
+    topology::Topology,
+    Context,
+};
+use core::{mem, net::SocketAddr, time::Duration};
+use futures::{stream::FuturesUnordered, StreamExt};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::{collections::BTreeSet, sync::Arc};
+use tokio::time::Instant;
+
+/// Once in `duration`, sends a random message to all peers.
+/// Pauses while no connection is there to send messages over.
+pub async fn producer_loop(duration: Duration, ctx: Context) {
+    fn generate_random_message(rng: &mut impl Rng) -> String {
+        let mut message = [0; 32];
+        rng.fill_bytes(&mut message);
+        bs58::encode(message).into_string()
+    }
+
+    let mut rng = Pcg64Mcg::from_entropy();
+
+    let mut deadline = Instant::now() + duration;
+    loop {
+        tokio::time::sleep_until(deadline).await;
+        deadline += duration;
+
+        if ctx.message_sender.receiver_count() == 0 {
+            log(&[b"No peers to send messages to, pausing"]);
+            while ctx.message_sender.receiver_count() == 0 {
+                ctx.subscribed.notified().await;
+            }
+            log(&[b"Peers are connected again, resuming"]);
+            deadline = Instant::now() + duration;
+            continue;
+        }
+
+        broadcast_message(&ctx, generate_random_message(&mut rng)).await;
+    }
+}
+
+/// Sends a new message with `body` to all peers, if there are any,
+/// including those being reconnected to.
+/// Waits while `ctx.memory_budget` is exceeded.
+pub async fn broadcast_message(ctx: &Context, body: String) {
+    if ctx.drain.is_draining() {
+        return;
+    }
+    if body.len() > ctx.max_transfer_size {
+        log(&[
+            b"Skipped a message of ",
+            body.len().to_string().as_bytes(),
+            b" bytes, over the maximum transfer size",
+        ]);
+        return;
+    }
+    if ctx.memory_budget.is_exceeded() {
+        log(&[b"Memory budget exceeded, holding back messages"]);
+        ctx.memory_budget.wait().await;
+    }
+    let formatted_peers = ctx.peers.formatted();
+    // peers being reconnected to get the message once they are back
+    if formatted_peers.is_empty() && ctx.mailboxes.lock().await.is_empty() {
+        return;
+    }
+    // so that the message is not taken in again if a peer sends the body back
+    if let Some(dedup) = &ctx.dedup {
+        dedup.lock().await.insert(&body);
+    }
+    let timestamp = unix_millis();
+    let envelope = Envelope {
+        origin: ctx.local_addr(),
+        nonce: ctx.nonces.next(),
+        seq: ctx.seqs.next(),
+        timestamp,
+        expires: ctx
+            .message_ttl
+            .map(|ttl| timestamp + ttl.as_millis() as u64),
+        body,
+    };
+    log_message(
+        "message_sent",
+        &envelope,
+        &[
+            b"Sending message [",
+            envelope.body.as_bytes(),
+            b"] to [",
+            formatted_peers.as_bytes(),
+            b"]",
+        ],
+    );
+    let envelope = Arc::new(envelope);
+    if let Some(history) = &ctx.history {
+        history.lock().await.record(envelope.clone());
+    }
+    queue_message(ctx, envelope, 0);
+}
+
+/// Sends a new message with `body` to the peer listening on `peer` only,
+/// over the connection to it if there is one, or else over all the connections
+/// for relays to forward it. Returns the number of connections it is sent over.
+pub async fn send_to(ctx: &Context, peer: SocketAddr, body: String) -> usize {
+    let envelope = direct_envelope(ctx, body);
+    route_direct(ctx, peer, DirectKind::Message, envelope, 0, None).await
+}
+
+/// Sends a request with `body` to the peer listening on `peer`
+/// like `send_to` and waits up to `ctx.request_timeout` for the response.
+/// The request is cancelled if the future is dropped.
+pub async fn request(ctx: &Context, peer: SocketAddr, body: String) -> Result<String, RpcError> {
+    let (id, call) = ctx.calls.start();
+    let envelope = direct_envelope(ctx, body);
+    if route_direct(ctx, peer, DirectKind::Request(id), envelope, 0, None).await == 0 {
+        return Err(RpcError::Unreachable);
+    }
+    call.wait(ctx.request_timeout).await
+}
+
+/// Pings the peer listening on `peer` through the overlay like `send_to`,
+/// returning the round-trip time and the number of links the ping took.
+pub async fn overlay_ping(ctx: &Context, peer: SocketAddr) -> Result<(Duration, u32), RpcError> {
+    let (id, call) = ctx.pings.start();
+    let envelope = direct_envelope(ctx, String::new());
+    let start = Instant::now();
+    if route_direct(ctx, peer, DirectKind::Ping(id), envelope, 0, None).await == 0 {
+        return Err(RpcError::Unreachable);
+    }
+    let hops = call.wait(ctx.request_timeout).await?;
+    Ok((start.elapsed(), hops))
+}
+
+/// Creates a new direct message with `body`.
+pub fn direct_envelope(ctx: &Context, body: String) -> Arc<Envelope> {
+    let envelope = Envelope {
+        origin: ctx.local_addr(),
+        nonce: ctx.nonces.next(),
+        seq: 0,
+        timestamp: unix_millis(),
+        expires: None,
+        body,
+    };
+    envelope.into()
+}
+
+/// Traces the route to the peer listening on `peer` through the overlay like `send_to`,
+/// returning the listen addresses of the relays on the way followed by that of the peer.
+pub async fn trace_route(ctx: &Context, peer: SocketAddr) -> Result<Vec<SocketAddr>, RpcError> {
+    let (id, call) = ctx.traces.start();
+    let envelope = direct_envelope(ctx, String::new());
+    let kind = DirectKind::Trace {
+        id,
+        path: Vec::new(),
+    };
+    if route_direct(ctx, peer, kind, envelope, 0, None).await == 0 {
+        return Err(RpcError::Unreachable);
+    }
+    call.wait(ctx.request_timeout).await
+}
+
+/// Gathers the overlay graph by querying this peer's neighbors for theirs,
+/// then the nodes found that way, and so on, like `send_to`.
+pub async fn gather_topology(ctx: &Context) -> Topology {
+    let mut topology = Topology::default();
+    let neighbors = neighbors(ctx).await;
+    let mut found: BTreeSet<_> = neighbors.iter().copied().collect();
+    topology.nodes.insert(ctx.local_addr(), Some(neighbors));
+    while !found.is_empty() {
+        let mut queries: FuturesUnordered<_> = found
+            .iter()
+            .map(|&node| async move { (node, query_neighbors(ctx, node).await.ok()) })
+            .collect();
+        found.clear();
+        while let Some((node, neighbors)) = queries.next().await {
+            for &neighbor in neighbors.iter().flatten() {
+                if !topology.nodes.contains_key(&neighbor) {
+                    found.insert(neighbor);
+                }
+            }
+            topology.nodes.insert(node, neighbors);
+        }
+        found.retain(|node| !topology.nodes.contains_key(node));
+    }
+    topology
+}
+
+/// Returns the listen addresses of the peers connected to, sorted.
+pub async fn neighbors(ctx: &Context) -> Vec<SocketAddr> {
+    let mut neighbors: Vec<_> = ctx.connections.lock().await.keys().copied().collect();
+    neighbors.sort_unstable();
+    neighbors
+}
+
+/// Queries the peer listening on `peer` for its neighbors like `send_to`.
+async fn query_neighbors(ctx: &Context, peer: SocketAddr) -> Result<Vec<SocketAddr>, RpcError> {
+    let (id, call) = ctx.neighbor_queries.start();
+    let envelope = direct_envelope(ctx, String::new());
+    if route_direct(ctx, peer, DirectKind::Neighbors(id), envelope, 0, None).await == 0 {
+        return Err(RpcError::Unreachable);
+    }
+    call.wait(ctx.request_timeout).await
+}
+
+/// Sends `envelope`, which traversed `hops` links so far, to the peer listening on `to`,
+/// directly if connected to it, or else to all the peers other than `from`
+/// for relays to forward it. Returns the number of connections it is sent over.
+pub async fn route_direct(
+    ctx: &Context,
+    to: SocketAddr,
+    kind: DirectKind,
+    envelope: Arc<Envelope>,
+    hops: u32,
+    from: Option<SocketAddr>,
+) -> usize {
+    let route: Vec<_> = {
+        let connections = ctx.connections.lock().await;
+        match connections.get(&to) {
+            Some(connection) => vec![(to, connection.clone())],
+            None => connections
+                .iter()
+                .filter(|&(&peer, _)| Some(peer) != from)
+                .map(|(&peer, connection)| (peer, connection.clone()))
+                .collect(),
+        }
+    };
+    let message = Message::Direct {
+        to,
+        kind,
+        hops: hops + 1,
+        envelope: envelope.clone(),
+    };
+    let mut sent = 0;
+    for (peer, connection) in route {
+        if send_message(ctx, &connection, &message).await.is_ok() {
+            audit(ctx, AuditEvent::Sent, peer, &envelope, "");
+            sent += 1;
+        }
+    }
+    sent
+}
+
+/// Queues `envelope`, which traversed `hops` links to this node, for all peers,
+/// charging it to `ctx.memory_budget` until every peer has taken it.
+pub fn queue_message(ctx: &Context, envelope: Arc<Envelope>, hops: u32) {
+    let charge = ctx
+        .memory_budget
+        .charge(mem::size_of::<Envelope>() + envelope.body.len());
+    // fails only if no connection is subscribed
+    let _ = ctx
+        .message_sender
+        .send(Charged::new(Queued { envelope, hops }, charge));
+}
@@ -0,0 +1,90 @@
+use core::{net::SocketAddr, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+/// Puts peers disconnecting more than `threshold` times within `window`
+/// into quarantine for `cooldown`.
+///
+/// Quarantined peers are kept in the known list,
+/// but are neither dialed nor sent messages.
+pub struct Quarantine {
+    threshold: usize,
+    window: Duration,
+    cooldown: Duration,
+    disconnects: HashMap<SocketAddr, VecDeque<Instant>>,
+    quarantined_until: HashMap<SocketAddr, Instant>,
+}
+
+impl Quarantine {
+    pub fn new(threshold: usize, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            disconnects: HashMap::new(),
+            quarantined_until: HashMap::new(),
+        }
+    }
+
+    /// Records a disconnection of `addr` at `now`.
+    /// Returns whether `addr` was put into quarantine.
+    pub fn record_disconnect(&mut self, addr: SocketAddr, now: Instant) -> bool {
+        let disconnects = self.disconnects.entry(addr).or_default();
+        while disconnects
+            .front()
+            .is_some_and(|&time| time + self.window < now)
+        {
+            disconnects.pop_front();
+        }
+        disconnects.push_back(now);
+        if disconnects.len() <= self.threshold {
+            return false;
+        }
+        disconnects.clear();
+        self.quarantined_until.insert(addr, now + self.cooldown);
+        true
+    }
+
+    pub fn is_quarantined(&mut self, addr: SocketAddr, now: Instant) -> bool {
+        match self.quarantined_until.get(&addr) {
+            Some(&until) if now < until => true,
+            Some(_) => {
+                self.quarantined_until.remove(&addr);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the time the quarantine of `addr` ends, if it is quarantined.
+    pub fn quarantined_until(&self, addr: SocketAddr) -> Option<Instant> {
+        self.quarantined_until.get(&addr).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine() {
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        let other = "127.0.0.1:8081".parse().unwrap();
+        let mut quarantine = Quarantine::new(2, Duration::from_secs(10), Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(!quarantine.record_disconnect(addr, at(0)));
+        assert!(!quarantine.record_disconnect(addr, at(5)));
+        // the first disconnection is out of the window
+        assert!(!quarantine.record_disconnect(addr, at(11)));
+        assert!(!quarantine.record_disconnect(other, at(12)));
+        assert!(!quarantine.is_quarantined(addr, at(12)));
+        assert!(quarantine.record_disconnect(addr, at(13)));
+        assert!(quarantine.is_quarantined(addr, at(14)));
+        assert!(!quarantine.is_quarantined(other, at(14)));
+        assert!(quarantine.is_quarantined(addr, at(72)));
+        assert!(!quarantine.is_quarantined(addr, at(73)));
+        assert_eq!(quarantine.quarantined_until(addr), None);
+    }
+}
@@ -0,0 +1,570 @@
+use crate::{
+    address_rules::is_sent_by,
+    audit,
+    audit::AuditEvent,
+    clock::{ClockEstimate, ClockSample},
+    error::AppResult,
+    filter::{self, Verdict},
+    history,
+    log::{debug_event, log_event, log_message_event},
+    membership,
+    message::{unix_millis, DirectKind, Envelope, Message, Rejection},
+    observer::Direction,
+    peering::{connected_peers_count, heal_partition, membership_view},
+    producer::{direct_envelope, neighbors, queue_message, route_direct},
+    sender::{observe, send_message},
+    stats::Counter,
+    transfer::{self, MAX_MESSAGE_LEN},
+    Context,
+};
+use core::{net::SocketAddr, ops::Range};
+use quinn::Connection;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// Passes messages received from `connection` to `ctx.handlers`, unless
+/// they are rejected by `ctx.replay_guard` or `ctx.filters`, or in seed mode,
+/// in order with `ctx.reorderer`.
+/// In relay-only mode, forwards them to the other peers instead,
+/// and with `--forward` besides, up to `ctx.max_hops` links from their origins.
+///
+/// Answers pings and history requests, updates the clock offset
+/// estimate of the peer on pongs and takes in snapshots of kv stores.
+pub async fn receiver_loop(
+    ctx: &Context,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+) -> AppResult<()> {
+    let peer_addr = remote_addr.to_string();
+    loop {
+        let mut recv = connection.accept_uni().await?;
+        // leaving room for the rest of the message besides the body
+        let max_len = ctx.max_transfer_size + MAX_MESSAGE_LEN;
+        let data = transfer::read_message(&mut recv, max_len, |received, len| {
+            debug_event(
+                "transfer_progress",
+                remote_addr,
+                &[
+                    b"Received ",
+                    received.to_string().as_bytes(),
+                    b" of ",
+                    len.to_string().as_bytes(),
+                    b" bytes of a message from ",
+                    peer_addr.as_bytes(),
+                ],
+            );
+        })
+        .await?;
+        let delay = ctx
+            .download
+            .reserve(connection.stable_id(), data.len(), Instant::now());
+        if !delay.is_zero() {
+            // the next streams are not accepted meanwhile
+            tokio::time::sleep(delay).await;
+        }
+        let message: Message = bincode::deserialize(&data)?;
+        ctx.stats.increment(Counter::MessagesReceived);
+        ctx.stats.add(Counter::BytesReceived, data.len() as _);
+        observe(
+            ctx,
+            Direction::Received,
+            connection,
+            recv.id(),
+            message.kind(),
+            &data,
+        );
+        if let Some(envelope) = message.envelope() {
+            audit(ctx, AuditEvent::Received, remote_addr, envelope, "");
+        }
+        let received = unix_millis();
+        let (envelope, hops, resent, gossip) = match message {
+            Message::Gossip(envelope)
+            | Message::History(envelope)
+            | Message::Direct { envelope, .. }
+            | Message::Relayed { envelope, .. }
+                if ctx.seed_mode =>
+            {
+                audit(
+                    ctx,
+                    AuditEvent::Dropped,
+                    remote_addr,
+                    &envelope,
+                    "seed mode",
+                );
+                continue;
+            }
+            Message::Gossip(envelope) => (envelope, 1, false, true),
+            Message::Relayed { hops, envelope } => (envelope, hops, false, false),
+            Message::History(envelope) => (envelope, 1, true, false),
+            Message::Snapshot(entries) => {
+                if let Some(kv_store) = &ctx.kv_store {
+                    let received = entries.len().to_string();
+                    let taken = kv_store.merge(entries).to_string();
+                    debug_event(
+                        "snapshot",
+                        remote_addr,
+                        &[
+                            b"Took ",
+                            taken.as_bytes(),
+                            b" of ",
+                            received.as_bytes(),
+                            b" entries of the snapshot from ",
+                            peer_addr.as_bytes(),
+                        ],
+                    );
+                }
+                continue;
+            }
+            Message::Ping { sent } => {
+                send_message(
+                    ctx,
+                    connection,
+                    &Message::Pong {
+                        ping_sent: sent,
+                        ping_received: received,
+                        sent: unix_millis(),
+                    },
+                )
+                .await?;
+                continue;
+            }
+            Message::Pong {
+                ping_sent,
+                ping_received,
+                sent,
+            } => {
+                let sample = ClockSample::new(ping_sent, ping_received, sent, received);
+                ctx.stats.rtt(sample.rtt);
+                debug_event(
+                    "pong",
+                    remote_addr,
+                    &[
+                        b"Pong from ",
+                        peer_addr.as_bytes(),
+                        format!(", rtt {}ms, clock offset {}ms", sample.rtt, sample.offset)
+                            .as_bytes(),
+                    ],
+                );
+                let mut clocks = ctx.clocks.lock().await;
+                clocks.entry(remote_addr).or_default().update(sample);
+                continue;
+            }
+            Message::MembershipDigest(digest) => {
+                let view = membership_view(ctx);
+                if digest != membership::digest(&view) {
+                    send_message(ctx, connection, &Message::Membership(view)).await?;
+                }
+                continue;
+            }
+            Message::Membership(view) => {
+                heal_partition(ctx, remote_addr, view).await;
+                continue;
+            }
+            Message::Aggregate(share) => {
+                let metric = connected_peers_count(ctx);
+                ctx.aggregator.lock().await.receive(&share, metric);
+                continue;
+            }
+            Message::HistoryRequest { since } => {
+                if let Some(history) = &ctx.history {
+                    let history = history.lock().await;
+                    let messages = since
+                        .into_iter()
+                        .flat_map(|(origin, nonce)| history.since(origin, nonce))
+                        .collect();
+                    ctx.supervisor.spawn(
+                        "send_history",
+                        send_history(ctx.clone(), connection.clone(), remote_addr, messages),
+                    );
+                }
+                continue;
+            }
+            Message::Direct {
+                to,
+                kind,
+                hops,
+                envelope,
+            } => {
+                receive_direct(ctx, remote_addr, to, kind, hops, envelope, received).await;
+                continue;
+            }
+            Message::RetransmitRequest { origin, seqs } => {
+                if let Some(history) = &ctx.history {
+                    let messages = history.lock().await.seqs(origin, seqs);
+                    ctx.supervisor.spawn(
+                        "send_history",
+                        send_history(ctx.clone(), connection.clone(), remote_addr, messages),
+                    );
+                }
+                continue;
+            }
+        };
+
+        let origin_now = origin_time(ctx, envelope.origin, received).await;
+        // gossip is only sent by the origins of the messages, which relay them otherwise
+        let from_origin = is_sent_by(envelope.origin, remote_addr);
+        let mut res = if gossip && !from_origin {
+            Err(Rejection::OriginMismatch)
+        } else {
+            ctx.replay_guard
+                .lock()
+                .await
+                .check(&envelope, origin_now, from_origin)
+        };
+        let mut sequences = ctx.sequences.lock().await;
+        // messages filling gaps are older than the last one seen from their origin
+        let fills_gap =
+            res == Err(Rejection::Replayed) && sequences.is_missing(envelope.origin, envelope.seq);
+        if fills_gap {
+            res = Ok(());
+        }
+        if let Err(rejection) = res {
+            let reason = rejection.to_string();
+            audit(ctx, AuditEvent::Dropped, remote_addr, &envelope, &reason);
+            // a relay receives the same message over several paths,
+            // and messages sent again may have been received already
+            if resent || (ctx.relay_only || ctx.forward) && rejection == Rejection::Replayed {
+                continue;
+            }
+            log_message_event(
+                "rejected",
+                remote_addr,
+                &envelope,
+                &[
+                    b"Rejected message [",
+                    envelope.body.as_bytes(),
+                    b"] from ",
+                    peer_addr.as_bytes(),
+                    b", reason: ",
+                    reason.as_bytes(),
+                ],
+            );
+            continue;
+        }
+        let missing = sequences.receive(envelope.origin, envelope.seq);
+        drop(sequences);
+        if let Some(seqs) = missing {
+            report_gap(ctx, connection, remote_addr, envelope.origin, seqs).await?;
+        }
+        let duplicate = match &ctx.dedup {
+            Some(dedup) => !dedup.lock().await.insert(&envelope.body),
+            None => false,
+        };
+        let (verdict, drop_reason) = if duplicate {
+            ctx.stats.increment(Counter::DuplicatePayloads);
+            // still passed to the reorderer, like a filtered out one
+            let verdict = Verdict {
+                deliver: false,
+                forward: false,
+            };
+            (verdict, "duplicate payload")
+        } else {
+            let verdict = filter::evaluate(&ctx.filters.lock().await, &envelope);
+            (verdict, "filtered")
+        };
+        if let Some(history) = ctx.history.as_ref().filter(|_| verdict.forward) {
+            history.lock().await.record(envelope.clone());
+        }
+        if (ctx.relay_only || ctx.forward) && verdict.forward {
+            if hops < ctx.max_hops {
+                audit(ctx, AuditEvent::Forwarded, remote_addr, &envelope, "");
+                ctx.memory_budget.wait().await;
+                queue_message(ctx, envelope.clone(), hops);
+            } else if ctx.relay_only {
+                audit(
+                    ctx,
+                    AuditEvent::Dropped,
+                    remote_addr,
+                    &envelope,
+                    "hop limit",
+                );
+            }
+        }
+        if ctx.relay_only {
+            if !verdict.forward {
+                audit(
+                    ctx,
+                    AuditEvent::Dropped,
+                    remote_addr,
+                    &envelope,
+                    drop_reason,
+                );
+            }
+            continue;
+        }
+        if !verdict.deliver {
+            audit(
+                ctx,
+                AuditEvent::Dropped,
+                remote_addr,
+                &envelope,
+                drop_reason,
+            );
+        }
+        let Some(reorderer) = &ctx.reorderer else {
+            if verdict.deliver {
+                for handler in &*ctx.handlers {
+                    handler.on_message(remote_addr, &envelope);
+                }
+            }
+            continue;
+        };
+        let mut reorderer = reorderer.lock().await;
+        if fills_gap && reorderer.is_passed(envelope.origin, envelope.seq) {
+            audit(
+                ctx,
+                AuditEvent::Dropped,
+                remote_addr,
+                &envelope,
+                "out of order",
+            );
+            log_message_event(
+                "out_of_order",
+                remote_addr,
+                &envelope,
+                &[
+                    b"Skipped message [",
+                    envelope.body.as_bytes(),
+                    b"] from ",
+                    peer_addr.as_bytes(),
+                    b", later messages of its origin are delivered already",
+                ],
+            );
+            continue;
+        }
+        let delivery = verdict.deliver.then(|| (remote_addr, envelope.clone()));
+        // delivered under the lock, so that no other connection delivers in between
+        for (from, envelope) in reorderer
+            .push(envelope.origin, envelope.seq, delivery)
+            .into_iter()
+            .flatten()
+        {
+            for handler in &*ctx.handlers {
+                handler.on_message(from, &envelope);
+            }
+        }
+    }
+}
+
+/// Handles a direct message for the peer listening on `to`, which traversed `hops` links,
+/// received from the peer on `remote_addr` at `received`, if it is for this peer,
+/// or forwards it in relay-only mode, unless it is rejected.
+async fn receive_direct(
+    ctx: &Context,
+    remote_addr: SocketAddr,
+    to: SocketAddr,
+    kind: DirectKind,
+    hops: u32,
+    envelope: Arc<Envelope>,
+    received: u64,
+) {
+    let local_addr = ctx.local_addr();
+    let for_us = to == local_addr;
+    if !for_us && !ctx.relay_only {
+        audit(
+            ctx,
+            AuditEvent::Dropped,
+            remote_addr,
+            &envelope,
+            "for another peer",
+        );
+        return;
+    }
+    let origin_now = origin_time(ctx, envelope.origin, received).await;
+    let from_origin = is_sent_by(envelope.origin, remote_addr);
+    if let Err(rejection) = ctx
+        .replay_guard
+        .lock()
+        .await
+        .check(&envelope, origin_now, from_origin)
+    {
+        audit(
+            ctx,
+            AuditEvent::Dropped,
+            remote_addr,
+            &envelope,
+            &rejection.to_string(),
+        );
+        // relays forward the message to all their peers, so it may come more than once
+        if rejection != Rejection::Replayed {
+            log_message_event(
+                "rejected",
+                remote_addr,
+                &envelope,
+                &[
+                    b"Rejected direct message [",
+                    envelope.body.as_bytes(),
+                    b"] from ",
+                    remote_addr.to_string().as_bytes(),
+                    b", reason: ",
+                    rejection.to_string().as_bytes(),
+                ],
+            );
+        }
+        return;
+    }
+    let verdict = filter::evaluate(&ctx.filters.lock().await, &envelope);
+    let filtered = if for_us {
+        kind == DirectKind::Message && !verdict.deliver
+    } else {
+        !verdict.forward
+    };
+    if filtered {
+        audit(ctx, AuditEvent::Dropped, remote_addr, &envelope, "filtered");
+    }
+    if !for_us {
+        if verdict.forward {
+            audit(ctx, AuditEvent::Forwarded, remote_addr, &envelope, "");
+            let kind = kind.forwarded_by(local_addr);
+            route_direct(ctx, to, kind, envelope, hops, Some(remote_addr)).await;
+        }
+        return;
+    }
+    match kind {
+        DirectKind::Message => {
+            if verdict.deliver {
+                for handler in &*ctx.handlers {
+                    handler.on_message(remote_addr, &envelope);
+                }
+            }
+        }
+        DirectKind::Request(id) => {
+            ctx.supervisor
+                .spawn("answer_request", answer_request(ctx.clone(), id, envelope));
+        }
+        DirectKind::Response(id) => ctx.calls.complete(id, Ok(envelope.body.clone())),
+        DirectKind::Failure(id) => ctx.calls.complete(id, Err(envelope.body.clone())),
+        DirectKind::Ping(id) => {
+            let pong = direct_envelope(ctx, String::new());
+            let kind = DirectKind::Pong { id, hops };
+            route_direct(ctx, envelope.origin, kind, pong, 0, None).await;
+        }
+        DirectKind::Pong { id, hops } => ctx.pings.complete(id, Ok(hops)),
+        DirectKind::Trace { id, mut path } => {
+            path.push(local_addr);
+            let reply = direct_envelope(ctx, String::new());
+            let kind = DirectKind::TraceReply { id, path };
+            route_direct(ctx, envelope.origin, kind, reply, 0, None).await;
+        }
+        DirectKind::TraceReply { id, path } => ctx.traces.complete(id, Ok(path)),
+        DirectKind::Neighbors(id) => {
+            let reply = direct_envelope(ctx, String::new());
+            let neighbors = neighbors(ctx).await;
+            let kind = DirectKind::NeighborsReply { id, neighbors };
+            route_direct(ctx, envelope.origin, kind, reply, 0, None).await;
+        }
+        DirectKind::NeighborsReply { id, neighbors } => {
+            ctx.neighbor_queries.complete(id, Ok(neighbors));
+        }
+    }
+}
+
+/// Answers the request with `id` in `envelope` with `ctx.responder`.
+async fn answer_request(ctx: Context, id: u64, envelope: Arc<Envelope>) {
+    let (kind, body) = match &ctx.responder {
+        Some(responder) => match responder.respond(envelope.origin, &envelope.body).await {
+            Ok(response) => (DirectKind::Response(id), response),
+            Err(e) => (DirectKind::Failure(id), e),
+        },
+        None => (
+            DirectKind::Failure(id),
+            "no --on-request command".to_owned(),
+        ),
+    };
+    let response = direct_envelope(&ctx, body);
+    route_direct(&ctx, envelope.origin, kind, response, 0, None).await;
+}
+
+/// Asks the peer on the other end of `connection`, which we were away from,
+/// for the messages of each origin newer than the last one we received.
+pub async fn request_history(ctx: &Context, connection: &Connection) -> AppResult<()> {
+    let since: Vec<_> = ctx.replay_guard.lock().await.last_nonces().collect();
+    for since in since.chunks(history::REQUEST_ORIGINS) {
+        let message = Message::HistoryRequest {
+            since: since.to_vec(),
+        };
+        send_message(ctx, connection, &message).await?;
+    }
+    Ok(())
+}
+
+/// Sends `messages` from `ctx.history` requested by the peer
+/// on `remote_addr` again, skipping the expired ones.
+async fn send_history(
+    ctx: Context,
+    connection: Connection,
+    remote_addr: SocketAddr,
+    messages: Vec<Arc<Envelope>>,
+) -> AppResult<()> {
+    let mut sent = 0;
+    for envelope in messages {
+        if envelope.expires.is_some()
+            && envelope.is_expired(origin_time(&ctx, envelope.origin, unix_millis()).await)
+        {
+            continue;
+        }
+        send_message(&ctx, &connection, &Message::History(envelope.clone())).await?;
+        audit(&ctx, AuditEvent::Sent, remote_addr, &envelope, "");
+        sent += 1;
+    }
+    if sent > 0 {
+        log_event(
+            "history_sent",
+            remote_addr,
+            &[
+                b"Sent ",
+                sent.to_string().as_bytes(),
+                b" messages missed by ",
+                remote_addr.to_string().as_bytes(),
+            ],
+        );
+    }
+    Ok(())
+}
+
+/// Logs the messages of `origin` with sequence numbers in `seqs` found missing
+/// on a message from the peer on `remote_addr` and, with `ctx.retransmit_gaps`,
+/// asks the peer for them.
+async fn report_gap(
+    ctx: &Context,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+    origin: SocketAddr,
+    seqs: Range<u64>,
+) -> AppResult<()> {
+    let missed = seqs.end - seqs.start;
+    ctx.stats.add(Counter::MissedMessages, missed);
+    log_event(
+        "gap",
+        remote_addr,
+        &[
+            b"Missed ",
+            missed.to_string().as_bytes(),
+            b" messages of ",
+            origin.to_string().as_bytes(),
+            b" before one from ",
+            remote_addr.to_string().as_bytes(),
+        ],
+    );
+    if ctx.retransmit_gaps {
+        send_message(
+            ctx,
+            connection,
+            &Message::RetransmitRequest { origin, seqs },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Converts `now` to the time by the clock of `origin`, as far as we know.
+pub async fn origin_time(ctx: &Context, origin: SocketAddr, now: u64) -> u64 {
+    let origin_offset = ctx
+        .clocks
+        .lock()
+        .await
+        .get(&origin)
+        .and_then(ClockEstimate::best)
+        .map_or(0, |sample| sample.offset);
+    now.saturating_add_signed(origin_offset)
+}
@@ -0,0 +1,102 @@
+use core::{mem, net::SocketAddr};
+use std::collections::{BTreeMap, HashMap};
+
+/// The messages of an origin held back for a gap.
+struct Pending<T> {
+    /// The sequence number of the next message to hand over.
+    next: u64,
+    held: BTreeMap<u64, T>,
+}
+
+impl<T> Pending<T> {
+    /// Takes the held messages from `next` on without a gap.
+    fn release(&mut self, released: &mut Vec<T>) {
+        while let Some(item) = self.held.remove(&self.next) {
+            released.push(item);
+            self.next += 1;
+        }
+    }
+}
+
+/// Hands over the messages of each origin in the order of their sequence numbers.
+///
+/// The messages received past a gap are held back until it is filled,
+/// or until more than `window` of them are, when the gap is skipped.
+pub struct Reorderer<T> {
+    window: usize,
+    origins: HashMap<SocketAddr, Pending<T>>,
+}
+
+impl<T> Reorderer<T> {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            origins: HashMap::new(),
+        }
+    }
+
+    /// Adds the message of `origin` with `seq` and returns the messages
+    /// to hand over now, in order.
+    ///
+    /// The first message of an origin counts as the start of its sequence, and
+    /// so does one the order went past, as the origin numbers its messages from 1
+    /// again when it restarts. Messages filling skipped gaps are to be checked
+    /// with `is_passed` beforehand.
+    pub fn push(&mut self, origin: SocketAddr, seq: u64, item: T) -> Vec<T> {
+        let mut released = Vec::new();
+        let pending = self.origins.entry(origin).or_insert_with(|| Pending {
+            next: seq,
+            held: BTreeMap::new(),
+        });
+        if seq < pending.next {
+            released.extend(mem::take(&mut pending.held).into_values());
+            pending.next = seq;
+        }
+        pending.held.insert(seq, item);
+        pending.release(&mut released);
+        if pending.held.len() > self.window {
+            pending.next = *pending.held.keys().next().unwrap();
+            pending.release(&mut released);
+        }
+        released
+    }
+
+    /// Whether messages of `origin` past `seq` were handed over already.
+    pub fn is_passed(&self, origin: SocketAddr, seq: u64) -> bool {
+        self.origins
+            .get(&origin)
+            .is_some_and(|pending| seq < pending.next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorderer() {
+        let origin = "127.0.0.1:8080".parse().unwrap();
+        let mut reorderer = Reorderer::new(3);
+        assert_eq!(reorderer.push(origin, 5, 5), [5]);
+        assert_eq!(reorderer.push(origin, 6, 6), [6]);
+        assert_eq!(reorderer.push(origin, 8, 8), [0; 0]);
+        assert_eq!(reorderer.push(origin, 9, 9), [0; 0]);
+        assert_eq!(reorderer.push(origin, 7, 7), [7, 8, 9]);
+        assert!(reorderer.is_passed(origin, 9));
+        assert!(!reorderer.is_passed(origin, 10));
+
+        // the gap of 10 is skipped
+        assert_eq!(reorderer.push(origin, 11, 11), [0; 0]);
+        assert_eq!(reorderer.push(origin, 12, 12), [0; 0]);
+        assert_eq!(reorderer.push(origin, 14, 14), [0; 0]);
+        assert_eq!(reorderer.push(origin, 15, 15), [11, 12]);
+        assert!(reorderer.is_passed(origin, 10));
+        assert_eq!(reorderer.push(origin, 13, 13), [13, 14, 15]);
+
+        // the origin restarted
+        assert_eq!(reorderer.push(origin, 17, 17), [0; 0]);
+        assert_eq!(reorderer.push(origin, 1, 1), [17, 1]);
+        assert_eq!(reorderer.push(origin, 2, 2), [2]);
+        assert!(!reorderer.is_passed("127.0.0.1:8081".parse().unwrap(), 1));
+    }
+}
@@ -0,0 +1,153 @@
+use core::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tokio::{io::AsyncWriteExt, process::Command, sync::oneshot};
+
+/// The reason an RPC call failed.
+#[derive(Error, Debug, PartialEq)]
+pub enum RpcError {
+    #[error("no connections to send the request over")]
+    Unreachable,
+    #[error("timed out")]
+    TimedOut,
+    #[error("failed on the peer: {0}")]
+    Failed(String),
+}
+
+/// The RPC calls awaiting their responses of type `T`, by their IDs.
+pub struct Calls<T> {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<T, String>>>>,
+}
+
+impl<T> Default for Calls<T> {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> Calls<T> {
+    /// Registers a new call, returning its ID and the call to wait for the response on.
+    pub fn start(self: &Arc<Self>) -> (u64, PendingCall<T>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, response) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let call = PendingCall {
+            id,
+            calls: self.clone(),
+            response,
+        };
+        (id, call)
+    }
+
+    /// Passes the response to the call with `id`, unless it is over already.
+    pub fn complete(&self, id: u64, response: Result<T, String>) {
+        if let Some(sender) = self.pending.lock().unwrap().remove(&id) {
+            let _ = sender.send(response);
+        }
+    }
+}
+
+/// An RPC call awaiting its response, cancelled when dropped.
+pub struct PendingCall<T> {
+    id: u64,
+    calls: Arc<Calls<T>>,
+    response: oneshot::Receiver<Result<T, String>>,
+}
+
+impl<T> PendingCall<T> {
+    /// Waits up to `timeout` for the response.
+    pub async fn wait(mut self, timeout: Duration) -> Result<T, RpcError> {
+        match tokio::time::timeout(timeout, &mut self.response).await {
+            Ok(Ok(response)) => response.map_err(RpcError::Failed),
+            Ok(Err(_)) | Err(_) => Err(RpcError::TimedOut),
+        }
+    }
+}
+
+impl<T> Drop for PendingCall<T> {
+    fn drop(&mut self) {
+        self.calls.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Answers RPC requests by running a shell command with the request body on stdin
+/// and the origin of the request in the `GOSSIP_ORIGIN` environment variable.
+/// The output of the command is the response.
+pub struct ExecResponder {
+    command: String,
+}
+
+impl ExecResponder {
+    pub fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    /// Runs the command for the request with `body` from `origin`, returning
+    /// its output, or its error output or exit status if it fails.
+    pub async fn respond(&self, origin: SocketAddr, body: &str) -> Result<String, String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("GOSSIP_ORIGIN", origin.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let mut stdin = child.stdin.take().unwrap();
+        // the command may not read its stdin
+        let _ = stdin.write_all(body.as_bytes()).await;
+        drop(stdin);
+        let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else if output.stderr.is_empty() {
+            Err(output.status.to_string())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).into_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_calls() {
+        let calls = Arc::new(Calls::<String>::default());
+        let (id, call) = calls.start();
+        calls.complete(id, Ok("pong".into()));
+        assert_eq!(call.wait(Duration::from_secs(1)).await, Ok("pong".into()));
+
+        let (id, call) = calls.start();
+        calls.complete(id, Err("no".into()));
+        assert_eq!(
+            call.wait(Duration::from_secs(1)).await,
+            Err(RpcError::Failed("no".into()))
+        );
+
+        let (_, call) = calls.start();
+        assert_eq!(
+            call.wait(Duration::from_millis(10)).await,
+            Err(RpcError::TimedOut)
+        );
+
+        let (id, call) = calls.start();
+        drop(call);
+        calls.complete(id, Ok("late".into()));
+        assert!(calls.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    audit,
+    audit::AuditEvent,
+    error::{AppError, AppResult, Operation, WithContext},
+    flow_window::FlowWindow,
+    log::{debug_event, debug_message_event, log, log_event},
+    mailbox::Mailbox,
+    memory_budget::Charged,
+    message::{unix_millis, Envelope, Message, Queued},
+    observer::Direction,
+    pacing::{self, Pacer},
+    receiver::origin_time,
+    stats::{Counter, PathStats},
+    transfer::{self, MAX_MESSAGE_LEN},
+    Context,
+};
+use core::{net::SocketAddr, time::Duration};
+use quinn::{Connection, SendStream, StreamId};
+use std::sync::Arc;
+use tokio::{sync::broadcast, time::Instant};
+
+/// Sends `message` over a new unidirectional stream of `connection`.
+pub async fn send_message(
+    ctx: &Context,
+    connection: &Connection,
+    message: &Message,
+) -> AppResult<()> {
+    let _in_flight = ctx.drain.track();
+    let mut send = write_message(ctx, connection, message).await?;
+    send.finish()
+        .await
+        .with_context(connection.remote_address(), Operation::MessageSend)
+}
+
+/// Writes `message` to a new unidirectional stream of `connection`,
+/// which is returned to be finished.
+async fn write_message(
+    ctx: &Context,
+    connection: &Connection,
+    message: &Message,
+) -> AppResult<SendStream> {
+    async {
+        let data = transfer::frame(bincode::serialize(message)?);
+        let delay = ctx
+            .upload
+            .reserve(connection.stable_id(), data.len(), Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let mut send = connection.open_uni().await?;
+        observe(
+            ctx,
+            Direction::Sent,
+            connection,
+            send.id(),
+            message.kind(),
+            &data,
+        );
+        send.write_all(&data).await?;
+        ctx.stats.increment(Counter::MessagesSent);
+        ctx.stats.add(Counter::BytesSent, data.len() as _);
+        Ok::<_, AppError>(send)
+    }
+    .await
+    .with_context(connection.remote_address(), Operation::MessageSend)
+}
+
+/// Sends messages received from `message_receiver` to `connection`
+/// with the peer listening on `remote_addr`, unless it is quarantined,
+/// the origin of the message or the message is expired.
+///
+/// Messages are not waited to be acknowledged one by one, but those
+/// exceeding the `FlowWindow` of the peer are skipped until it catches up.
+/// With adaptive pacing, messages are also thinned out by a `Pacer`.
+pub async fn sender_loop(
+    ctx: &Context,
+    message_receiver: &mut broadcast::Receiver<Charged<Queued>>,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+) -> AppResult<()> {
+    let window = Arc::new(FlowWindow::new(ctx.flow_window));
+    let mut slow = false;
+    let mut pacer = ctx.adaptive_pacing.then(Pacer::default);
+    let mut next_pacer_update = Instant::now() + pacing::UPDATE_INTERVAL;
+    while let Ok(Charged { value: queued, .. }) = message_receiver.recv().await {
+        let envelope = &queued.envelope;
+        // counted from here, as the message is out of the queue
+        let in_flight = ctx.drain.track();
+        let quarantined = ctx
+            .quarantine
+            .lock()
+            .await
+            .is_quarantined(remote_addr, Instant::now());
+        if envelope.origin == remote_addr {
+            continue;
+        }
+        if quarantined {
+            audit(
+                ctx,
+                AuditEvent::Dropped,
+                remote_addr,
+                envelope,
+                "quarantined",
+            );
+            continue;
+        }
+        // messages may wait in the queue for a while
+        if envelope.expires.is_some()
+            && envelope.is_expired(origin_time(ctx, envelope.origin, unix_millis()).await)
+        {
+            audit(ctx, AuditEvent::Dropped, remote_addr, envelope, "expired");
+            continue;
+        }
+        if let Some(pacer) = &mut pacer {
+            if Instant::now() >= next_pacer_update {
+                next_pacer_update = Instant::now() + pacing::UPDATE_INTERVAL;
+                update_pacer(pacer, connection, remote_addr);
+            }
+            if !pacer.admit() {
+                ctx.stats.increment(Counter::CongestionSkips);
+                audit(ctx, AuditEvent::Dropped, remote_addr, envelope, "congested");
+                continue;
+            }
+        }
+        let message = queued.message();
+        let len = bincode::serialized_size(&message)? as usize;
+        if !window.try_acquire(len) {
+            ctx.stats.increment(Counter::SlowPeerSkips);
+            audit(ctx, AuditEvent::Dropped, remote_addr, envelope, "slow peer");
+            if !slow {
+                slow = true;
+                log_event(
+                    "slow",
+                    remote_addr,
+                    &[
+                        b"Peer ",
+                        remote_addr.to_string().as_bytes(),
+                        b" is slow, skipping messages to it",
+                    ],
+                );
+            }
+            continue;
+        }
+        if slow {
+            slow = false;
+            log_event(
+                "caught_up",
+                remote_addr,
+                &[b"Peer ", remote_addr.to_string().as_bytes(), b" caught up"],
+            );
+        }
+        let res = write_message(ctx, connection, &message).await;
+        let mut send = res.inspect_err(|_| window.release(len))?;
+        audit(ctx, AuditEvent::Sent, remote_addr, envelope, "");
+        debug_message_event(
+            "message_sent",
+            remote_addr,
+            envelope,
+            &[
+                b"Sent message [",
+                envelope.body.as_bytes(),
+                b"] to ",
+                remote_addr.to_string().as_bytes(),
+            ],
+        );
+        let window = window.clone();
+        let charge = ctx.memory_budget.charge(len);
+        // the streams are accepted in the order they are opened,
+        // so the messages still arrive in order
+        ctx.supervisor.spawn("send_finish", async move {
+            let _ = send.finish().await;
+            window.release(len);
+            drop((charge, in_flight));
+        });
+    }
+
+    Ok(())
+}
+
+/// Sends the entries of the kv store in `snapshot` to the peer listening on `remote_addr`,
+/// unless there are none or they exceed the maximum transfer size.
+pub async fn send_snapshot(
+    ctx: &Context,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+    snapshot: Vec<(SocketAddr, Arc<Envelope>)>,
+) -> AppResult<()> {
+    if snapshot.is_empty() {
+        return Ok(());
+    }
+    let entries = snapshot.len().to_string();
+    let message = Message::Snapshot(snapshot.into_iter().map(|(_, e)| e).collect());
+    let len = bincode::serialized_size(&message)?;
+    if len > (ctx.max_transfer_size + MAX_MESSAGE_LEN) as u64 {
+        log(&[
+            b"Skipped sending a snapshot of ",
+            len.to_string().as_bytes(),
+            b" bytes to ",
+            remote_addr.to_string().as_bytes(),
+            b", over the maximum transfer size",
+        ]);
+        return Ok(());
+    }
+    send_message(ctx, connection, &message).await?;
+    debug_event(
+        "snapshot",
+        remote_addr,
+        &[
+            b"Sent a snapshot of ",
+            entries.as_bytes(),
+            b" entries to ",
+            remote_addr.to_string().as_bytes(),
+        ],
+    );
+    Ok(())
+}
+
+/// Keeps the messages for the peer on `remote_addr` in its mailbox
+/// in `ctx.mailboxes`, until the mailbox is taken by a new connection.
+pub async fn mailbox_loop(ctx: Context, remote_addr: SocketAddr) {
+    let mut message_receiver = ctx.message_sender.subscribe();
+    ctx.subscribed.notify_one();
+    loop {
+        // wakes up now and then to notice the mailbox is taken
+        let res = tokio::time::timeout(Duration::from_secs(1), message_receiver.recv()).await;
+        let mut mailboxes = ctx.mailboxes.lock().await;
+        let Some(mailbox) = mailboxes.get_mut(&remote_addr) else {
+            return;
+        };
+        match res {
+            Ok(Ok(message)) if message.value.envelope.origin != remote_addr => {
+                mailbox.push(message, Instant::now());
+            }
+            Ok(Err(broadcast::error::RecvError::Closed)) => return,
+            _ => {}
+        }
+    }
+}
+
+/// Sends the messages kept in `mailbox` while the peer on `remote_addr` was away.
+pub async fn flush_mailbox(
+    ctx: &Context,
+    mailbox: Mailbox,
+    connection: &Connection,
+    remote_addr: SocketAddr,
+) -> AppResult<()> {
+    let mut flushed = 0;
+    for queued in mailbox.drain(Instant::now()) {
+        let envelope = &queued.envelope;
+        if envelope.expires.is_some()
+            && envelope.is_expired(origin_time(ctx, envelope.origin, unix_millis()).await)
+        {
+            audit(ctx, AuditEvent::Dropped, remote_addr, envelope, "expired");
+            continue;
+        }
+        send_message(ctx, connection, &queued.message()).await?;
+        audit(ctx, AuditEvent::Sent, remote_addr, envelope, "");
+        flushed += 1;
+    }
+    if flushed > 0 {
+        log_event(
+            "mailbox_flushed",
+            remote_addr,
+            &[
+                b"Sent ",
+                flushed.to_string().as_bytes(),
+                b" messages kept while ",
+                remote_addr.to_string().as_bytes(),
+                b" was away",
+            ],
+        );
+    }
+    Ok(())
+}
+
+/// Updates `pacer` with the path statistics of `connection`,
+/// logging when the peer on `remote_addr` gets congested or recovers.
+fn update_pacer(pacer: &mut Pacer, connection: &Connection, remote_addr: SocketAddr) {
+    let prev_ratio = pacer.ratio();
+    pacer.update(&PathStats::of(connection));
+    let ratio = pacer.ratio();
+    if ratio < prev_ratio {
+        log_event(
+            "congested",
+            remote_addr,
+            &[
+                b"Peer ",
+                remote_addr.to_string().as_bytes(),
+                b" is congested, sending ",
+                format!("{:.1}", ratio * 100.0).as_bytes(),
+                b"% of messages to it",
+            ],
+        );
+    } else if ratio == 1.0 && prev_ratio < 1.0 {
+        log_event(
+            "recovered",
+            remote_addr,
+            &[b"Peer ", remote_addr.to_string().as_bytes(), b" recovered"],
+        );
+    }
+}
+
+/// Records `data` of type `kind` sent or received on `stream`
+/// of `connection`, if observing.
+pub fn observe(
+    ctx: &Context,
+    direction: Direction,
+    connection: &Connection,
+    stream: StreamId,
+    kind: &str,
+    data: &[u8],
+) {
+    if let Some(observer) = &ctx.observer {
+        observer.record(direction, connection.remote_address(), stream, kind, data);
+    }
+}
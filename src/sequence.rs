@@ -0,0 +1,140 @@
+use core::{net::SocketAddr, ops::Range};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The maximum number of messages received past a gap kept track of,
+/// beyond it the oldest gap is given up on.
+const MAX_AHEAD: usize = 1024;
+
+/// Generates the sequence numbers of the messages of this peer.
+#[derive(Default)]
+pub struct SequenceGenerator(AtomicU64);
+
+impl SequenceGenerator {
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// The sequence numbers received from an origin.
+#[derive(Default)]
+struct Received {
+    /// The highest sequence number up to which all messages are received.
+    contiguous: u64,
+    /// The sequence numbers received past a gap.
+    ahead: BTreeSet<u64>,
+}
+
+impl Received {
+    fn highest(&self) -> u64 {
+        self.ahead.last().copied().unwrap_or(self.contiguous)
+    }
+
+    /// Moves `contiguous` past the sequence numbers received right after it.
+    fn advance(&mut self) {
+        while self.ahead.first() == Some(&(self.contiguous + 1)) {
+            self.contiguous = self.ahead.pop_first().unwrap();
+        }
+    }
+}
+
+/// Tracks the sequence numbers of the messages received from each origin
+/// to detect the messages missed.
+#[derive(Default)]
+pub struct SequenceTracker {
+    origins: HashMap<SocketAddr, Received>,
+}
+
+impl SequenceTracker {
+    /// Records the message of `origin` with `seq`, accepted by `ReplayGuard`,
+    /// and returns the sequence numbers found missing by it, if any.
+    ///
+    /// The first message of an origin counts as the start of its sequence,
+    /// and so does a message not past the contiguous ones,
+    /// as the origin numbers its messages from 1 again when it restarts.
+    pub fn receive(&mut self, origin: SocketAddr, seq: u64) -> Option<Range<u64>> {
+        let Some(received) = self.origins.get_mut(&origin) else {
+            self.origins.insert(
+                origin,
+                Received {
+                    contiguous: seq,
+                    ..Default::default()
+                },
+            );
+            return None;
+        };
+        if seq <= received.contiguous {
+            *received = Received {
+                contiguous: seq,
+                ..Default::default()
+            };
+            return None;
+        }
+        let highest = received.highest();
+        received.ahead.insert(seq);
+        received.advance();
+        if received.ahead.len() > MAX_AHEAD {
+            received.contiguous = received.ahead.pop_first().unwrap();
+            received.advance();
+        }
+        (seq > highest + 1).then_some(highest + 1..seq)
+    }
+
+    /// The number of messages of all origins found missing and not received yet.
+    pub fn missing_count(&self) -> u64 {
+        self.origins
+            .values()
+            .map(|received| received.highest() - received.contiguous - received.ahead.len() as u64)
+            .sum()
+    }
+
+    /// Whether the message of `origin` with `seq` was found missing and is not received yet.
+    pub fn is_missing(&self, origin: SocketAddr, seq: u64) -> bool {
+        self.origins.get(&origin).is_some_and(|received| {
+            seq > received.contiguous && seq < received.highest() && !received.ahead.contains(&seq)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_tracker() {
+        let origin = "127.0.0.1:8080".parse().unwrap();
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.receive(origin, 5), None);
+        assert_eq!(tracker.receive(origin, 6), None);
+        assert_eq!(tracker.receive(origin, 9), Some(7..9));
+        assert!(tracker.is_missing(origin, 7));
+        assert!(tracker.is_missing(origin, 8));
+        assert_eq!(tracker.missing_count(), 2);
+        assert!(!tracker.is_missing(origin, 9));
+        assert!(!tracker.is_missing(origin, 10));
+        assert_eq!(tracker.receive(origin, 12), Some(10..12));
+        assert_eq!(tracker.receive(origin, 8), None);
+        assert!(!tracker.is_missing(origin, 8));
+        assert_eq!(tracker.receive(origin, 7), None);
+        assert_eq!(tracker.origins[&origin].contiguous, 9);
+        assert_eq!(tracker.receive(origin, 10), None);
+        assert_eq!(tracker.receive(origin, 11), None);
+        assert_eq!(tracker.origins[&origin].contiguous, 12);
+        assert!(tracker.origins[&origin].ahead.is_empty());
+
+        // the origin restarted
+        assert_eq!(tracker.receive(origin, 1), None);
+        assert_eq!(tracker.receive(origin, 2), None);
+        assert!(!tracker.is_missing(origin, 3));
+
+        // the gap is given up on
+        assert_eq!(tracker.receive(origin, 4), Some(3..4));
+        for seq in 5..5 + MAX_AHEAD as u64 {
+            assert_eq!(tracker.receive(origin, seq), None);
+        }
+        assert!(!tracker.is_missing(origin, 3));
+        assert_eq!(tracker.origins[&origin].contiguous, 4 + MAX_AHEAD as u64);
+    }
+}
@@ -0,0 +1,164 @@
+use core::{fmt, net::SocketAddr, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+/// The number of the latest reconnection latencies of a peer
+/// its distribution is taken over.
+const LATENCY_SAMPLES: usize = 64;
+
+/// The connections to a peer over the lifetime of the node.
+#[derive(Default)]
+struct PeerSessions {
+    count: u64,
+    /// The time connected over the closed connections.
+    connected: Duration,
+    /// When the open connection was established.
+    connected_since: Option<Instant>,
+    /// When the last connection was closed, while it is not reestablished.
+    disconnected_since: Option<Instant>,
+    last_disconnect_reason: Option<String>,
+    /// The latest times from a disconnection to the next connection, oldest first.
+    reconnect_latencies: VecDeque<Duration>,
+}
+
+/// Tracks the connections to each peer, so that peers which disconnect often
+/// or for long stand out.
+#[derive(Default)]
+pub struct SessionTracker {
+    peers: HashMap<SocketAddr, PeerSessions>,
+}
+
+impl SessionTracker {
+    /// Records a connection to `peer` established at `now`,
+    /// returning the time since it was disconnected if it was.
+    pub fn connected(&mut self, peer: SocketAddr, now: Instant) -> Option<Duration> {
+        let sessions = self.peers.entry(peer).or_default();
+        sessions.count += 1;
+        sessions.connected_since = Some(now);
+        let latency = now.duration_since(sessions.disconnected_since.take()?);
+        if sessions.reconnect_latencies.len() == LATENCY_SAMPLES {
+            sessions.reconnect_latencies.pop_front();
+        }
+        sessions.reconnect_latencies.push_back(latency);
+        Some(latency)
+    }
+
+    /// Records the connection to `peer` closed at `now` for `reason`.
+    pub fn disconnected(&mut self, peer: SocketAddr, reason: String, now: Instant) {
+        let sessions = self.peers.entry(peer).or_default();
+        if let Some(since) = sessions.connected_since.take() {
+            sessions.connected += now.duration_since(since);
+        }
+        sessions.disconnected_since = Some(now);
+        sessions.last_disconnect_reason = Some(reason);
+    }
+
+    /// The statistics of the connections to `peer` at `now`, if there were any.
+    pub fn stats(&self, peer: SocketAddr, now: Instant) -> Option<SessionStats> {
+        self.peers.get(&peer).map(|sessions| {
+            let mut latencies: Vec<_> = sessions.reconnect_latencies.iter().copied().collect();
+            latencies.sort_unstable();
+            let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+            SessionStats {
+                count: sessions.count,
+                connected: sessions.connected
+                    + sessions
+                        .connected_since
+                        .map_or(Duration::ZERO, |since| now.duration_since(since)),
+                last_disconnect_reason: sessions.last_disconnect_reason.clone(),
+                reconnect_latency: (!latencies.is_empty()).then(|| LatencyDistribution {
+                    p50: percentile(50),
+                    p90: percentile(90),
+                    max: percentile(100),
+                }),
+            }
+        })
+    }
+
+    /// The peers connected to at least once which are not connected now.
+    pub fn disconnected_peers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.peers
+            .iter()
+            .filter(|(_, sessions)| sessions.connected_since.is_none())
+            .map(|(&peer, _)| peer)
+    }
+}
+
+/// The connections to a peer as reported by the admin API
+/// and emitted to StatsD with `--statsd`.
+#[derive(Debug, PartialEq)]
+pub struct SessionStats {
+    /// The number of connections established.
+    pub count: u64,
+    /// The time connected over all connections.
+    pub connected: Duration,
+    pub last_disconnect_reason: Option<String>,
+    /// Over the latest reconnections.
+    pub reconnect_latency: Option<LatencyDistribution>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LatencyDistribution {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sessions {}, connected {}s",
+            self.count,
+            self.connected.as_secs()
+        )?;
+        if let Some(reason) = &self.last_disconnect_reason {
+            write!(f, ", last disconnect: {reason}")?;
+        }
+        if let Some(latency) = &self.reconnect_latency {
+            write!(
+                f,
+                ", reconnect latency p50 {}ms, p90 {}ms, max {}ms",
+                latency.p50.as_millis(),
+                latency.p90.as_millis(),
+                latency.max.as_millis()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 1));
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut tracker = SessionTracker::default();
+        assert!(tracker.stats(peer, start).is_none());
+
+        assert_eq!(tracker.connected(peer, start), None);
+        assert_eq!(
+            tracker.stats(peer, secs(10)).unwrap().to_string(),
+            "sessions 1, connected 10s"
+        );
+        assert_eq!(tracker.disconnected_peers().count(), 0);
+
+        tracker.disconnected(peer, "timed out".to_owned(), secs(10));
+        assert_eq!(tracker.disconnected_peers().collect::<Vec<_>>(), [peer]);
+        assert_eq!(
+            tracker.connected(peer, secs(12)),
+            Some(Duration::from_secs(2))
+        );
+        tracker.disconnected(peer, "reset".to_owned(), secs(20));
+        tracker.connected(peer, secs(30));
+        assert_eq!(
+            tracker.stats(peer, secs(35)).unwrap().to_string(),
+            "sessions 3, connected 23s, last disconnect: reset, \
+             reconnect latency p50 2000ms, p90 2000ms, max 10000ms"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use core::{
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    time::Duration,
+};
+use quinn_proto::{ConnectionId, ConnectionIdGenerator};
+use rand::Rng;
+use std::{collections::hash_map::DefaultHasher, io, os::fd::AsRawFd};
+
+/// The maximum number of endpoints sharing a socket address.
+pub const MAX_SHARDS: u8 = 64;
+
+const CID_LEN: usize = 8;
+
+/// Generates the connection IDs of the endpoint number `shard` out of `shards`,
+/// whose first byte modulo `shards` is `shard`, for packets to be steered
+/// to the endpoint by the program of `attach_steering_program`.
+pub struct ShardedCidGenerator {
+    shard: u8,
+    shards: u8,
+}
+
+impl ShardedCidGenerator {
+    pub fn new(shard: u8, shards: u8) -> Self {
+        Self { shard, shards }
+    }
+}
+
+impl ConnectionIdGenerator for ShardedCidGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut bytes = [0; CID_LEN];
+        rand::thread_rng().fill(&mut bytes[..]);
+        bytes[0] = bytes[0] % (u8::MAX / self.shards) * self.shards + self.shard;
+        ConnectionId::new(&bytes)
+    }
+
+    fn cid_len(&self) -> usize {
+        CID_LEN
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The endpoint out of `shards` to connect to the peer on `addr` from.
+pub fn shard_of(addr: SocketAddr, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    addr.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Steers the QUIC packets received on the `SO_REUSEPORT` group of `socket`
+/// to the socket whose index in the group is the first byte of their destination
+/// connection ID modulo `shards`.
+///
+/// The program sees the UDP payload. The destination connection ID
+/// of long header packets starts at byte 6, and at byte 1 of short header ones.
+pub fn attach_steering_program(socket: &impl AsRawFd, shards: u8) -> io::Result<()> {
+    let mut program = [
+        // A = packet[0]
+        bpf(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, 0, 0, 0),
+        // long header?
+        bpf(libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K, 0, 2, 0x80),
+        bpf(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, 0, 0, 6),
+        bpf(libc::BPF_JMP | libc::BPF_JA, 0, 0, 1),
+        bpf(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, 0, 0, 1),
+        bpf(
+            libc::BPF_ALU | libc::BPF_MOD | libc::BPF_K,
+            0,
+            0,
+            shards.into(),
+        ),
+        bpf(libc::BPF_RET | libc::BPF_A, 0, 0, 0),
+    ];
+    let fprog = libc::sock_fprog {
+        len: program.len() as _,
+        filter: program.as_mut_ptr(),
+    };
+    // SAFETY: `fprog` points to a valid program of `len` instructions,
+    // which the kernel copies
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_REUSEPORT_CBPF,
+            (&fprog as *const libc::sock_fprog).cast(),
+            size_of::<libc::sock_fprog>() as _,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn bpf(code: u32, jt: u8, jf: u8, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as _,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded_cid_generator() {
+        for shards in [1, 3, MAX_SHARDS] {
+            for shard in 0..shards {
+                let mut generator = ShardedCidGenerator::new(shard, shards);
+                for _ in 0..100 {
+                    let cid = generator.generate_cid();
+                    assert_eq!(cid.len(), CID_LEN);
+                    assert_eq!(cid[0] % shards, shard);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shard_of() {
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(shard_of(addr, 4), shard_of(addr, 4));
+        assert!(shard_of(addr, 4) < 4);
+        assert_eq!(shard_of(addr, 1), 0);
+    }
+}
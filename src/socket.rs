@@ -0,0 +1,185 @@
+use crate::stats::{Counter, Stats};
+use core::{
+    fmt,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+use quinn::{
+    udp::{EcnCodepoint, RecvMeta, Transmit, UdpState},
+    AsyncUdpSocket, Runtime, TokioRuntime,
+};
+use std::{
+    io::{self, IoSliceMut},
+    os::fd::AsRawFd,
+    sync::Arc,
+};
+
+/// A UDP socket with send offload and ECN which can be turned off,
+/// counting the received packets marked Congestion Experienced.
+///
+/// Without send offload, the datagrams of a transmit are sent one by one
+/// rather than as a single segmented one. Without ECN, packets are sent
+/// unmarked and the marks of received ones are dropped, so that the peer
+/// fails to validate ECN and stops marking its packets too.
+pub struct TunedUdpSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    gso: bool,
+    ecn: bool,
+    stats: Arc<Stats>,
+}
+
+impl TunedUdpSocket {
+    pub fn new(
+        socket: std::net::UdpSocket,
+        gso: bool,
+        ecn: bool,
+        stats: Arc<Stats>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            inner: TokioRuntime.wrap_udp_socket(socket)?,
+            gso,
+            ecn,
+            stats,
+        })
+    }
+}
+
+impl fmt::Debug for TunedUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TunedUdpSocket")
+            .field("inner", &self.inner)
+            .field("gso", &self.gso)
+            .field("ecn", &self.ecn)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for TunedUdpSocket {
+    fn poll_send(
+        &self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        if self.gso && self.ecn {
+            return self.inner.poll_send(state, cx, transmits);
+        }
+        let ecn = |transmit: &Transmit| transmit.ecn.filter(|_| self.ecn);
+        if self.gso {
+            let transmits: Vec<_> = transmits
+                .iter()
+                .map(|transmit| Transmit {
+                    destination: transmit.destination,
+                    ecn: ecn(transmit),
+                    contents: transmit.contents.clone(),
+                    segment_size: transmit.segment_size,
+                    src_ip: transmit.src_ip,
+                })
+                .collect();
+            return self.inner.poll_send(state, cx, &transmits);
+        }
+        // the indices of the transmits the datagrams are split from
+        let mut origins = Vec::new();
+        let mut datagrams = Vec::new();
+        for (i, transmit) in transmits.iter().enumerate() {
+            let len = transmit.contents.len();
+            let segment_size = transmit.segment_size.unwrap_or(len).max(1);
+            for start in (0..len).step_by(segment_size) {
+                origins.push(i);
+                datagrams.push(Transmit {
+                    destination: transmit.destination,
+                    ecn: ecn(transmit),
+                    contents: transmit
+                        .contents
+                        .slice(start..len.min(start + segment_size)),
+                    segment_size: None,
+                    src_ip: transmit.src_ip,
+                });
+            }
+        }
+        match self.inner.poll_send(state, cx, &datagrams) {
+            Poll::Ready(Ok(0)) => Poll::Ready(Ok(0)),
+            // a partially sent transmit counts as sent, the rest of it as lost
+            Poll::Ready(Ok(sent)) => Poll::Ready(Ok(origins[sent - 1] + 1)),
+            res => res,
+        }
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let res = self.inner.poll_recv(cx, bufs, meta);
+        if let Poll::Ready(Ok(received)) = res {
+            for meta in &mut meta[..received] {
+                if meta.ecn == Some(EcnCodepoint::Ce) {
+                    // a segmented receive counts as one packet per segment
+                    let packets = meta.len.div_ceil(meta.stride.max(1));
+                    self.stats.add(Counter::CongestionMarks, packets as _);
+                }
+                if !self.ecn {
+                    meta.ecn = None;
+                }
+            }
+        }
+        res
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+/// Turns off receive offload on `socket`, which quinn turns on where it can.
+pub fn disable_gro(socket: &impl AsRawFd) -> io::Result<()> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        let off: libc::c_int = 0;
+        // SAFETY: the option value is a valid `c_int` of the given length
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_GRO,
+                core::ptr::addr_of!(off).cast(),
+                size_of::<libc::c_int>() as _,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    let _ = socket;
+    Ok(())
+}
+
+/// Describes whether an offload is active, given the number of segments
+/// the platform supports and whether it is enabled.
+pub fn describe(name: &str, segments: usize, enabled: bool) -> String {
+    if segments <= 1 {
+        format!("{name} not supported")
+    } else if enabled {
+        format!("{name} active, up to {segments} segments")
+    } else {
+        format!("{name} disabled")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("GSO", 64, true), "GSO active, up to 64 segments");
+        assert_eq!(describe("GSO", 64, false), "GSO disabled");
+        assert_eq!(describe("GRO", 1, true), "GRO not supported");
+    }
+}
@@ -0,0 +1,224 @@
+use core::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    task::{ready, Context, Poll},
+};
+use quinn::{
+    udp::{RecvMeta, Transmit, UdpState},
+    AsyncUdpSocket,
+};
+use std::io::{self, IoSliceMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadBuf},
+    net::{TcpStream, UdpSocket},
+};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A UDP socket relaying datagrams through a SOCKS5 proxy (RFC 1928).
+#[derive(Debug)]
+pub struct Socks5UdpSocket {
+    io: UdpSocket,
+    relay: SocketAddr,
+    /// The UDP association lives as long as this connection.
+    _control: TcpStream,
+}
+
+impl Socks5UdpSocket {
+    /// Sets up a UDP association with the SOCKS5 proxy at `proxy`.
+    pub async fn associate(proxy: SocketAddr) -> io::Result<Self> {
+        let mut control = TcpStream::connect(proxy).await?;
+
+        control.write_all(&[VERSION, 1, NO_AUTHENTICATION]).await?;
+        let mut reply = [0; 2];
+        control.read_exact(&mut reply).await?;
+        if reply != [VERSION, NO_AUTHENTICATION] {
+            return Err(proxy_error("the proxy requires authentication"));
+        }
+
+        let unspecified = SocketAddr::new(
+            match proxy {
+                SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            },
+            0,
+        );
+        let mut request = vec![VERSION, UDP_ASSOCIATE, 0];
+        encode_address(&unspecified, &mut request);
+        control.write_all(&request).await?;
+
+        let mut reply = [0; 3];
+        control.read_exact(&mut reply).await?;
+        if reply[0] != VERSION || reply[1] != 0 {
+            return Err(proxy_error(&format!(
+                "the proxy refused to associate, reply code {}",
+                reply[1]
+            )));
+        }
+        let mut relay = read_address(&mut control).await?;
+        // the proxy may answer with an unspecified address,
+        // meaning that the relay is on the proxy's host
+        if relay.ip().is_unspecified() {
+            relay.set_ip(proxy.ip());
+        }
+
+        let io = UdpSocket::bind(unspecified).await?;
+        Ok(Self {
+            io,
+            relay,
+            _control: control,
+        })
+    }
+}
+
+impl AsyncUdpSocket for Socks5UdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        for (i, transmit) in transmits.iter().enumerate() {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            for segment in transmit.contents.chunks(segment_size.max(1)) {
+                let mut datagram = vec![0, 0, 0];
+                encode_address(&transmit.destination, &mut datagram);
+                datagram.extend_from_slice(segment);
+                match self.io.poll_send_to(cx, &datagram, self.relay) {
+                    Poll::Ready(Ok(_)) => {}
+                    Poll::Ready(Err(e)) if i == 0 => return Poll::Ready(Err(e)),
+                    Poll::Pending if i == 0 => return Poll::Pending,
+                    // report the transmits which were sent,
+                    // a partially sent one is dropped as if it was lost
+                    Poll::Ready(Err(_)) | Poll::Pending => return Poll::Ready(Ok(i)),
+                }
+            }
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut buf = ReadBuf::new(&mut bufs[0]);
+            let from = ready!(self.io.poll_recv_from(cx, &mut buf))?;
+            let len = buf.filled().len();
+            if from != self.relay {
+                continue;
+            }
+            let Some((source, header_len)) = decode_header(buf.filled()) else {
+                continue;
+            };
+            bufs[0].copy_within(header_len..len, 0);
+            meta[0] = RecvMeta {
+                addr: source,
+                len: len - header_len,
+                stride: len - header_len,
+                ecn: None,
+                dst_ip: None,
+            };
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(format!("SOCKS5: {message}"))
+}
+
+/// Appends the SOCKS5 encoding of `addr` to `buf`.
+fn encode_address(addr: &SocketAddr, buf: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Reads a SOCKS5-encoded address from `stream`.
+async fn read_address(stream: &mut TcpStream) -> io::Result<SocketAddr> {
+    let ip = match stream.read_u8().await? {
+        ATYP_IPV4 => {
+            let mut octets = [0; 4];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::from(octets)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            stream.read_exact(&mut octets).await?;
+            IpAddr::from(octets)
+        }
+        ATYP_DOMAIN => return Err(proxy_error("domain names are not supported")),
+        atyp => return Err(proxy_error(&format!("unknown address type {atyp}"))),
+    };
+    Ok(SocketAddr::new(ip, stream.read_u16().await?))
+}
+
+/// Decodes the header of a datagram received from the relay,
+/// returning the source address and the length of the header.
+///
+/// Returns `None` for fragmented or malformed datagrams.
+fn decode_header(datagram: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (&[0, 0, 0, atyp], rest) = datagram.split_first_chunk::<4>()? else {
+        return None;
+    };
+    let (ip, rest) = match atyp {
+        ATYP_IPV4 => {
+            let (octets, rest) = rest.split_first_chunk::<4>()?;
+            (IpAddr::from(*octets), rest)
+        }
+        ATYP_IPV6 => {
+            let (octets, rest) = rest.split_first_chunk::<16>()?;
+            (IpAddr::from(*octets), rest)
+        }
+        _ => return None,
+    };
+    let (port, rest) = rest.split_first_chunk::<2>()?;
+    let addr = SocketAddr::new(ip, u16::from_be_bytes(*port));
+    Some((addr, datagram.len() - rest.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        for addr in [
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 8080),
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 443),
+        ] {
+            let mut datagram = vec![0, 0, 0];
+            encode_address(&addr, &mut datagram);
+            let header_len = datagram.len();
+            datagram.extend_from_slice(b"payload");
+            assert_eq!(decode_header(&datagram), Some((addr, header_len)));
+        }
+    }
+
+    #[test]
+    fn test_decode_fragmented() {
+        let mut datagram = vec![0, 0, 1];
+        encode_address(&"10.0.0.1:8080".parse().unwrap(), &mut datagram);
+        assert_eq!(decode_header(&datagram), None);
+        assert_eq!(decode_header(&[0, 0, 0, ATYP_IPV4, 10]), None);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    address_validation::AddressValidation,
+    config::{configure_server, SUPPORTED_SIG_ALGS},
+    log::log,
+};
+use core::time::Duration;
+use quinn::TransportConfig;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, PrivateKey, ServerName,
+};
+use spiffe::{error::GrpcClientError, SpiffeId, SpiffeIdError, TrustDomain, WorkloadApiClient};
+use std::{
+    io,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use thiserror::Error;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// How often the SVID is fetched again, SPIRE rotates them at half of their lifetime.
+const ROTATE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum SpiffeError {
+    #[error("Workload API error: {0}")]
+    Grpc(#[from] GrpcClientError),
+    #[error("invalid trust domain: {0}")]
+    TrustDomain(#[from] SpiffeIdError),
+    #[error("no SVID is issued for this workload")]
+    NoSvid,
+    #[error("no bundle for trust domain {0}")]
+    NoBundle(String),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+/// A connection to the SPIFFE Workload API, the node's SVID is fetched from.
+pub struct Spiffe {
+    client: WorkloadApiClient,
+    /// The trust domain peers' SPIFFE IDs have to be members of, if they are verified.
+    trust_domain: Option<TrustDomain>,
+    /// The X.509 authorities of `trust_domain`.
+    authorities: Arc<RwLock<Vec<Certificate>>>,
+}
+
+impl Spiffe {
+    /// Connects to the Workload API on `socket`, e.g. `unix:///tmp/spire-agent/public/api.sock`.
+    pub async fn connect(socket: &str, trust_domain: Option<&str>) -> Result<Self, SpiffeError> {
+        Ok(Self {
+            client: WorkloadApiClient::new_from_path(socket).await?,
+            trust_domain: trust_domain.map(TrustDomain::new).transpose()?,
+            authorities: Arc::default(),
+        })
+    }
+
+    /// Fetches the certificate chain and the key of the node's SVID,
+    /// as well as the authorities of the trust domain.
+    pub async fn fetch(&mut self) -> Result<(Vec<Certificate>, PrivateKey), SpiffeError> {
+        let context = self.client.fetch_x509_context().await?;
+        let svid = context.default_svid().ok_or(SpiffeError::NoSvid)?;
+        if let Some(trust_domain) = &self.trust_domain {
+            let bundle = context
+                .bundle_set()
+                .get_bundle(trust_domain)
+                .ok_or_else(|| SpiffeError::NoBundle(trust_domain.to_string()))?;
+            *self.authorities.write().unwrap() = bundle
+                .authorities()
+                .iter()
+                .map(|authority| Certificate(authority.content().to_vec()))
+                .collect();
+        }
+        Ok((
+            svid.cert_chain()
+                .iter()
+                .map(|cert| Certificate(cert.content().to_vec()))
+                .collect(),
+            PrivateKey(svid.private_key().content().to_vec()),
+        ))
+    }
+
+    /// A verifier of peers' SVIDs, if a trust domain is configured.
+    pub fn verifier(&self) -> Option<Arc<SpiffeVerifier>> {
+        Some(Arc::new(SpiffeVerifier {
+            trust_domain: self.trust_domain.clone()?,
+            authorities: self.authorities.clone(),
+        }))
+    }
+}
+
+/// Fetches the SVID once in `ROTATE_INTERVAL` and replaces the certificate
+/// of the endpoints with it, keeping `transport`. Logs errors on failure.
+pub async fn rotate_loop(
+    mut spiffe: Spiffe,
+    address_validation: Arc<AddressValidation>,
+    transport: Arc<TransportConfig>,
+) {
+    loop {
+        tokio::time::sleep(ROTATE_INTERVAL).await;
+        let res = spiffe.fetch().await.and_then(|(certs, key)| {
+            Ok(configure_server(
+                certs,
+                key,
+                false,
+                None,
+                transport.clone(),
+            )?)
+        });
+        match res {
+            Ok(server_config) => {
+                address_validation.set_server_config(Some(server_config));
+            }
+            Err(e) => log(&[
+                b"Failed to fetch the SVID, error: ",
+                e.to_string().as_bytes(),
+            ]),
+        }
+    }
+}
+
+/// Verifies that the certificate chain of a peer is issued by the authorities
+/// of `trust_domain` and that its SPIFFE ID is a member of it.
+/// Hostnames are not verified, SVIDs are not issued for them.
+pub struct SpiffeVerifier {
+    trust_domain: TrustDomain,
+    authorities: Arc<RwLock<Vec<Certificate>>>,
+}
+
+impl ServerCertVerifier for SpiffeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let other = |e: webpki::Error| {
+            rustls::Error::InvalidCertificate(CertificateError::Other(Arc::new(e)))
+        };
+        let authorities = self.authorities.read().unwrap();
+        let anchors: Vec<_> = authorities
+            .iter()
+            .filter_map(|authority| webpki::TrustAnchor::try_from_cert_der(&authority.0).ok())
+            .collect();
+        let intermediates: Vec<_> = intermediates.iter().map(|cert| &cert.0[..]).collect();
+        webpki::EndEntityCert::try_from(&end_entity.0[..])
+            .map_err(other)?
+            .verify_for_usage(
+                SUPPORTED_SIG_ALGS,
+                &anchors,
+                &intermediates,
+                webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?,
+                webpki::KeyUsage::server_auth(),
+                &[],
+            )
+            .map_err(other)?;
+        match spiffe_id(&end_entity.0) {
+            Some(id) if id.is_member_of(&self.trust_domain) => Ok(ServerCertVerified::assertion()),
+            _ => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+}
+
+/// The SPIFFE ID in the URI SAN of the certificate `der`, if any.
+fn spiffe_id(der: &[u8]) -> Option<SpiffeId> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let san = cert.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::URI(uri) => SpiffeId::new(uri).ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, SanType};
+
+    #[test]
+    fn test_spiffe_id() {
+        let mut params = CertificateParams::new(vec![]);
+        params.subject_alt_names = vec![SanType::URI("spiffe://example.org/gossip".to_owned())];
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let id = spiffe_id(&cert.serialize_der().unwrap()).unwrap();
+        assert_eq!(id.path(), "/gossip");
+        assert!(id.is_member_of(&TrustDomain::new("example.org").unwrap()));
+        assert!(!id.is_member_of(&TrustDomain::new("example.com").unwrap()));
+
+        let cert = rcgen::generate_simple_self_signed(["example.org".to_owned()]).unwrap();
+        assert!(spiffe_id(&cert.serialize_der().unwrap()).is_none());
+    }
+}
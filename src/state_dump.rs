@@ -0,0 +1,155 @@
+use crate::{log::log, peers::PeerState, stats::PathStats, Context};
+use core::fmt::Write;
+use std::{fs, path::PathBuf};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::Instant,
+};
+
+/// Dumps the state of the peer on each SIGUSR1, to `path` if set or else to the log.
+pub async fn dump_loop(ctx: Context, path: Option<PathBuf>) {
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log(&[
+                b"Failed to listen for SIGUSR1, error: ",
+                e.to_string().as_bytes(),
+            ]);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        let dump = dump(&ctx).await;
+        match &path {
+            Some(path) => match fs::write(path, &dump) {
+                Ok(()) => log(&[
+                    b"Dumped the state to ",
+                    path.display().to_string().as_bytes(),
+                ]),
+                Err(e) => log(&[
+                    b"Failed to dump the state to ",
+                    path.display().to_string().as_bytes(),
+                    b", error: ",
+                    e.to_string().as_bytes(),
+                ]),
+            },
+            None => log(&[b"State dump:\n", dump.trim_end().as_bytes()]),
+        }
+    }
+}
+
+/// Describes the peer table, the queues, the deduplication state
+/// and the reconnection timers of the peer.
+async fn dump(ctx: &Context) -> String {
+    let now = Instant::now();
+    let mut dump = String::new();
+
+    dump.push_str("peers:\n");
+    let mut peers: Vec<_> = ctx
+        .peers
+        .snapshot()
+        .iter()
+        .map(|(&peer, &state)| (peer, state))
+        .collect();
+    peers.sort_unstable_by_key(|&(peer, _)| peer);
+    let connections = ctx.connections.lock().await.clone();
+    let clocks = ctx.clocks.lock().await;
+    let quarantine = ctx.quarantine.lock().await;
+    let mailboxes = ctx.mailboxes.lock().await;
+    let reconnecting = ctx.reconnecting.lock().await;
+    for (peer, state) in peers {
+        write!(
+            dump,
+            "  {peer}: {}",
+            match state {
+                PeerState::Connected => "connected",
+                PeerState::Disconnected => "disconnected",
+            }
+        )
+        .unwrap();
+        if let Some(connection) = connections.get(&peer) {
+            write!(dump, ", {}", PathStats::of(connection)).unwrap();
+        }
+        if let Some(sample) = clocks.get(&peer).and_then(|clock| clock.best()) {
+            write!(dump, ", clock offset {}ms", sample.offset).unwrap();
+        }
+        if let Some(until) = quarantine
+            .quarantined_until(peer)
+            .filter(|&until| until > now)
+        {
+            write!(dump, ", quarantined for {:?}", until - now).unwrap();
+        }
+        if reconnecting.contains(&peer) {
+            dump.push_str(", reconnecting");
+        }
+        if let Some(mailbox) = mailboxes.get(&peer) {
+            write!(
+                dump,
+                ", mailbox of {} messages, {} bytes",
+                mailbox.messages(),
+                mailbox.bytes()
+            )
+            .unwrap();
+        }
+        dump.push('\n');
+    }
+    drop((clocks, quarantine, mailboxes, reconnecting));
+
+    dump.push_str("queues:\n");
+    writeln!(
+        dump,
+        "  broadcast: {} messages, {} receivers",
+        ctx.message_sender.len(),
+        ctx.message_sender.receiver_count()
+    )
+    .unwrap();
+    writeln!(
+        dump,
+        "  memory budget: {} of {} bytes",
+        ctx.memory_budget.used(),
+        ctx.memory_budget.limit()
+    )
+    .unwrap();
+
+    dump.push_str("deduplication:\n");
+    writeln!(
+        dump,
+        "  replay guard: {} origins",
+        ctx.replay_guard.lock().await.origin_count()
+    )
+    .unwrap();
+    writeln!(
+        dump,
+        "  sequences: {} messages missing",
+        ctx.sequences.lock().await.missing_count()
+    )
+    .unwrap();
+    if let Some(dedup) = &ctx.dedup {
+        writeln!(
+            dump,
+            "  dedup cache: {} payloads",
+            dedup.lock().await.payload_count()
+        )
+        .unwrap();
+    }
+    if let Some(history) = &ctx.history {
+        writeln!(
+            dump,
+            "  history: {} messages",
+            history.lock().await.message_count()
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        dump,
+        "retry budget: {:.2} tokens",
+        ctx.retry_budget.lock().await.tokens(now)
+    )
+    .unwrap();
+    dump.push_str("stats:\n");
+    for line in ctx.stats.to_string().lines() {
+        writeln!(dump, "  {line}").unwrap();
+    }
+    dump
+}
@@ -0,0 +1,210 @@
+use clap::ValueEnum;
+use core::{fmt::Write, net::SocketAddr};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// The peers of a `--peers-file` by their addresses, with their aliases.
+pub type StaticPeers = HashMap<SocketAddr, Option<String>>;
+
+/// Parses a peers file of one address per line, optionally followed by an alias.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse_peers(contents: &str) -> Result<StaticPeers, String> {
+    let mut peers = StaticPeers::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let addr = words.next().unwrap();
+        let addr = addr
+            .parse()
+            .map_err(|_| format!("line {}: invalid address `{addr}`", i + 1))?;
+        let alias = words.next().map(str::to_owned);
+        if words.next().is_some() {
+            return Err(format!("line {}: expected an address and an alias", i + 1));
+        }
+        peers.insert(addr, alias);
+    }
+    Ok(peers)
+}
+
+pub fn read_peers_file(path: &Path) -> io::Result<StaticPeers> {
+    parse_peers(&fs::read_to_string(path)?).map_err(io::Error::other)
+}
+
+/// The format peers are exported and imported in.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PeerFormat {
+    /// An array of objects with `addr` and `alias` fields.
+    Json,
+    /// `addr,alias` rows after that header, without quoting.
+    Csv,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeerEntry {
+    addr: SocketAddr,
+    #[serde(default)]
+    alias: Option<String>,
+}
+
+/// Formats `peers` in `format`, ordered by address.
+pub fn export_peers(peers: &StaticPeers, format: PeerFormat) -> String {
+    let mut entries: Vec<_> = peers
+        .iter()
+        .map(|(&addr, alias)| PeerEntry {
+            addr,
+            alias: alias.clone(),
+        })
+        .collect();
+    entries.sort_unstable_by_key(|entry| entry.addr);
+    match format {
+        PeerFormat::Json => serde_json::to_string_pretty(&entries).unwrap() + "\n",
+        PeerFormat::Csv => {
+            let mut csv = "addr,alias\n".to_owned();
+            for entry in entries {
+                writeln!(csv, "{},{}", entry.addr, entry.alias.unwrap_or_default()).unwrap();
+            }
+            csv
+        }
+    }
+}
+
+/// Parses peers exported with `export_peers`. Aliases may not contain whitespace,
+/// which the peers file separates them with.
+pub fn import_peers(input: &str, format: PeerFormat) -> Result<StaticPeers, String> {
+    let entries = match format {
+        PeerFormat::Json => serde_json::from_str(input).map_err(|e| e.to_string())?,
+        PeerFormat::Csv => input
+            .lines()
+            .enumerate()
+            .filter(|&(i, line)| !(line.trim().is_empty() || i == 0 && line == "addr,alias"))
+            .map(|(i, line)| {
+                let (addr, alias) = line.split_once(',').unwrap_or((line, ""));
+                let addr = addr.trim();
+                Ok(PeerEntry {
+                    addr: addr
+                        .parse()
+                        .map_err(|_| format!("line {}: invalid address `{addr}`", i + 1))?,
+                    alias: Some(alias.trim().to_owned()).filter(|alias| !alias.is_empty()),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+    };
+    let mut peers = StaticPeers::new();
+    for PeerEntry { addr, alias } in entries {
+        if let Some(alias) = alias.as_ref().filter(|alias| {
+            alias.is_empty() || alias.contains(char::is_whitespace) || alias.starts_with('#')
+        }) {
+            return Err(format!("invalid alias {alias:?} of {addr}"));
+        }
+        peers.insert(addr, alias);
+    }
+    Ok(peers)
+}
+
+/// Adds `imported` to the peers file at `path`, creating it if there is none,
+/// returning the number of peers added or given another alias. The lines
+/// are appended, so that the comments and order of the file are kept.
+pub fn merge_into_peers_file(path: &Path, imported: &StaticPeers) -> io::Result<usize> {
+    let mut contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let existing = parse_peers(&contents).map_err(io::Error::other)?;
+    let mut changed: Vec<_> = imported
+        .iter()
+        .filter(|&(addr, alias)| existing.get(addr) != Some(alias))
+        .collect();
+    if changed.is_empty() {
+        return Ok(0);
+    }
+    changed.sort_unstable_by_key(|&(&addr, _)| addr);
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    for (addr, alias) in &changed {
+        match alias {
+            Some(alias) => writeln!(contents, "{addr} {alias}"),
+            None => writeln!(contents, "{addr}"),
+        }
+        .unwrap();
+    }
+    fs::write(path, contents)?;
+    Ok(changed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peers() {
+        let peers =
+            parse_peers("# hubs\n127.0.0.1:9000 hub-a\n\n  [::1]:9001  \n127.0.0.1:9000 hub-b\n")
+                .unwrap();
+        assert_eq!(
+            peers,
+            StaticPeers::from([
+                ("127.0.0.1:9000".parse().unwrap(), Some("hub-b".to_owned())),
+                ("[::1]:9001".parse().unwrap(), None),
+            ])
+        );
+        assert_eq!(
+            parse_peers("127.0.0.1:9000\nhub:9000\n"),
+            Err("line 2: invalid address `hub:9000`".to_owned())
+        );
+        assert_eq!(
+            parse_peers("127.0.0.1:9000 hub a"),
+            Err("line 1: expected an address and an alias".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_export_import_peers() {
+        let peers = StaticPeers::from([
+            ("127.0.0.1:9001".parse().unwrap(), None),
+            ("127.0.0.1:9000".parse().unwrap(), Some("hub-a".to_owned())),
+        ]);
+        let csv = export_peers(&peers, PeerFormat::Csv);
+        assert_eq!(csv, "addr,alias\n127.0.0.1:9000,hub-a\n127.0.0.1:9001,\n");
+        assert_eq!(import_peers(&csv, PeerFormat::Csv), Ok(peers.clone()));
+        let json = export_peers(&peers, PeerFormat::Json);
+        assert_eq!(import_peers(&json, PeerFormat::Json), Ok(peers));
+        assert_eq!(
+            import_peers(r#"[{"addr": "127.0.0.1:9000"}]"#, PeerFormat::Json),
+            Ok(StaticPeers::from([(
+                "127.0.0.1:9000".parse().unwrap(),
+                None
+            )]))
+        );
+        assert_eq!(
+            import_peers("127.0.0.1:9000,hub a", PeerFormat::Csv),
+            Err("invalid alias \"hub a\" of 127.0.0.1:9000".to_owned())
+        );
+        assert_eq!(
+            import_peers("addr,alias\nhub:9000,", PeerFormat::Csv),
+            Err("line 2: invalid address `hub:9000`".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_merge_into_peers_file() {
+        let path = std::env::temp_dir().join(format!("peers-{}", std::process::id()));
+        fs::write(&path, "# hubs\n127.0.0.1:9000 hub-a").unwrap();
+        let imported = StaticPeers::from([
+            ("127.0.0.1:9000".parse().unwrap(), Some("hub-a".to_owned())),
+            ("127.0.0.1:9001".parse().unwrap(), None),
+        ]);
+        assert_eq!(merge_into_peers_file(&path, &imported).unwrap(), 1);
+        assert_eq!(merge_into_peers_file(&path, &imported).unwrap(), 0);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# hubs\n127.0.0.1:9000 hub-a\n127.0.0.1:9001\n"
+        );
+        assert_eq!(read_peers_file(&path).unwrap(), imported);
+        fs::remove_file(path).unwrap();
+    }
+}
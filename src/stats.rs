@@ -0,0 +1,246 @@
+use crate::{sessions::SessionStats, statsd::Statsd};
+use core::{fmt, net::SocketAddr, time::Duration};
+use quinn::Connection;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Counter {
+    /// Outgoing connections which failed, not counting timeouts.
+    DialFailures,
+    /// Outgoing connections which timed out.
+    DialTimeouts,
+    Reconnects,
+    /// Messages of all kinds, including pings and aggregation shares.
+    MessagesSent,
+    MessagesReceived,
+    BytesSent,
+    BytesReceived,
+    /// Gossip messages not sent to peers over their flow window.
+    SlowPeerSkips,
+    /// Gossip messages not sent to congested peers with adaptive pacing.
+    CongestionSkips,
+    /// Received gossip messages dropped for repeating the body of a recent one.
+    DuplicatePayloads,
+    /// Gossip messages of other peers found missing by their sequence numbers.
+    MissedMessages,
+    /// Packets received marked Congestion Experienced by ECN.
+    CongestionMarks,
+    /// Tasks of the supervisor which panicked.
+    TaskPanics,
+}
+
+impl Counter {
+    const ALL: [Self; 13] = [
+        Self::DialFailures,
+        Self::DialTimeouts,
+        Self::Reconnects,
+        Self::MessagesSent,
+        Self::MessagesReceived,
+        Self::BytesSent,
+        Self::BytesReceived,
+        Self::SlowPeerSkips,
+        Self::CongestionSkips,
+        Self::DuplicatePayloads,
+        Self::MissedMessages,
+        Self::CongestionMarks,
+        Self::TaskPanics,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DialFailures => "dial_failures",
+            Self::DialTimeouts => "dial_timeouts",
+            Self::Reconnects => "reconnects",
+            Self::MessagesSent => "messages_sent",
+            Self::MessagesReceived => "messages_received",
+            Self::BytesSent => "bytes_sent",
+            Self::BytesReceived => "bytes_received",
+            Self::SlowPeerSkips => "slow_peer_skips",
+            Self::CongestionSkips => "congestion_skips",
+            Self::DuplicatePayloads => "duplicate_payloads",
+            Self::MissedMessages => "missed_messages",
+            Self::CongestionMarks => "congestion_marks",
+            Self::TaskPanics => "task_panics",
+        }
+    }
+}
+
+/// Counters of the events of a peer, reported by the admin API
+/// and emitted to StatsD with `--statsd`.
+pub struct Stats {
+    counters: [AtomicU64; Counter::ALL.len()],
+    statsd: Option<Statsd>,
+}
+
+impl Stats {
+    pub fn new(statsd: Option<Statsd>) -> Self {
+        Self {
+            counters: Default::default(),
+            statsd,
+        }
+    }
+
+    pub fn add(&self, counter: Counter, value: u64) {
+        self.counters[counter as usize].fetch_add(value, Ordering::Relaxed);
+        if let Some(statsd) = &self.statsd {
+            statsd.count(counter.name(), value);
+        }
+    }
+
+    pub fn increment(&self, counter: Counter) {
+        self.add(counter, 1);
+    }
+
+    /// Records the round-trip time of a ping in milliseconds.
+    pub fn rtt(&self, rtt: u64) {
+        if let Some(statsd) = &self.statsd {
+            statsd.timing("rtt", rtt);
+        }
+    }
+
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Emits the path statistics of the connection to `peer` as gauges.
+    pub fn path(&self, peer: SocketAddr, stats: &PathStats) {
+        let Some(statsd) = &self.statsd else {
+            return;
+        };
+        let prefix = peer_metric_prefix(peer);
+        statsd.gauge(&format!("{prefix}rtt"), stats.rtt.as_millis() as _);
+        statsd.gauge(&format!("{prefix}cwnd"), stats.cwnd);
+        statsd.gauge(&format!("{prefix}lost_packets"), stats.lost_packets);
+        statsd.gauge(
+            &format!("{prefix}congestion_events"),
+            stats.congestion_events,
+        );
+        statsd.gauge(&format!("{prefix}datagrams_sent"), stats.datagrams_sent);
+    }
+
+    /// Emits the number of connections to `peer` and the time connected to it as gauges.
+    pub fn sessions(&self, peer: SocketAddr, stats: &SessionStats) {
+        let Some(statsd) = &self.statsd else {
+            return;
+        };
+        let prefix = peer_metric_prefix(peer);
+        statsd.gauge(&format!("{prefix}sessions"), stats.count);
+        statsd.gauge(
+            &format!("{prefix}connected_secs"),
+            stats.connected.as_secs(),
+        );
+    }
+
+    /// Records the time it took to reconnect to `peer`.
+    pub fn reconnect_latency(&self, peer: SocketAddr, latency: Duration) {
+        if let Some(statsd) = &self.statsd {
+            statsd.timing(
+                &format!("{}reconnect_latency", peer_metric_prefix(peer)),
+                latency.as_millis() as _,
+            );
+        }
+    }
+}
+
+/// Metric names cannot contain the dots and colons of addresses.
+fn peer_metric_prefix(peer: SocketAddr) -> String {
+    format!(
+        "peers.{}.",
+        peer.to_string().replace(['.', ':', '[', ']'], "_")
+    )
+}
+
+/// The QUIC path statistics of a connection, to tell network problems
+/// from application ones.
+pub struct PathStats {
+    pub rtt: Duration,
+    /// The congestion window in bytes.
+    pub cwnd: u64,
+    pub lost_packets: u64,
+    /// Reductions of the congestion window, on losses or ECN congestion marks.
+    pub congestion_events: u64,
+    pub datagrams_sent: u64,
+}
+
+impl PathStats {
+    pub fn of(connection: &Connection) -> Self {
+        let stats = connection.stats();
+        Self {
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            lost_packets: stats.path.lost_packets,
+            congestion_events: stats.path.congestion_events,
+            datagrams_sent: stats.udp_tx.datagrams,
+        }
+    }
+}
+
+impl fmt::Display for PathStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt {}ms, cwnd {}, lost packets {}, congestion events {}, datagrams sent {}",
+            self.rtt.as_millis(),
+            self.cwnd,
+            self.lost_packets,
+            self.congestion_events,
+            self.datagrams_sent
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for counter in Counter::ALL {
+            writeln!(
+                f,
+                "{}: {}",
+                counter.name().replace('_', " "),
+                self.get(counter)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        for (i, counter) in Counter::ALL.into_iter().enumerate() {
+            assert_eq!(counter as usize, i);
+        }
+        let stats = Stats::new(None);
+        stats.increment(Counter::Reconnects);
+        stats.add(Counter::BytesSent, 10);
+        stats.add(Counter::BytesSent, 5);
+        assert_eq!(stats.get(Counter::Reconnects), 1);
+        assert_eq!(stats.get(Counter::BytesSent), 15);
+        assert!(stats.to_string().contains("\nbytes sent: 15\n"));
+    }
+
+    #[test]
+    fn test_path_stats() {
+        assert_eq!(
+            peer_metric_prefix("127.0.0.1:9000".parse().unwrap()),
+            "peers.127_0_0_1_9000."
+        );
+        assert_eq!(
+            peer_metric_prefix("[::1]:9000".parse().unwrap()),
+            "peers.___1__9000."
+        );
+        let stats = PathStats {
+            rtt: Duration::from_millis(12),
+            cwnd: 14720,
+            lost_packets: 1,
+            congestion_events: 2,
+            datagrams_sent: 120,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "rtt 12ms, cwnd 14720, lost packets 1, congestion events 2, datagrams sent 120"
+        );
+    }
+}
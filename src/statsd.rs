@@ -0,0 +1,84 @@
+use core::net::SocketAddr;
+use rand::Rng;
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+/// Emits metrics over UDP in the StatsD format.
+///
+/// Each metric is sent with the probability of `sample_rate`,
+/// which is passed along so that the server can scale the values.
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    sample_rate: f64,
+}
+
+impl Statsd {
+    /// Emits to `addr`, either `IP:port` or `host:port`.
+    pub fn new(addr: &str, prefix: String, sample_rate: f64) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))?;
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local_addr)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix,
+            sample_rate,
+        })
+    }
+
+    pub fn count(&self, name: &str, value: u64) {
+        self.send(name, value, "c");
+    }
+
+    pub fn timing(&self, name: &str, millis: u64) {
+        self.send(name, millis, "ms");
+    }
+
+    /// Gauges are not sampled, only their last value matters.
+    pub fn gauge(&self, name: &str, value: u64) {
+        let metric = format_metric(&self.prefix, name, value, "g", 1.0);
+        let _ = self.socket.send(metric.as_bytes());
+    }
+
+    fn send(&self, name: &str, value: u64, kind: &str) {
+        if self.sample_rate < 1.0 && !rand::thread_rng().gen_bool(self.sample_rate) {
+            return;
+        }
+        let metric = format_metric(&self.prefix, name, value, kind, self.sample_rate);
+        // metrics are best-effort
+        let _ = self.socket.send(metric.as_bytes());
+    }
+}
+
+fn format_metric(prefix: &str, name: &str, value: u64, kind: &str, sample_rate: f64) -> String {
+    if sample_rate < 1.0 {
+        format!("{prefix}{name}:{value}|{kind}|@{sample_rate}")
+    } else {
+        format!("{prefix}{name}:{value}|{kind}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_metric() {
+        assert_eq!(
+            format_metric("p2p_gossip.", "reconnects", 1, "c", 1.0),
+            "p2p_gossip.reconnects:1|c"
+        );
+        assert_eq!(format_metric("", "rtt", 20, "ms", 0.25), "rtt:20|ms|@0.25");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    log::log,
+    stats::{Counter, Stats},
+};
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use core::{any::Any, fmt, future::Future, mem, panic::AssertUnwindSafe, time::Duration};
+use futures::FutureExt;
+use std::sync::{Arc, Mutex};
+use tokio::{task::JoinSet, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+/// The time the tasks have to stop once cancelled. They stop at their next
+/// await point, so only a task blocking its thread runs into it.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How a subsystem is restarted when it fails, with exponential backoff.
+///
+/// A subsystem which ran for longer than the longest delay before failing
+/// is restarted as if it failed for the first time.
+#[derive(Clone, Copy)]
+pub struct RestartPolicy {
+    /// The number of restarts in a row after which the subsystem is given up on.
+    pub max_restarts: usize,
+}
+
+/// Runs the tasks of the node, so that they are all cancelled on shutdown.
+/// The tasks which panic are logged and counted.
+pub struct Supervisor {
+    tasks: Mutex<JoinSet<()>>,
+    cancel: CancellationToken,
+    stats: Arc<Stats>,
+}
+
+impl Supervisor {
+    pub fn new(stats: Arc<Stats>) -> Self {
+        Self {
+            tasks: Mutex::default(),
+            cancel: CancellationToken::new(),
+            stats,
+        }
+    }
+
+    /// Runs `task` until it finishes or the supervisor shuts down.
+    /// The output of `task` is dropped.
+    pub fn spawn<F>(&self, name: &'static str, task: F)
+    where
+        F: Future + Send + 'static,
+    {
+        let cancel = self.cancel.clone();
+        let stats = self.stats.clone();
+        let task = async move {
+            let res = tokio::select! {
+                res = AssertUnwindSafe(task).catch_unwind() => res,
+                () = cancel.cancelled() => return,
+            };
+            if let Err(panic) = res {
+                log(&[
+                    b"Task ",
+                    name.as_bytes(),
+                    b" panicked: ",
+                    panic_message(&*panic).as_bytes(),
+                ]);
+                stats.add(Counter::TaskPanics, 1);
+            }
+        };
+        let mut tasks = self.tasks.lock().unwrap();
+        // a join set keeps the finished tasks until they are joined
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Runs the subsystem `start` starts like `spawn`, starting it again
+    /// as per `policy` when it returns an error or panics.
+    pub fn spawn_restarting<S, F, E>(&self, name: &'static str, policy: RestartPolicy, mut start: S)
+    where
+        S: FnMut() -> F + Send + 'static,
+        F: Future<Output = Result<(), E>> + Send,
+        E: fmt::Display,
+    {
+        let stats = self.stats.clone();
+        self.spawn(name, async move {
+            let mut backoff = ExponentialBackoff {
+                max_elapsed_time: None,
+                ..Default::default()
+            };
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                let error = match AssertUnwindSafe(start()).catch_unwind().await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => e.to_string(),
+                    Err(panic) => {
+                        stats.add(Counter::TaskPanics, 1);
+                        format!("panicked: {}", panic_message(&*panic))
+                    }
+                };
+                if started.elapsed() > backoff.max_interval {
+                    backoff.reset();
+                    restarts = 0;
+                }
+                if restarts == policy.max_restarts {
+                    log(&[
+                        b"Gave up on ",
+                        name.as_bytes(),
+                        b" after ",
+                        restarts.to_string().as_bytes(),
+                        b" restarts, error: ",
+                        error.as_bytes(),
+                    ]);
+                    return;
+                }
+                restarts += 1;
+                let delay = backoff.next_backoff().unwrap();
+                log(&[
+                    b"Restarting ",
+                    name.as_bytes(),
+                    b" in ",
+                    delay.as_millis().to_string().as_bytes(),
+                    b"ms, error: ",
+                    error.as_bytes(),
+                ]);
+                tokio::time::sleep(delay).await;
+            }
+        });
+    }
+
+    /// Cancels the tasks and waits for them to stop, aborting them after
+    /// `SHUTDOWN_TIMEOUT`. Tasks spawned afterwards are cancelled as soon as they run.
+    pub async fn shutdown(&self) {
+        self.cancel.cancel();
+        let mut tasks = mem::take(&mut *self.tasks.lock().unwrap());
+        let stopped = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if stopped.is_err() {
+            log(&[
+                b"Timed out stopping the tasks, aborting ",
+                tasks.len().to_string().as_bytes(),
+                b" of them",
+            ]);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_supervisor() {
+        let stats = Arc::new(Stats::new(None));
+        let supervisor = Supervisor::new(stats.clone());
+        supervisor.spawn("panicking", async { panic!("oops") });
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        supervisor.spawn("pending", async move {
+            // dropped once cancelled
+            let _sender = sender;
+            futures::future::pending::<()>().await
+        });
+        while stats.get(Counter::TaskPanics) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        supervisor.shutdown().await;
+        assert!(receiver.await.is_err());
+        assert!(supervisor.tasks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart() {
+        let supervisor = Supervisor::new(Arc::new(Stats::new(None)));
+        let starts = Arc::new(AtomicUsize::new(0));
+        supervisor.spawn_restarting("failing", RestartPolicy { max_restarts: 2 }, {
+            let starts = starts.clone();
+            move || {
+                let starts = starts.clone();
+                async move {
+                    starts.fetch_add(1, Ordering::Relaxed);
+                    Err("oops")
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(starts.load(Ordering::Relaxed), 3);
+
+        let starts = Arc::new(AtomicUsize::new(0));
+        supervisor.spawn_restarting("succeeding", RestartPolicy { max_restarts: 2 }, {
+            let starts = starts.clone();
+            move || {
+                let starts = starts.clone();
+                async move {
+                    if starts.fetch_add(1, Ordering::Relaxed) == 0 {
+                        panic!("oops");
+                    }
+                    Ok::<_, &str>(())
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(starts.load(Ordering::Relaxed), 2);
+    }
+}
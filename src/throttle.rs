@@ -0,0 +1,68 @@
+use crate::token_bucket::TokenBucket;
+use core::time::Duration;
+use std::{collections::HashMap, sync::Mutex};
+use tokio::time::Instant;
+
+/// Limits the bytes sent or received per second over all connections,
+/// and over each connection. Up to a second worth of bytes passes at once.
+#[derive(Default)]
+pub struct Throttle {
+    total: Option<Mutex<TokenBucket>>,
+    per_connection: Option<u32>,
+    /// By the stable IDs of the connections.
+    connections: Mutex<HashMap<usize, TokenBucket>>,
+}
+
+impl Throttle {
+    /// Without a rate, bytes are not limited by it.
+    pub fn new(total: Option<u32>, per_connection: Option<u32>) -> Self {
+        Self {
+            total: total.map(|rate| Mutex::new(TokenBucket::new(rate, rate as _))),
+            per_connection,
+            connections: Mutex::default(),
+        }
+    }
+
+    /// Takes `bytes` for the connection with `id` at `now`,
+    /// returning the time to wait until they are available.
+    pub fn reserve(&self, id: usize, bytes: usize, now: Instant) -> Duration {
+        let total = self.total.as_ref().map_or(Duration::ZERO, |total| {
+            total.lock().unwrap().reserve_many(now, bytes as _)
+        });
+        let connection = self.per_connection.map_or(Duration::ZERO, |rate| {
+            self.connections
+                .lock()
+                .unwrap()
+                .entry(id)
+                .or_insert_with(|| TokenBucket::new(rate, rate as _))
+                .reserve_many(now, bytes as _)
+        });
+        total.max(connection)
+    }
+
+    /// Forgets the connection with `id` once it is closed.
+    pub fn remove(&self, id: usize) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let now = Instant::now();
+        let unlimited = Throttle::default();
+        assert_eq!(unlimited.reserve(1, 1 << 20, now), Duration::ZERO);
+
+        let throttle = Throttle::new(Some(1000), Some(400));
+        assert_eq!(throttle.reserve(1, 400, now), Duration::ZERO);
+        assert_eq!(throttle.reserve(1, 200, now), Duration::from_millis(500));
+        assert_eq!(throttle.reserve(2, 400, now), Duration::ZERO);
+        // over the total
+        assert_eq!(throttle.reserve(3, 400, now), Duration::from_millis(400));
+        throttle.remove(1);
+        assert!(throttle.connections.lock().unwrap().get(&1).is_none());
+    }
+}
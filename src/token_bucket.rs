@@ -0,0 +1,108 @@
+use core::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket holding up to `capacity` tokens, refilled at `rate` tokens per second.
+///
+/// Tokens can be reserved in advance, so that callers exceeding the budget
+/// are queued and spread out at `rate` instead of failing.
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    /// Negative when tokens are reserved in advance.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(capacity: u32, rate: f64) -> Self {
+        Self {
+            capacity: capacity as _,
+            rate,
+            tokens: capacity as _,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = self.updated.max(now);
+    }
+
+    /// Takes a token at `now`, returning the time to wait until it is available.
+    pub fn reserve(&mut self, now: Instant) -> Duration {
+        self.reserve_many(now, 1.0)
+    }
+
+    /// Takes `tokens` at `now`, returning the time to wait until they are available,
+    /// `Duration::MAX` if they never are, the bucket not being refilled without a positive rate.
+    pub fn reserve_many(&mut self, now: Instant, tokens: f64) -> Duration {
+        self.refill(now);
+        self.tokens -= tokens;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else if self.rate > 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::MAX
+        }
+    }
+
+    /// Returns the tokens available at `now`, negative when reserved in advance.
+    pub fn tokens(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens
+    }
+
+    /// Takes a token at `now` if one is available.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(2, 4.0);
+        let start = Instant::now();
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::from_millis(250));
+        assert_eq!(bucket.reserve(start), Duration::from_millis(500));
+        // the reserved tokens are refilled first
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.reserve(later), Duration::from_millis(250));
+        // never more than `capacity`
+        let much_later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(much_later), Duration::ZERO);
+        assert_eq!(bucket.reserve(much_later), Duration::ZERO);
+        assert_eq!(bucket.reserve(much_later), Duration::from_millis(250));
+        assert_eq!(
+            bucket.reserve_many(much_later, 3.0),
+            Duration::from_millis(1000)
+        );
+        // never refilled
+        let mut empty = TokenBucket::new(1, 0.0);
+        assert_eq!(empty.reserve(start), Duration::ZERO);
+        assert_eq!(empty.reserve(start), Duration::MAX);
+    }
+
+    #[test]
+    fn test_try_take() {
+        let mut bucket = TokenBucket::new(1, 2.0);
+        let start = Instant::now();
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(!bucket.try_take(start + Duration::from_millis(400)));
+        assert!(bucket.try_take(start + Duration::from_millis(600)));
+    }
+}
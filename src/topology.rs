@@ -0,0 +1,66 @@
+use core::{fmt::Write, net::SocketAddr};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The overlay graph, as the neighbors of each node by its listen address,
+/// `None` for the nodes which did not answer.
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct Topology {
+    pub nodes: BTreeMap<SocketAddr, Option<Vec<SocketAddr>>>,
+}
+
+impl Topology {
+    /// Formats the graph as an undirected Graphviz graph,
+    /// with the nodes which did not answer dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph overlay {\n");
+        let mut edges = BTreeSet::new();
+        for (&node, neighbors) in &self.nodes {
+            if neighbors.is_none() {
+                writeln!(dot, "  \"{node}\" [style=dashed];").unwrap();
+            }
+            for &neighbor in neighbors.iter().flatten() {
+                edges.insert((node.min(neighbor), node.max(neighbor)));
+            }
+        }
+        for (a, b) in edges {
+            writeln!(dot, "  \"{a}\" -- \"{b}\";").unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let topology = Topology {
+            nodes: BTreeMap::from([
+                (addr(1), Some(vec![addr(2), addr(3)])),
+                (addr(2), Some(vec![addr(1)])),
+                (addr(3), None),
+            ]),
+        };
+        assert_eq!(
+            topology.to_dot(),
+            "graph overlay {
+  \"127.0.0.1:3\" [style=dashed];
+  \"127.0.0.1:1\" -- \"127.0.0.1:2\";
+  \"127.0.0.1:1\" -- \"127.0.0.1:3\";
+}
+"
+        );
+        assert_eq!(
+            topology.to_json(),
+            r#"{"nodes":{"127.0.0.1:1":["127.0.0.1:2","127.0.0.1:3"],"127.0.0.1:2":["127.0.0.1:1"],"127.0.0.1:3":null}}"#
+        );
+    }
+}
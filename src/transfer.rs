@@ -0,0 +1,100 @@
+use crate::error::{AppError, AppResult};
+use quinn::RecvStream;
+use sha2::{Digest, Sha256};
+
+/// The maximum length of a message sent as is, which peers not knowing
+/// of transfers read.
+pub const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Starts the streams of transfers. It is an invalid enum tag for messages,
+/// so that peers not knowing of transfers fail to read them.
+const TRANSFER_MARKER: [u8; 4] = [0xff; 4];
+
+/// The marker, the length of the message as a little-endian `u64`
+/// and its SHA-256 hash.
+const HEADER_LEN: usize = TRANSFER_MARKER.len() + 8 + 32;
+
+/// The number of progress reports per transfer.
+const PROGRESS_STEPS: usize = 4;
+
+/// Frames the serialized message `data` to be written to a stream of its own,
+/// as a transfer if it is longer than `MAX_MESSAGE_LEN`.
+pub fn frame(data: Vec<u8>) -> Vec<u8> {
+    if data.len() <= MAX_MESSAGE_LEN {
+        return data;
+    }
+    let mut framed = Vec::with_capacity(HEADER_LEN + data.len());
+    framed.extend(TRANSFER_MARKER);
+    framed.extend((data.len() as u64).to_le_bytes());
+    framed.extend(Sha256::digest(&data));
+    framed.extend(data);
+    framed
+}
+
+/// Reads a message framed with `frame` from `recv`, refusing transfers
+/// longer than `max_len`. Calls `progress` with the bytes received
+/// and the length of the transfer a few times while receiving one.
+pub async fn read_message(
+    recv: &mut RecvStream,
+    max_len: usize,
+    mut progress: impl FnMut(usize, usize),
+) -> AppResult<Vec<u8>> {
+    let mut marker = [0; TRANSFER_MARKER.len()];
+    recv.read_exact(&mut marker).await?;
+    if marker != TRANSFER_MARKER {
+        let rest = recv
+            .read_to_end(MAX_MESSAGE_LEN - TRANSFER_MARKER.len())
+            .await?;
+        return Ok([&marker[..], &rest].concat());
+    }
+
+    let mut header = [0; HEADER_LEN - TRANSFER_MARKER.len()];
+    recv.read_exact(&mut header).await?;
+    let (len, hash) = header.split_at(8);
+    let len = u64::from_le_bytes(len.try_into().unwrap());
+    if len > max_len as u64 {
+        return Err(AppError::TransferTooLarge { len, max_len });
+    }
+    let len = len as usize;
+    let mut data = Vec::with_capacity(len);
+    let step = len.div_ceil(PROGRESS_STEPS);
+    let mut next_report = step;
+    while data.len() < len {
+        let Some(chunk) = recv.read_chunk(len - data.len(), true).await? else {
+            break;
+        };
+        data.extend_from_slice(&chunk.bytes);
+        if data.len() >= next_report || data.len() == len {
+            progress(data.len(), len);
+            next_report = data.len() + step;
+        }
+    }
+    if data.len() < len || recv.read_chunk(1, true).await?.is_some() {
+        return Err(AppError::TransferLengthMismatch);
+    }
+    if Sha256::digest(&data)[..] != *hash {
+        return Err(AppError::TransferHashMismatch);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let short = vec![1; MAX_MESSAGE_LEN];
+        assert_eq!(frame(short.clone()), short);
+
+        let long = vec![1; MAX_MESSAGE_LEN + 1];
+        let framed = frame(long.clone());
+        assert_eq!(framed.len(), HEADER_LEN + long.len());
+        assert!(framed.starts_with(&TRANSFER_MARKER));
+        assert_eq!(framed[4..12], (long.len() as u64).to_le_bytes());
+        assert_eq!(framed[12..HEADER_LEN], Sha256::digest(&long)[..]);
+        assert_eq!(framed[HEADER_LEN..], long);
+        // peers not knowing of transfers fail to read them
+        assert!(bincode::deserialize::<crate::message::Message>(&framed).is_err());
+    }
+}
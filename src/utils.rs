@@ -1,9 +1,17 @@
+use crate::peers::PeerState;
 use core::{
     fmt::Write,
-    net::SocketAddr,
-    ops::{Deref, DerefMut},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    ops::{Deref, DerefMut, RangeInclusive},
+    str::FromStr,
+};
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    io,
+    os::fd::AsRawFd,
+    ptr,
 };
-use std::collections::HashMap;
 use tokio::sync::oneshot;
 
 pub struct SocketAddrDeserializer<'a> {
@@ -14,24 +22,246 @@ impl<'a> Iterator for SocketAddrDeserializer<'a> {
     type Item = SocketAddr;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let peer: SocketAddr = bincode::deserialize(self.data).ok()?;
-        self.data = &self.data[if peer.is_ipv4() {
-            IPV4_SERIALIZED_LEN
-        } else {
-            IPV6_SERIALIZED_LEN
-        }..];
+        let peer = deserialize_address(self.data).ok()?;
+        self.data = &self.data[serialized_len(peer)..];
         Some(peer)
     }
 }
 
-pub fn deserialize_addresses(data: &[u8]) -> SocketAddrDeserializer {
+pub fn deserialize_addresses(data: &[u8]) -> SocketAddrDeserializer<'_> {
     SocketAddrDeserializer { data }
 }
 
-/// The length of a `SocketAddr::V4`, serialized with bincode.
-const IPV4_SERIALIZED_LEN: usize = 10;
-/// The length of a `SocketAddr::V6`, serialized with bincode.
-const IPV6_SERIALIZED_LEN: usize = 22;
+/// Formats `bytes` as lowercase hexadecimal digits.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The enum tag of scoped IPv6 addresses, following the ones bincode gives
+/// to the variants of `SocketAddr`. It is an invalid tag for the nodes
+/// of versions without scope IDs, so that they stop reading there.
+const SCOPED_IPV6_TAG: u32 = 2;
+
+/// Serializes `addr` with bincode. Since bincode drops the scope ID of
+/// a `SocketAddr::V6`, a scoped one is serialized as the address and the port
+/// followed by the scope ID, tagged with `SCOPED_IPV6_TAG`.
+pub fn serialize_address(addr: &SocketAddr) -> Vec<u8> {
+    match addr {
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => {
+            bincode::serialize(&(SCOPED_IPV6_TAG, v6.ip(), v6.port(), v6.scope_id()))
+        }
+        _ => bincode::serialize(addr),
+    }
+    .unwrap()
+}
+
+/// Deserializes an address serialized with `serialize_address`.
+pub fn deserialize_address(data: &[u8]) -> bincode::Result<SocketAddr> {
+    if data.starts_with(&SCOPED_IPV6_TAG.to_le_bytes()) {
+        let (_, ip, port, scope_id): (u32, Ipv6Addr, u16, u32) = bincode::deserialize(data)?;
+        return Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)));
+    }
+    bincode::deserialize(data)
+}
+
+/// The length of a `SocketAddr::V4`, serialized with `serialize_address`.
+pub const IPV4_SERIALIZED_LEN: usize = 10;
+/// The length of a `SocketAddr::V6` without a scope ID, serialized with `serialize_address`.
+pub const IPV6_SERIALIZED_LEN: usize = 22;
+/// The length of a `SocketAddr::V6` with a scope ID, serialized with `serialize_address`.
+pub const SCOPED_IPV6_SERIALIZED_LEN: usize = 26;
+
+/// The length of `addr`, serialized with `serialize_address`.
+pub fn serialized_len(addr: SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => IPV4_SERIALIZED_LEN,
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => SCOPED_IPV6_SERIALIZED_LEN,
+        SocketAddr::V6(_) => IPV6_SERIALIZED_LEN,
+    }
+}
+
+/// An IP address with an optional IPv6 scope ID, e.g. `fe80::1%eth0`.
+#[derive(Clone, Copy, Debug)]
+pub struct ScopedIp {
+    pub ip: IpAddr,
+    pub scope_id: u32,
+}
+
+impl ScopedIp {
+    pub fn with_port(self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V4(_) => SocketAddr::new(self.ip, port),
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id)),
+        }
+    }
+}
+
+impl FromStr for ScopedIp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, scope_id) = match s.split_once('%') {
+            Some((ip, scope)) => (ip, Some(scope)),
+            None => (s, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|e| format!("{e}"))?;
+        let scope_id = match (ip, scope_id) {
+            (_, None) => 0,
+            (IpAddr::V6(_), Some(scope)) => parse_scope_id(scope)?,
+            (IpAddr::V4(_), Some(_)) => return Err("IPv4 addresses have no scope".into()),
+        };
+        Ok(Self { ip, scope_id })
+    }
+}
+
+/// A port or an inclusive range of ports, e.g. `9000-9010`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortRange(pub RangeInclusive<u16>);
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |port: &str| port.parse::<u16>().map_err(|e| format!("{e}"));
+        let range = match s.split_once('-') {
+            Some((start, end)) => parse(start)?..=parse(end)?,
+            None => parse(s)?..=parse(s)?,
+        };
+        if range.is_empty() {
+            return Err("the port range is empty".into());
+        }
+        Ok(Self(range))
+    }
+}
+
+/// Parses a scope ID, which is either numeric or an interface name.
+fn parse_scope_id(s: &str) -> Result<u32, String> {
+    if let Ok(scope_id) = s.parse() {
+        return Ok(scope_id);
+    }
+    let name = CString::new(s).map_err(|e| format!("{e}"))?;
+    // SAFETY: `name` is a valid nul-terminated string
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(format!("unknown interface {s:?}")),
+        scope_id => Ok(scope_id),
+    }
+}
+
+/// Sets the traffic class of the packets sent over the IPv6 `socket`.
+pub fn set_tclass_v6(socket: &impl AsRawFd, tclass: u8) -> io::Result<()> {
+    let tclass = libc::c_int::from(tclass);
+    // SAFETY: the option value is a valid `c_int` of the given length
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            ptr::addr_of!(tclass).cast(),
+            size_of::<libc::c_int>() as _,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the addresses of the network interface `name`.
+pub fn interface_addresses(name: &str) -> io::Result<Vec<ScopedIp>> {
+    let mut ifaddrs = ptr::null_mut();
+    // SAFETY: on success, `ifaddrs` points to a valid linked list,
+    // which is freed below
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cur = ifaddrs;
+    // SAFETY: the list items and their fields are either null or valid
+    while let Some(ifaddr) = unsafe { cur.as_ref() } {
+        cur = ifaddr.ifa_next;
+        let Some(addr) = (unsafe { ifaddr.ifa_addr.as_ref() }) else {
+            continue;
+        };
+        if unsafe { CStr::from_ptr(ifaddr.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        match i32::from(addr.sa_family) {
+            libc::AF_INET => {
+                let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+                addrs.push(ScopedIp {
+                    ip: Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into(),
+                    scope_id: 0,
+                });
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+                addrs.push(ScopedIp {
+                    ip: Ipv6Addr::from(addr.sin6_addr.s6_addr).into(),
+                    scope_id: addr.sin6_scope_id,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // SAFETY: `ifaddrs` was allocated by `getifaddrs`
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(addrs)
+}
+
+/// Returns the hostname of this machine.
+pub fn hostname() -> io::Result<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of its length
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Parses a socket address, accepting interface names
+/// as scope IDs, e.g. `[fe80::1%eth0]:8080`.
+pub fn parse_socket_addr(s: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = s.parse() {
+        return Ok(addr);
+    }
+    let (ip, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("invalid socket address {s:?}"))?;
+    let ip = ip
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(ip);
+    let port = port.parse().map_err(|e| format!("{e}"))?;
+    Ok(ip.parse::<ScopedIp>()?.with_port(port))
+}
+
+/// Resolves `host`, which is either a socket address or `hostname:port`.
+///
+/// The addresses are ordered as per Happy Eyeballs (RFC 8305),
+/// see `happy_eyeballs_order`.
+pub async fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    if let Ok(addr) = parse_socket_addr(host) {
+        return Ok(vec![addr]);
+    }
+    Ok(happy_eyeballs_order(tokio::net::lookup_host(host).await?))
+}
+
+/// Interleaves IPv6 and IPv4 addresses, starting with IPv6,
+/// keeping the order within each address family.
+pub fn happy_eyeballs_order(addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
 
 /// A struct holding an `oneshot::Sender` that never sends,
 /// effectively allowing the thread owning the receiver
@@ -62,13 +292,13 @@ impl<T> DerefMut for NotifyOnDrop<T> {
     }
 }
 
-pub fn format_peers(peers: &HashMap<SocketAddr, bool>) -> String {
+pub fn format_peers(peers: &HashMap<SocketAddr, PeerState>) -> String {
     // with IPv6, the length may be greater than the capacity provided
     let mut formatted_peers =
         String::with_capacity("\"255.255.255.255:65535\", ".len() * peers.len());
     for (i, (addr, _)) in peers
         .iter()
-        .filter(|&(_, &finalized)| finalized)
+        .filter(|&(_, &state)| state == PeerState::Connected)
         .enumerate()
     {
         if i != 0 {
@@ -90,11 +320,10 @@ mod tests {
     fn test_ipv4_serialized_len() {
         assert_eq!(
             IPV4_SERIALIZED_LEN,
-            bincode::serialize(&SocketAddr::new(
+            serialize_address(&SocketAddr::new(
                 IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
                 8080,
             ))
-            .unwrap()
             .len()
         )
     }
@@ -103,13 +332,86 @@ mod tests {
     fn test_ipv6_serialized_len() {
         assert_eq!(
             IPV6_SERIALIZED_LEN,
-            bincode::serialize(&SocketAddr::new(
+            serialize_address(&SocketAddr::new(
                 IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0xc00a, 0x2ff)),
                 8080,
             ))
-            .unwrap()
             .len()
         );
+        let scoped = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 3));
+        assert_eq!(SCOPED_IPV6_SERIALIZED_LEN, serialize_address(&scoped).len());
+    }
+
+    #[test]
+    fn test_scoped_address_compatibility() {
+        // unscoped addresses are serialized as by the versions without scope IDs
+        let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 0));
+        assert_eq!(serialize_address(&addr), bincode::serialize(&addr).unwrap());
+        // which stop reading at scoped ones
+        let scoped = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 3));
+        assert!(bincode::deserialize::<SocketAddr>(&serialize_address(&scoped)).is_err());
+        assert_eq!(
+            deserialize_address(&serialize_address(&scoped)).unwrap(),
+            scoped
+        );
+    }
+
+    #[test]
+    fn test_parse_socket_addr() {
+        assert_eq!(
+            parse_socket_addr("127.0.0.1:8080"),
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080))
+        );
+        assert_eq!(
+            parse_socket_addr("[fe80::1%3]:8080"),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                8080,
+                0,
+                3
+            )))
+        );
+        assert_eq!(
+            parse_socket_addr("[fe80::1%lo]:8080"),
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                8080,
+                0,
+                1
+            )))
+        );
+        assert!(parse_socket_addr("127.0.0.1%1:8080").is_err());
+        assert!(parse_socket_addr("[fe80::1%nonexistent0]:8080").is_err());
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!("8080".parse(), Ok(PortRange(8080..=8080)));
+        assert_eq!("9000-9010".parse(), Ok(PortRange(9000..=9010)));
+        assert!("9010-9000".parse::<PortRange>().is_err());
+        assert!("9000-".parse::<PortRange>().is_err());
+        assert!("65536".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn test_interface_addresses() {
+        let addrs = interface_addresses("lo").unwrap();
+        assert!(addrs
+            .iter()
+            .any(|addr| addr.ip == IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert!(interface_addresses("nonexistent0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_happy_eyeballs_order() {
+        let v4 = |i| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)), 8080);
+        let v6 = |i| SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, i)), 8080);
+        assert_eq!(
+            happy_eyeballs_order([v4(1), v4(2), v4(3), v6(1), v6(2)]),
+            [v6(1), v4(1), v6(2), v4(2), v4(3)]
+        );
+        assert_eq!(happy_eyeballs_order([v4(1), v4(2)]), [v4(1), v4(2)]);
+        assert_eq!(happy_eyeballs_order([]), []);
     }
 
     #[test]
@@ -119,18 +421,22 @@ mod tests {
             let len = rng.gen_range(0..100);
             let addresses: Vec<_> = (0..len)
                 .map(|_| {
-                    let ip = if rng.gen() {
-                        IpAddr::V4(Ipv4Addr::from(rng.gen::<u32>()))
+                    if rng.gen() {
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::from(rng.gen::<u32>())), rng.gen())
                     } else {
-                        IpAddr::V6(Ipv6Addr::from(rng.gen::<u128>()))
-                    };
-                    SocketAddr::new(ip, rng.gen())
+                        SocketAddr::V6(SocketAddrV6::new(
+                            Ipv6Addr::from(rng.gen::<u128>()),
+                            rng.gen(),
+                            0,
+                            if rng.gen() { rng.gen() } else { 0 },
+                        ))
+                    }
                 })
                 .collect();
 
             let mut data = Vec::new();
             for addr in &addresses {
-                bincode::serialize_into(&mut data, addr).unwrap();
+                data.extend(serialize_address(addr));
             }
 
             for (i, peer) in deserialize_addresses(&data).enumerate() {
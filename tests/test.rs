@@ -61,6 +61,11 @@ fn happy_3_peers() -> io::Result<()> {
     // launch
 
     for (i, port) in [8080, 8081, 8082].iter().enumerate() {
+        let line = lines[i].next().expect("expected a line");
+        assert!(
+            line.starts_with("00:00:00 - Segmentation offload: GSO "),
+            "{line}"
+        );
         let line = lines[i].next().expect("expected a line");
         assert_eq!(
             line,